@@ -1,16 +1,23 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Json},
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Json, Response},
     routing::{get, post},
     Router,
 };
-use serde_json::{json, Value};
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
 use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::models::*;
-use crate::services::{self, AppState, compiler, sandbox, fuzzer, logger};
+use crate::services::{AppState, artifacts, batch, compare, compiler, sandbox, fuzzer, logger, pcap, policy, pty, verdict, attack, selftest, rootfs, elf, store};
 
 pub fn create_router(state: Arc<AppState>) -> Router {
     Router::new()
@@ -24,9 +31,27 @@ pub fn create_router(state: Arc<AppState>) -> Router {
 
         // Sandbox endpoints
         .route("/api/sandbox/run", post(run_sandbox))
+        .route("/api/sandbox/pty", get(sandbox_pty))
+        .route("/api/sandbox/:id/cancel", post(cancel_sandbox_run))
+        .route("/api/sandbox/:id/replay", post(replay_sandbox_run))
+        .route("/api/sandbox/:id/verdict", post(rescore_sandbox_run))
+        .route("/api/sandbox/:id/attack", post(remap_sandbox_run))
         .route("/api/sandbox/logs/:id", get(get_sandbox_logs))
         .route("/api/sandbox/resources/:id", get(get_sandbox_resources))
         .route("/api/sandbox/runs", get(list_sandbox_runs))
+        .route("/api/sandbox/batch", post(start_sandbox_batch))
+        .route("/api/sandbox/batch/:id", get(get_sandbox_batch))
+        .route("/api/sandbox/batches", get(list_sandbox_batches))
+        .route("/api/sandbox/selftest", post(run_sandbox_selftest).get(list_sandbox_selftests))
+        .route("/api/sandbox/status/:id", get(get_sandbox_status))
+        .route("/api/sandbox/queue", get(get_sandbox_queue))
+        .route("/api/sandbox/filesystem/:id", get(get_sandbox_filesystem_changes))
+        .route("/api/sandbox/processes/:id", get(get_sandbox_process_tree))
+        .route("/api/sandbox/network/:id", get(get_sandbox_network_activity))
+        .route("/api/sandbox/pcap/:id", get(get_sandbox_pcap_summary))
+        .route("/api/sandbox/diff/:left/:right", get(diff_sandbox_runs))
+        .route("/api/sandbox/artifacts/:id", get(list_sandbox_artifacts))
+        .route("/api/sandbox/artifacts/:id/*name", get(download_sandbox_artifact))
 
         // Fuzzing endpoints
         .route("/api/fuzz/start", post(start_fuzzing))
@@ -42,6 +67,36 @@ pub fn create_router(state: Arc<AppState>) -> Router {
         // Projects endpoints
         .route("/api/projects", get(list_projects).post(create_project))
 
+        // Syscall policies
+        .route("/api/policies", get(list_policies).post(save_policy))
+        .route("/api/policies/learn", post(learn_policy))
+        .route("/api/policies/:name", get(get_policy).delete(delete_policy))
+        .route("/api/policies/:name/seccomp", get(export_policy_seccomp))
+
+        // Verdict rules
+        .route("/api/verdict/rules", get(list_verdict_rules).post(save_verdict_rule))
+        .route("/api/verdict/rules/:id", get(get_verdict_rule).delete(delete_verdict_rule))
+        .route("/api/verdict/rules/:id/versions", get(get_verdict_rule_versions))
+
+        // ATT&CK mapping
+        .route("/api/attack/mapping", get(get_attack_mapping).put(save_attack_mapping).delete(reset_attack_mapping))
+
+        // Binary inspection
+        .route("/api/binaries", get(list_binaries).post(inspect_binary))
+        .route("/api/binaries/:id/inspect", get(get_binary_inspection))
+
+        // Root filesystem images
+        .route("/api/rootfs", get(list_rootfs_images))
+        .route(
+            "/api/rootfs/:name",
+            get(get_rootfs_image).post(upload_rootfs_image).delete(delete_rootfs_image),
+        )
+
+        // Binary store
+        .route("/api/store", get(list_stored_binaries).post(upload_stored_binary))
+        .route("/api/store/gc", post(collect_stored_binaries))
+        .route("/api/store/:sha256", get(get_stored_binary).delete(delete_stored_binary))
+
         .with_state(state)
 }
 
@@ -104,33 +159,241 @@ async fn get_bytecode(State(_state): State<Arc<AppState>>) -> impl IntoResponse
 // ============================================================================
 
 async fn run_sandbox(State(state): State<Arc<AppState>>, Json(req): Json<SandboxRunRequest>) -> impl IntoResponse {
-    let run = sandbox::run_sandbox(req).await;
-    let run_id = run.id;
+    match sandbox::submit_run(&state, req).await {
+        Ok(run) => Json(run).into_response(),
+        Err(e) => submit_error_response(e),
+    }
+}
 
-    // Store the run
-    {
-        let mut runs = state.sandbox_runs.lock().unwrap();
-        runs.insert(run_id, run.clone());
+fn submit_error_response(error: sandbox::SubmitError) -> Response {
+    match error {
+        sandbox::SubmitError::QueueFull { queued } => (
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({ "success": false, "error": "Sandbox queue is full", "queued": queued })),
+        )
+            .into_response(),
+        sandbox::SubmitError::ProjectNotFound => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Project not found" })),
+        )
+            .into_response(),
+        sandbox::SubmitError::PolicyNotFound(name) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Syscall policy '{}' not found", name) })),
+        )
+            .into_response(),
+        sandbox::SubmitError::RootfsNotFound(name) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Root filesystem image '{}' not found", name) })),
+        )
+            .into_response(),
+        sandbox::SubmitError::BinaryNotFound(sha256) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Stored binary {} not found", sha256) })),
+        )
+            .into_response(),
+        sandbox::SubmitError::HostBinaryPath(path) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": format!("{} is not in the run's workspace or image; store it and pass binary_sha256", path)
+            })),
+        )
+            .into_response(),
+        sandbox::SubmitError::StoredBinaryInRootfs => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Stored binaries cannot run inside a root filesystem image" })),
+        )
+            .into_response(),
+        sandbox::SubmitError::RunNotFound(id) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Run {} not found", id) })),
+        )
+            .into_response(),
     }
+}
 
-    // Log the sandbox run
-    logger::add_log(
-        &state.logs,
-        match run.status {
-            RunStatus::Completed => LogLevel::Info,
-            RunStatus::Failed | RunStatus::Timeout => LogLevel::Error,
-            _ => LogLevel::Info,
-        },
-        LogSource::Sandbox,
-        format!("Sandbox run {} completed with status {:?}", run_id, run.status),
-        Some(json!({
-            "run_id": run_id,
-            "status": run.status,
-            "exit_code": run.exit_code
-        })),
-    );
+async fn sandbox_pty(State(state): State<Arc<AppState>>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| pty_session(state, socket))
+}
+
+/// Drives one interactive run: the first text message is a `PtyStartRequest`,
+/// then keystrokes and resizes flow in and terminal output flows out until the
+/// target exits, when the finished run is sent and the socket closed.
+async fn pty_session(state: Arc<AppState>, socket: WebSocket) {
+    let (mut sender, mut receiver) = socket.split();
+
+    let start = match receiver.next().await {
+        Some(Ok(Message::Text(text))) => serde_json::from_str::<PtyStartRequest>(&text).map_err(|e| e.to_string()),
+        _ => Err("Expected a start request".to_string()),
+    };
+    let start = match start {
+        Ok(start) => start,
+        Err(error) => {
+            let _ = sender.send(pty_message(&PtyServerMessage::Error { error })).await;
+            return;
+        }
+    };
+
+    let (input, input_rx) = mpsc::channel(64);
+    let (events, mut events_rx) = mpsc::channel(64);
+    let terminal = pty::Terminal {
+        input: input_rx,
+        events,
+        cols: start.cols.unwrap_or(80),
+        rows: start.rows.unwrap_or(24),
+    };
+
+    let session_state = state.clone();
+    let mut session = tokio::spawn(async move { sandbox::submit_interactive(&session_state, start.run, terminal).await });
+
+    // Ends once the session drops the terminal, after the last of its output
+    let forward = tokio::spawn(async move {
+        while let Some(event) = events_rx.recv().await {
+            let message = match event {
+                pty::TerminalEvent::Started { run_id } => pty_message(&PtyServerMessage::Started { run_id }),
+                pty::TerminalEvent::Output(data) => Message::Binary(data),
+            };
+            if sender.send(message).await.is_err() {
+                break;
+            }
+        }
+        sender
+    });
+
+    let mut input = Some(input);
+    let result = loop {
+        tokio::select! {
+            result = &mut session => break result,
+            message = receiver.next(), if input.is_some() => {
+                let sent = match message {
+                    Some(Ok(Message::Binary(data))) => Some(pty::TerminalInput::Data(data)),
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<PtyClientMessage>(&text) {
+                        Ok(PtyClientMessage::Input { data }) => Some(pty::TerminalInput::Data(data.into_bytes())),
+                        Ok(PtyClientMessage::Resize { cols, rows }) => Some(pty::TerminalInput::Resize { cols, rows }),
+                        Err(_) => None,
+                    },
+                    Some(Ok(_)) => None,
+                    // Dropping the input ends the session
+                    Some(Err(_)) | None => {
+                        input = None;
+                        None
+                    }
+                };
+                if let (Some(sent), Some(input)) = (sent, &input) {
+                    let _ = input.send(sent).await;
+                }
+            }
+        }
+    };
 
-    Json(run)
+    let Ok(mut sender) = forward.await else {
+        return;
+    };
+    let message = match result {
+        Ok(Ok(run)) => PtyServerMessage::Exit { run: Box::new(run) },
+        Ok(Err(e)) => PtyServerMessage::Error { error: e.to_string() },
+        Err(_) => PtyServerMessage::Error { error: "Sandbox session failed".to_string() },
+    };
+    let _ = sender.send(pty_message(&message)).await;
+    let _ = sender.close().await;
+}
+
+fn pty_message(message: &PtyServerMessage) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap_or_default())
+}
+
+/// Scores the run again with the current rules, e.g. after a rule was tuned.
+async fn rescore_sandbox_run(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let rules = verdict::current_rules(&state);
+    let mut runs = state.sandbox_runs.lock().unwrap();
+    match runs.get_mut(&id) {
+        Some(run) if run.completed_at.is_some() => {
+            let scored = verdict::evaluate(run, &rules);
+            run.verdict = Some(scored.clone());
+            Json(json!({ "success": true, "data": scored })).into_response()
+        }
+        Some(_) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": "Run has not finished" })),
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Run not found" })),
+        )
+            .into_response(),
+    }
+}
+
+/// Maps the run's behaviour again with the current ATT&CK table.
+async fn remap_sandbox_run(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let mapping = state.attack_mapping.lock().unwrap().clone();
+    let mut runs = state.sandbox_runs.lock().unwrap();
+    match runs.get_mut(&id) {
+        Some(run) if run.completed_at.is_some() => {
+            run.attack_techniques = attack::map_run(run, &mapping);
+            Json(json!({ "success": true, "data": run.attack_techniques })).into_response()
+        }
+        Some(_) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": "Run has not finished" })),
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Run not found" })),
+        )
+            .into_response(),
+    }
+}
+
+async fn cancel_sandbox_run(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let cancelled = sandbox::cancel_run(&state, id);
+
+    if cancelled {
+        logger::add_log(
+            &state.logs,
+            LogLevel::Warning,
+            LogSource::Sandbox,
+            format!("Sandbox run {} cancelled", id),
+            Some(json!({ "run_id": id })),
+        );
+    }
+
+    Json(json!({ "success": cancelled, "run_id": id }))
+}
+
+async fn replay_sandbox_run(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<Uuid>,
+    body: Option<Json<ReplayRequest>>,
+) -> impl IntoResponse {
+    let use_current_policy = body.and_then(|Json(req)| req.use_current_policy).unwrap_or(false);
+
+    match sandbox::replay_run(&state, id, use_current_policy).await {
+        Ok((run, comparison)) => {
+            logger::add_log(
+                &state.logs,
+                LogLevel::Info,
+                LogSource::Sandbox,
+                format!("Sandbox run {} replayed as {}", id, run.id),
+                Some(json!({
+                    "run_id": run.id,
+                    "replay_of": id,
+                    "identical": comparison.identical
+                })),
+            );
+
+            Json(json!({ "success": true, "data": { "run": run, "comparison": comparison } })).into_response()
+        }
+        Err(sandbox::SubmitError::RunNotFound(_)) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Run not found" })),
+        )
+            .into_response(),
+        Err(e) => submit_error_response(e),
+    }
 }
 
 async fn get_sandbox_logs(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
@@ -155,17 +418,733 @@ async fn get_sandbox_resources(State(state): State<Arc<AppState>>, Path(id): Pat
     }
 }
 
+async fn start_sandbox_batch(State(state): State<Arc<AppState>>, Json(req): Json<BatchRunRequest>) -> impl IntoResponse {
+    match batch::start_batch(&state, req) {
+        Ok(started) => Json(json!({ "success": true, "data": started })).into_response(),
+        Err(batch::BatchError::NoTarget) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Batch needs a binary or code to run" })),
+        )
+            .into_response(),
+        Err(batch::BatchError::TooLarge { cells }) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": format!("Batch has {} combinations, the limit is {}", cells, batch::MAX_BATCH_CELLS)
+            })),
+        )
+            .into_response(),
+        Err(batch::BatchError::PolicyNotFound(name)) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Syscall policy '{}' not found", name) })),
+        )
+            .into_response(),
+        Err(batch::BatchError::BinaryNotFound(sha256)) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Stored binary {} not found", sha256) })),
+        )
+            .into_response(),
+        Err(batch::BatchError::HostBinaryPath(path)) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": format!("{} is not in the run's workspace or image; store it and list it in stored_binaries", path)
+            })),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_sandbox_batch(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let found = state.sandbox_batches.lock().unwrap().get(&id).cloned();
+    match found {
+        Some(found) => Json(json!({ "success": true, "data": batch::current(&state, found) })),
+        None => Json(json!({ "success": false, "error": "Batch not found" })),
+    }
+}
+
+async fn list_sandbox_batches(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let batches: Vec<SandboxBatch> = state.sandbox_batches.lock().unwrap().values().cloned().collect();
+    let mut list: Vec<SandboxBatch> = batches.into_iter().map(|found| batch::current(&state, found)).collect();
+    list.sort_by_key(|batch| std::cmp::Reverse(batch.created_at));
+    Json(json!({ "success": true, "data": list }))
+}
+
+async fn run_sandbox_selftest(
+    State(state): State<Arc<AppState>>,
+    body: Option<Json<SelftestRequest>>,
+) -> impl IntoResponse {
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+    match selftest::run_selftest(&state, req).await {
+        Ok(report) => Json(json!({ "success": true, "data": report })).into_response(),
+        Err(selftest::SelftestError::PolicyNotFound(name)) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Syscall policy '{}' not found", name) })),
+        )
+            .into_response(),
+        Err(selftest::SelftestError::Setup(e)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": e })),
+        )
+            .into_response(),
+    }
+}
+
+async fn list_sandbox_selftests(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let reports = state.selftest_reports.lock().unwrap();
+    let list: Vec<SelftestReport> = reports.iter().rev().cloned().collect();
+    Json(json!({ "success": true, "data": list }))
+}
+
 async fn list_sandbox_runs(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     let runs = state.sandbox_runs.lock().unwrap();
-    let run_list: Vec<SandboxRun> = runs.values().cloned().collect();
+    let run_list: Vec<SandboxRun> = runs
+        .values()
+        .cloned()
+        .map(|mut run| {
+            run.queue_position = state.sandbox_queue.position(run.id);
+            run
+        })
+        .collect();
     Json(run_list)
 }
 
+async fn get_sandbox_status(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let runs = state.sandbox_runs.lock().unwrap();
+    if let Some(run) = runs.get(&id) {
+        let mut run = run.clone();
+        run.queue_position = state.sandbox_queue.position(id);
+        Json(json!({ "success": true, "data": run }))
+    } else {
+        Json(json!({ "success": false, "error": "Run not found" }))
+    }
+}
+
+async fn get_sandbox_queue(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let queue = &state.sandbox_queue;
+    Json(json!({
+        "running": queue.running_count(),
+        "queued": queue.queued_count(),
+        "max_concurrent": queue.config().max_concurrent,
+        "max_per_project": queue.config().max_per_project,
+        "max_queued": queue.config().max_queued
+    }))
+}
+
+async fn get_sandbox_filesystem_changes(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let runs = state.sandbox_runs.lock().unwrap();
+    if let Some(run) = runs.get(&id) {
+        Json(json!({ "success": true, "data": run.filesystem_changes }))
+    } else {
+        Json(json!({ "success": false, "error": "Run not found" }))
+    }
+}
+
+async fn get_sandbox_process_tree(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let runs = state.sandbox_runs.lock().unwrap();
+    if let Some(run) = runs.get(&id) {
+        Json(json!({ "success": true, "data": run.process_tree }))
+    } else {
+        Json(json!({ "success": false, "error": "Run not found" }))
+    }
+}
+
+async fn get_sandbox_network_activity(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let runs = state.sandbox_runs.lock().unwrap();
+    if let Some(run) = runs.get(&id) {
+        Json(json!({
+            "success": true,
+            "data": {
+                "mode": run.network_mode,
+                "events": run.network_activity
+            }
+        }))
+    } else {
+        Json(json!({ "success": false, "error": "Run not found" }))
+    }
+}
+
+async fn get_sandbox_pcap_summary(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let capture = {
+        let runs = state.sandbox_runs.lock().unwrap();
+        match runs.get(&id) {
+            Some(run) => run.artifacts.iter().find(|artifact| artifact.kind == ArtifactKind::Pcap).cloned(),
+            None => return Json(json!({ "success": false, "error": "Run not found" })),
+        }
+    };
+
+    let Some(capture) = capture else {
+        return Json(json!({ "success": false, "error": "Run has no packet capture" }));
+    };
+
+    let summary = tokio::fs::read(artifacts::run_artifact_dir(id).join(&capture.name))
+        .await
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| pcap::summarize(&bytes));
+
+    match summary {
+        Ok(parsed) => Json(json!({
+            "success": true,
+            "data": PcapSummary {
+                artifact: capture.name,
+                link_type: parsed.link_type,
+                packets: parsed.packets,
+                flows: parsed.flows,
+            }
+        })),
+        Err(e) => Json(json!({ "success": false, "error": e })),
+    }
+}
+
+async fn diff_sandbox_runs(
+    State(state): State<Arc<AppState>>,
+    Path((left, right)): Path<(Uuid, Uuid)>,
+) -> impl IntoResponse {
+    let (left, right) = {
+        let runs = state.sandbox_runs.lock().unwrap();
+        match (runs.get(&left), runs.get(&right)) {
+            (Some(left), Some(right)) => (left.clone(), right.clone()),
+            _ => return Json(json!({ "success": false, "error": "Run not found" })),
+        }
+    };
+
+    let (left_flows, right_flows) = (pcap::run_flows(&left).await, pcap::run_flows(&right).await);
+    let diff = compare::diff_runs(&left, &right, &left_flows, &right_flows);
+
+    Json(json!({ "success": true, "data": diff }))
+}
+
+async fn list_sandbox_artifacts(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
+    let runs = state.sandbox_runs.lock().unwrap();
+    if let Some(run) = runs.get(&id) {
+        Json(json!({ "success": true, "data": run.artifacts }))
+    } else {
+        Json(json!({ "success": false, "error": "Run not found" }))
+    }
+}
+
+async fn download_sandbox_artifact(
+    State(state): State<Arc<AppState>>,
+    Path((id, name)): Path<(Uuid, String)>,
+) -> impl IntoResponse {
+    let known = {
+        let runs = state.sandbox_runs.lock().unwrap();
+        runs.get(&id)
+            .map(|run| run.artifacts.iter().any(|artifact| artifact.name == name))
+            .unwrap_or(false)
+    };
+
+    // Only names recorded on the run are served, so the path cannot escape the run's directory
+    if !known {
+        return (StatusCode::NOT_FOUND, Json(json!({ "error": "Artifact not found" }))).into_response();
+    }
+
+    match tokio::fs::read(artifacts::run_artifact_dir(id).join(&name)).await {
+        Ok(bytes) => {
+            let file_name = name.rsplit('/').next().unwrap_or(&name).replace('"', "");
+            let content_type = match name.as_str() {
+                artifacts::PCAP_ARTIFACT => "application/vnd.tcpdump.pcap",
+                artifacts::RECORDING_ARTIFACT => "application/x-asciicast",
+                _ => "application/octet-stream",
+            };
+            (
+                [
+                    (header::CONTENT_TYPE, content_type.to_string()),
+                    (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+                ],
+                bytes,
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "error": e.to_string() }))).into_response(),
+    }
+}
+
+// ============================================================================
+// Verdict Rule Endpoints
+// ============================================================================
+
+async fn list_verdict_rules(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(verdict::current_rules(&state))
+}
+
+async fn get_verdict_rule(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> impl IntoResponse {
+    let rules = state.verdict_rules.lock().unwrap();
+    match rules.get(&id).and_then(|versions| versions.last()) {
+        Some(rule) => Json(rule.clone()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Rule not found" })),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_verdict_rule_versions(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> impl IntoResponse {
+    let rules = state.verdict_rules.lock().unwrap();
+    match rules.get(&id) {
+        Some(versions) => Json(versions.clone()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Rule not found" })),
+        )
+            .into_response(),
+    }
+}
+
+async fn save_verdict_rule(State(state): State<Arc<AppState>>, Json(req): Json<VerdictRuleRequest>) -> impl IntoResponse {
+    let no_syscalls = matches!(&req.matcher, BehaviourMatcher::Syscall { syscalls, .. } if syscalls.is_empty());
+    if req.id.trim().is_empty() || no_syscalls {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Rule needs an id, and a syscall matcher needs at least one syscall" })),
+        )
+            .into_response();
+    }
+
+    Json(verdict::save_rule(&state, req)).into_response()
+}
+
+async fn delete_verdict_rule(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> impl IntoResponse {
+    let mut rules = state.verdict_rules.lock().unwrap();
+    match rules.get(&id).and_then(|versions| versions.last()) {
+        Some(rule) if rule.builtin => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": "Built-in rules cannot be deleted; save a version with enabled set to false instead" })),
+        )
+            .into_response(),
+        Some(_) => {
+            rules.remove(&id);
+            Json(json!({ "success": true, "id": id })).into_response()
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Rule not found" })),
+        )
+            .into_response(),
+    }
+}
+
+// ============================================================================
+// ATT&CK Mapping Endpoints
+// ============================================================================
+
+async fn get_attack_mapping(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.attack_mapping.lock().unwrap().clone())
+}
+
+async fn save_attack_mapping(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<AttackMappingRequest>,
+) -> impl IntoResponse {
+    match attack::save_mapping(req) {
+        Ok(mapping) => {
+            *state.attack_mapping.lock().unwrap() = mapping.clone();
+            Json(mapping).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": e })),
+        )
+            .into_response(),
+    }
+}
+
+/// Goes back to the built-in table, dropping the saved one.
+async fn reset_attack_mapping(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    if let Err(e) = std::fs::remove_file(attack::mapping_path()) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "success": false, "error": format!("Failed to remove the saved mapping: {}", e) })),
+            )
+                .into_response();
+        }
+    }
+
+    let mapping = attack::builtin_mapping();
+    *state.attack_mapping.lock().unwrap() = mapping.clone();
+    Json(mapping).into_response()
+}
+
+// ============================================================================
+// Binary Inspection Endpoints
+// ============================================================================
+
+async fn list_binaries(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let mut binaries: Vec<BinaryInspection> = state.binaries.lock().unwrap().values().cloned().collect();
+    binaries.sort_by_key(|binary| std::cmp::Reverse(binary.inspected_at));
+    Json(binaries)
+}
+
+/// Inspects a binary from the store by its sha256. A hash the store no longer has
+/// still returns the inspection made when a run used it; host paths are not accepted.
+async fn inspect_binary(State(state): State<Arc<AppState>>, Json(req): Json<InspectBinaryRequest>) -> impl IntoResponse {
+    let sha256 = req.sha256.trim().to_lowercase();
+    if store::get_binary(&sha256).is_none() {
+        return match state.binaries.lock().unwrap().get(&sha256) {
+            Some(inspection) => Json(inspection.clone()).into_response(),
+            None => (
+                StatusCode::NOT_FOUND,
+                Json(json!({ "success": false, "error": "Binary not found" })),
+            )
+                .into_response(),
+        };
+    }
+
+    let blob = store::blob_path(&sha256);
+    if !elf::is_elf(&blob) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Binary is not an ELF file" })),
+        )
+            .into_response();
+    }
+
+    match tokio::task::spawn_blocking(move || elf::inspect_file(&blob)).await {
+        Ok(Ok(inspection)) => {
+            state.binaries.lock().unwrap().insert(inspection.id.clone(), inspection.clone());
+            Json(inspection).into_response()
+        }
+        // The error names the blob's path in the store, which stays in the log
+        Ok(Err(e)) => {
+            tracing::warn!("Failed to inspect stored binary {}: {}", sha256, e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "success": false, "error": "Binary could not be parsed as ELF" })),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": format!("Inspection failed: {}", e) })),
+        )
+            .into_response(),
+    }
+}
+
+async fn get_binary_inspection(State(state): State<Arc<AppState>>, Path(id): Path<String>) -> impl IntoResponse {
+    match state.binaries.lock().unwrap().get(&id.to_lowercase()) {
+        Some(inspection) => Json(inspection.clone()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Binary not found" })),
+        )
+            .into_response(),
+    }
+}
+
+// ============================================================================
+// Root Filesystem Endpoints
+// ============================================================================
+
+async fn list_rootfs_images() -> impl IntoResponse {
+    Json(rootfs::list_images())
+}
+
+async fn get_rootfs_image(Path(name): Path<String>) -> impl IntoResponse {
+    match rootfs::get_image(&name) {
+        Some(image) => Json(image).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Image not found" })),
+        )
+            .into_response(),
+    }
+}
+
+/// Takes the archive as the raw request body. It is streamed to disk first, then
+/// unpacked off the async runtime.
+async fn upload_rootfs_image(Path(name): Path<String>, body: Body) -> impl IntoResponse {
+    if !rootfs::valid_name(&name) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Image names use letters, digits, '.', '_' and '-'" })),
+        )
+            .into_response();
+    }
+    if rootfs::get_image(&name).is_some() {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": format!("Image '{}' already exists", name) })),
+        )
+            .into_response();
+    }
+
+    let upload = rootfs::images_dir().join(format!(".upload-{}", Uuid::new_v4()));
+    if let Err(response) = receive_upload(body, &upload, rootfs::MAX_UPLOAD_SIZE).await {
+        let _ = tokio::fs::remove_file(&upload).await;
+        return response;
+    }
+
+    let path = upload.clone();
+    let result = tokio::task::spawn_blocking(move || rootfs::import_image(&name, &path)).await;
+    let _ = tokio::fs::remove_file(&upload).await;
+
+    let (status, error) = match result {
+        Ok(Ok(image)) => return Json(image).into_response(),
+        Ok(Err(rootfs::ImportError::InvalidName)) => (StatusCode::BAD_REQUEST, "Invalid image name".to_string()),
+        Ok(Err(rootfs::ImportError::Exists)) => (StatusCode::CONFLICT, "Image already exists".to_string()),
+        Ok(Err(rootfs::ImportError::Invalid(e))) => (StatusCode::BAD_REQUEST, e),
+        Ok(Err(rootfs::ImportError::Storage(e))) => (StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("Import failed: {}", e)),
+    };
+    (status, Json(json!({ "success": false, "error": error }))).into_response()
+}
+
+async fn receive_upload(body: Body, path: &std::path::Path, max_size: u64) -> Result<(), Response> {
+    let storage_error = |e: std::io::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": format!("Failed to store upload: {}", e) })),
+        )
+            .into_response()
+    };
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await.map_err(storage_error)?;
+    }
+    let mut file = tokio::fs::File::create(path).await.map_err(storage_error)?;
+
+    let mut received = 0u64;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(json!({ "success": false, "error": format!("Upload interrupted: {}", e) })),
+            )
+                .into_response()
+        })?;
+
+        received += chunk.len() as u64;
+        if received > max_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(json!({ "success": false, "error": "Upload is too large" })),
+            )
+                .into_response());
+        }
+        file.write_all(&chunk).await.map_err(storage_error)?;
+    }
+
+    file.flush().await.map_err(storage_error)
+}
+
+/// Refuses while a queued or running sample still needs the image.
+async fn delete_rootfs_image(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> impl IntoResponse {
+    match rootfs::delete_image(&state, &name) {
+        Ok(store::Deletion::Deleted) => Json(json!({ "success": true, "name": name })).into_response(),
+        Ok(store::Deletion::InUse(user)) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": format!("Image is in use by {}", user) })),
+        )
+            .into_response(),
+        Ok(store::Deletion::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Image not found" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": format!("Failed to delete image: {}", e) })),
+        )
+            .into_response(),
+    }
+}
+
+// ============================================================================
+// Binary Store Endpoints
+// ============================================================================
+
+async fn list_stored_binaries() -> impl IntoResponse {
+    Json(store::list_binaries())
+}
+
+async fn get_stored_binary(Path(sha256): Path<String>) -> impl IntoResponse {
+    match store::get_binary(&sha256.to_lowercase()) {
+        Some(binary) => Json(binary).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Binary not found" })),
+        )
+            .into_response(),
+    }
+}
+
+/// Takes the binary as the raw request body and its metadata from the query string.
+/// ELF binaries are inspected as they are stored, under the same hash.
+async fn upload_stored_binary(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<StoreUploadParams>,
+    body: Body,
+) -> impl IntoResponse {
+    let name = params.name.trim().to_string();
+    if name.is_empty() || name.len() > 255 {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Binary name must be 1 to 255 characters" })),
+        )
+            .into_response();
+    }
+    let uploader = params.uploader.map(|uploader| uploader.trim().to_string()).filter(|uploader| !uploader.is_empty());
+    let tags: Vec<String> = params
+        .tags
+        .iter()
+        .flat_map(|tags| tags.split(','))
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+
+    let upload = store::store_dir().join(format!(".upload-{}", Uuid::new_v4()));
+    if let Err(response) = receive_upload(body, &upload, store::MAX_UPLOAD_SIZE).await {
+        let _ = tokio::fs::remove_file(&upload).await;
+        return response;
+    }
+
+    let path = upload.clone();
+    let result = tokio::task::spawn_blocking(move || store::add_binary(&path, &name, uploader, tags)).await;
+    // Already moved into the store unless something failed
+    let _ = tokio::fs::remove_file(&upload).await;
+
+    let binary = match result {
+        Ok(Ok(binary)) => binary,
+        Ok(Err(e)) => {
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(json!({ "success": false, "error": e }))).into_response();
+        }
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "success": false, "error": format!("Failed to store binary: {}", e) })),
+            )
+                .into_response();
+        }
+    };
+
+    let blob = store::blob_path(&binary.sha256);
+    if elf::is_elf(&blob) {
+        if let Ok(Ok(inspection)) = tokio::task::spawn_blocking(move || elf::inspect_file(&blob)).await {
+            state.binaries.lock().unwrap().insert(inspection.id.clone(), inspection);
+        }
+    }
+
+    Json(binary).into_response()
+}
+
+/// Refuses while a queued or running sample or a running campaign still needs the binary.
+async fn delete_stored_binary(State(state): State<Arc<AppState>>, Path(sha256): Path<String>) -> impl IntoResponse {
+    let sha256 = sha256.to_lowercase();
+    match store::delete_binary(&state, &sha256) {
+        Ok(store::Deletion::Deleted) => Json(json!({ "success": true, "sha256": sha256 })).into_response(),
+        Ok(store::Deletion::InUse(user)) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": format!("Binary is in use by {}", user) })),
+        )
+            .into_response(),
+        Ok(store::Deletion::NotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Binary not found" })),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "success": false, "error": format!("Failed to delete binary: {}", e) })),
+        )
+            .into_response(),
+    }
+}
+
+/// Removes stored binaries that no run, batch or campaign refers to. The body is
+/// optional.
+async fn collect_stored_binaries(
+    State(state): State<Arc<AppState>>,
+    req: Option<Json<StoreGcRequest>>,
+) -> impl IntoResponse {
+    let req = req.map(|Json(req)| req).unwrap_or_default();
+    let min_age = match req.min_age.as_deref() {
+        Some(value) => match sandbox::parse_duration(value) {
+            Some(duration) => duration,
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "success": false, "error": format!("Invalid min_age '{}'", value) })),
+                )
+                    .into_response();
+            }
+        },
+        None => std::time::Duration::from_secs(3600),
+    };
+    let cutoff = chrono::Duration::from_std(min_age)
+        .ok()
+        .and_then(|age| chrono::Utc::now().checked_sub_signed(age))
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+    let dry_run = req.dry_run.unwrap_or(false);
+
+    let referenced = store::referenced_binaries(&state);
+    let pins = state.store_pins.clone();
+    let removed = match tokio::task::spawn_blocking(move || store::collect_garbage(&pins, &referenced, cutoff, dry_run)).await {
+        Ok(removed) => removed,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({ "success": false, "error": format!("Garbage collection failed: {}", e) })),
+            )
+                .into_response();
+        }
+    };
+
+    if !dry_run && !removed.is_empty() {
+        logger::add_log(
+            &state.logs,
+            LogLevel::Info,
+            LogSource::System,
+            format!("Removed {} unreferenced stored binaries", removed.len()),
+            Some(json!({ "sha256": removed.iter().map(|binary| &binary.sha256).collect::<Vec<_>>() })),
+        );
+    }
+
+    Json(StoreGcReport {
+        freed_bytes: removed.iter().map(|binary| binary.size).sum(),
+        remaining: store::list_binaries().len(),
+        removed,
+        dry_run,
+    })
+    .into_response()
+}
+
 // ============================================================================
 // Fuzzing Endpoints
 // ============================================================================
 
-async fn start_fuzzing(State(state): State<Arc<AppState>>, Json(req): Json<FuzzStartRequest>) -> impl IntoResponse {
+/// The target is always a stored binary; it stays pinned until the campaign is
+/// recorded, which then keeps it from being collected.
+async fn start_fuzzing(State(state): State<Arc<AppState>>, Json(mut req): Json<FuzzStartRequest>) -> impl IntoResponse {
+    if req.target_binary.is_some() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({
+                "success": false,
+                "error": "target_binary is no longer accepted; upload the binary to /api/store and pass its target_sha256"
+            })),
+        )
+            .into_response();
+    }
+    req.target_sha256 = req.target_sha256.to_lowercase();
+    if req.target_sha256.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Campaign needs a target_sha256" })),
+        )
+            .into_response();
+    }
+    let Some(_pin) = store::pin(&state.store_pins, &req.target_sha256) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": format!("Stored binary {} not found", req.target_sha256) })),
+        )
+            .into_response();
+    };
+
     let campaign = fuzzer::start_fuzzing(req).await;
     let campaign_id = campaign.id;
 
@@ -184,7 +1163,7 @@ async fn start_fuzzing(State(state): State<Arc<AppState>>, Json(req): Json<FuzzS
         Some(json!({ "campaign_id": campaign_id })),
     );
 
-    Json(campaign)
+    Json(campaign).into_response()
 }
 
 async fn stop_fuzzing(State(state): State<Arc<AppState>>, Path(id): Path<Uuid>) -> impl IntoResponse {
@@ -272,3 +1251,119 @@ async fn create_project(
 
     Json(project)
 }
+
+// ============================================================================
+// Policy Endpoints
+// ============================================================================
+
+async fn list_policies(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let policies = state.syscall_policies.lock().unwrap();
+    let mut list: Vec<SyscallPolicy> = policies.values().cloned().collect();
+    list.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(list)
+}
+
+async fn get_policy(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> impl IntoResponse {
+    let policies = state.syscall_policies.lock().unwrap();
+    match policies.get(&name) {
+        Some(policy) => Json(policy.clone()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Policy not found" })),
+        )
+            .into_response(),
+    }
+}
+
+async fn save_policy(State(state): State<Arc<AppState>>, Json(req): Json<CreatePolicyRequest>) -> impl IntoResponse {
+    if req.name.trim().is_empty() || req.rules.iter().any(|rule| rule.syscalls.is_empty()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Policy needs a name and every rule needs at least one syscall" })),
+        )
+            .into_response();
+    }
+
+    let mut policies = state.syscall_policies.lock().unwrap();
+    if policies.get(&req.name).is_some_and(|existing| existing.builtin) {
+        return (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": "Built-in policies cannot be replaced" })),
+        )
+            .into_response();
+    }
+
+    let policy = SyscallPolicy {
+        name: req.name,
+        description: req.description,
+        mode: req.mode,
+        default_action: req.default_action,
+        rules: req.rules,
+        builtin: false,
+        updated_at: chrono::Utc::now(),
+    };
+    policies.insert(policy.name.clone(), policy.clone());
+
+    Json(policy).into_response()
+}
+
+async fn learn_policy(State(state): State<Arc<AppState>>, Json(req): Json<LearnPolicyRequest>) -> impl IntoResponse {
+    if req.name.trim().is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "success": false, "error": "Policy needs a name" })),
+        )
+            .into_response();
+    }
+
+    match policy::learn_policy(&state, req).await {
+        Ok(learned) => Json(json!({ "success": true, "data": learned })).into_response(),
+        Err(policy::LearnError::Submit(e)) => submit_error_response(e),
+        Err(policy::LearnError::NothingObserved) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "success": false, "error": "No learning run completed with a syscall trace" })),
+        )
+            .into_response(),
+        Err(policy::LearnError::BuiltinName) => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": "Built-in policies cannot be replaced" })),
+        )
+            .into_response(),
+    }
+}
+
+async fn export_policy_seccomp(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> impl IntoResponse {
+    let policies = state.syscall_policies.lock().unwrap();
+    match policies.get(&name) {
+        Some(found) => (
+            [(header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.json\"", name))],
+            Json(policy::to_oci_seccomp(found)),
+        )
+            .into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Policy not found" })),
+        )
+            .into_response(),
+    }
+}
+
+async fn delete_policy(State(state): State<Arc<AppState>>, Path(name): Path<String>) -> impl IntoResponse {
+    let mut policies = state.syscall_policies.lock().unwrap();
+    match policies.get(&name) {
+        Some(policy) if policy.builtin => (
+            StatusCode::CONFLICT,
+            Json(json!({ "success": false, "error": "Built-in policies cannot be deleted" })),
+        )
+            .into_response(),
+        Some(_) => {
+            policies.remove(&name);
+            Json(json!({ "success": true, "name": name })).into_response()
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "success": false, "error": "Policy not found" })),
+        )
+            .into_response(),
+    }
+}
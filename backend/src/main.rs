@@ -2,17 +2,22 @@ mod api;
 mod models;
 mod services;
 
-use axum::{
-    http::Method,
-    Router,
-};
+use axum::http::Method;
 use services::AppState;
 use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber;
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some(services::selftest::PROBE_FLAG) {
+        std::process::exit(services::selftest::run_probe(&args[2..]));
+    }
+
+    serve();
+}
 
 #[tokio::main]
-async fn main() {
+async fn serve() {
     // Initialize tracing
     tracing_subscriber::fmt()
         .with_max_level(tracing::Level::INFO)
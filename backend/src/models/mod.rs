@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 // ============================================================================
@@ -32,35 +33,414 @@ pub struct PolicyValidationResult {
 // Sandbox Models
 // ============================================================================
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SandboxRunRequest {
-    pub binary_path: Option<String>,
+    pub binary_path: Option<String>,  // Relative to the working directory, or inside the rootfs image
+    pub binary_sha256: Option<String>,  // A StoredBinary, in place of binary_path
     pub code: Option<String>,  // If provided, compiles and runs AegisLang
+    pub backend: Option<SandboxBackend>,  // Defaults to Wasm for WebAssembly modules
+    pub fuel: Option<u64>,  // Instruction budget of a Wasm run
+    pub rootfs: Option<String>,  // Name of a stored RootfsImage to run in
     pub memory_limit: Option<String>,
     pub timeout: Option<String>,
-    pub network_enabled: Option<bool>,
+    pub network_enabled: Option<bool>,  // Legacy switch, superseded by network_mode
+    pub network_mode: Option<NetworkMode>,
+    pub pids_limit: Option<u32>,
+    pub open_files_limit: Option<u32>,
+    pub file_size_limit: Option<String>,
+    pub cpu_quota: Option<f64>,  // Fraction of CPUs, e.g. 0.5
+    pub cpu_shares: Option<u32>,
+    pub disk_quota: Option<String>,
+    pub core_size_limit: Option<String>,
+    pub args: Option<Vec<String>>,
+    pub stdin: Option<String>,  // Base64-encoded bytes
+    pub working_dir: Option<String>,  // Relative to the run's workspace
+    pub env: Option<BTreeMap<String, String>>,
+    pub inherit_env: Option<bool>,  // Defaults to a clean environment
+    pub input_files: Option<Vec<SandboxInputFile>>,
+    pub output_paths: Option<Vec<String>>,  // Files or directories collected as artifacts
+    pub project_id: Option<Uuid>,
+    pub priority: Option<RunPriority>,
+    pub syscall_policy: Option<String>,  // Name of a stored SyscallPolicy
+    pub deterministic: Option<DeterministicConfig>,
+    pub rerun_of: Option<Uuid>,  // Compare the outcome with this earlier run
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DeterministicConfig {
+    pub clock: Option<DateTime<Utc>>,  // Defaults to 2000-01-01T00:00:00Z
+    pub timezone: Option<String>,  // Defaults to UTC
+    pub seed: Option<u64>,  // Seeds getrandom, /dev/urandom and AT_RANDOM
+    pub cpu: Option<u32>,  // CPU the run is pinned to, defaults to 0
+    pub hostname: Option<String>,
+}
+
+/// The settings a deterministic run actually used, with every default filled in.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DeterministicSettings {
+    pub clock: DateTime<Utc>,
+    pub timezone: String,
+    pub seed: u64,
+    pub cpu: u32,
+    pub hostname: String,
+    pub environment: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RunPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SandboxInputFile {
+    pub path: String,  // Relative to the working directory
+    pub content: String,  // Base64-encoded bytes
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SandboxRun {
     pub id: Uuid,
     pub status: RunStatus,
+    pub project_id: Option<Uuid>,
+    pub priority: RunPriority,
+    pub queue_position: Option<usize>,
     pub stdout: String,
     pub stderr: String,
     pub exit_code: Option<i32>,
+    pub signal: Option<i32>,  // Set when the process was killed by a signal instead of exiting
+    pub backtrace: Vec<StackFrame>,  // Of the core dump, when the process left one
     pub syscall_log: Vec<SyscallEntry>,
     pub resource_usage: ResourceUsage,
+    pub limits_hit: Vec<ResourceLimit>,
+    pub artifacts: Vec<RunArtifact>,
+    pub filesystem_changes: Vec<FileChange>,
+    pub process_tree: Vec<ProcessNode>,
+    pub syscall_policy: Option<String>,
+    pub policy_violations: Vec<PolicyViolation>,
+    pub syscall_counts: BTreeMap<String, u64>,  // Calls per syscall over the whole trace, not just the capped log
+    pub network_mode: Option<NetworkMode>,  // None means host networking
+    pub network_activity: Vec<NetworkEvent>,
+    pub network_activity_truncated: bool,  // Events or connections beyond the emulated network's caps were dropped
+    pub deterministic: Option<DeterministicSettings>,
+    pub rerun_of: Option<Uuid>,
+    pub reproduction: Option<ReproductionReport>,
+    pub request: SandboxRunRequest,  // As submitted, for replays
+    pub policy_snapshot: Option<SyscallPolicy>,  // The policy as it was when the run started
+    pub toolchain: ToolchainVersions,
+    pub replay_of: Option<Uuid>,
+    pub interactive: bool,  // Ran on a pseudo-terminal; stdout is the terminal transcript
+    pub backend: SandboxBackend,
+    pub rootfs_image: Option<RootfsImage>,  // The image as it was when the run was submitted
+    pub binary_inspection: Option<BinaryInspection>,  // Of an ELF binary_path, taken before it ran
+    pub verdict: Option<RunVerdict>,
+    pub attack_techniques: Vec<AttackTechnique>,
     pub created_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ToolchainVersions {
+    pub aegis_sandbox: Option<String>,
+    pub aegiscc: Option<String>,  // Only queried for code runs
+    #[serde(default)]
+    pub wasm_runtime: Option<String>,  // Only set for Wasm runs
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ReplayRequest {
+    pub use_current_policy: Option<bool>,  // Defaults to the policy snapshot taken by the original run
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunComparison {
+    pub original_run: Uuid,
+    pub replay_run: Uuid,
+    pub identical: bool,  // No outcome differences
+    pub setup_differences: Vec<RunDifference>,  // Toolchain, policy and deterministic settings
+    pub outcome_differences: Vec<RunDifference>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunDifference {
+    pub field: String,
+    pub original: serde_json::Value,
+    pub replay: serde_json::Value,
+}
+
+// First message on /api/sandbox/pty
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PtyStartRequest {
+    pub run: SandboxRunRequest,
+    pub cols: Option<u16>,  // Defaults to 80
+    pub rows: Option<u16>,  // Defaults to 24
+}
+
+// Binary frames from the client are keyboard input too
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum PtyClientMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+}
+
+// Terminal output goes to the client as binary frames
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum PtyServerMessage {
+    Started { run_id: Uuid },
+    Exit { run: Box<SandboxRun> },
+    Error { error: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchRunRequest {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub base: SandboxRunRequest,  // Settings shared by every cell
+    #[serde(default)]
+    pub matrix: BatchMatrix,
+}
+
+// An empty axis keeps the base request's value
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct BatchMatrix {
+    pub binaries: Vec<String>,
+    pub stored_binaries: Vec<String>,  // sha256 of StoredBinary entries
+    pub code: Vec<String>,
+    pub memory_limits: Vec<String>,
+    pub timeouts: Vec<String>,
+    pub network_modes: Vec<NetworkMode>,
+    pub syscall_policies: Vec<Option<String>>,  // null runs unconfined
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SandboxBatch {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub status: BatchStatus,
+    pub cells: Vec<BatchCell>,
+    pub outcomes: Vec<BatchOutcome>,  // Distinct results, in order of the first cell that produced each
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum BatchStatus {
+    Running,
+    Completed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchCell {
+    pub target: String,  // Binary path, "sha256:<hash>" of a stored binary, or "code #<n>"
+    pub memory_limit: Option<String>,
+    pub timeout: Option<String>,
+    pub network_mode: Option<NetworkMode>,
+    pub syscall_policy: Option<String>,
+    pub status: RunStatus,
+    pub run_id: Option<Uuid>,
+    pub error: Option<String>,  // Why the run could not be scheduled
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub limits_hit: Vec<ResourceLimit>,
+    pub execution_time_ms: Option<i64>,
+    pub memory_mb: Option<f64>,
+    pub syscalls_count: Option<i32>,
+    pub policy_violations: usize,
+    pub network_requests: usize,
+    pub outcome: Option<usize>,  // Index into the batch's outcomes
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BatchOutcome {
+    pub status: RunStatus,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub limits_hit: Vec<ResourceLimit>,
+    pub cells: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunDiff {
+    pub left_run: Uuid,
+    pub right_run: Uuid,
+    pub exit_status: Vec<FieldDiff>,
+    pub resource_usage: Vec<FieldDiff>,
+    pub stdout: Vec<Aligned<String>>,  // By line
+    pub stderr: Vec<Aligned<String>>,
+    pub syscalls: Vec<Aligned<SyscallEntry>>,
+    pub syscalls_aligned: bool,  // False when the logs were too long to align and are paired by position
+    pub first_divergence: Option<usize>,  // Index into `syscalls`
+    pub filesystem_changes: Vec<Aligned<FileChange>>,
+    pub network_flows: Vec<Aligned<NetworkFlow>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FieldDiff {
+    pub field: String,
+    pub left: serde_json::Value,
+    pub right: serde_json::Value,
+    pub changed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Aligned<T> {
+    pub op: DiffOp,
+    pub left: Option<T>,
+    pub right: Option<T>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum DiffOp {
+    Equal,
+    Changed,  // Same call, path or flow with different details
+    Removed,  // Only in the left run
+    Added,  // Only in the right run
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ReproductionReport {
+    pub original_run: Uuid,
+    pub reproduced: bool,
+    pub stdout_matches: bool,
+    pub exit_status_matches: bool,  // Exit code and signal
+    pub syscalls_match: bool,
+    pub settings_match: bool,  // Both runs used the same deterministic settings
+    pub first_syscall_divergence: Option<SyscallDivergence>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyscallDivergence {
+    pub index: usize,
+    pub original: Option<String>,  // None when that run's log ended first
+    pub rerun: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum RunStatus {
     Pending,
     Running,
     Completed,
     Failed,
     Timeout,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ResourceLimit {
+    Memory,
+    Timeout,
+    Processes,
+    OpenFiles,
+    FileSize,
+    Cpu,
+    Disk,
+    CoreSize,
+    Fuel,  // Wasm instruction budget
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum SandboxBackend {
+    #[default]
+    Native,  // Process under aegis-sandbox
+    Wasm,  // Module in the embedded WebAssembly runtime
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum NetworkMode {
+    None,
+    Loopback,
+    Emulated,  // Private network wired to the backend's fake DNS and sinks
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkEvent {
+    pub protocol: NetworkProtocol,
+    pub destination: Option<String>,  // Address the sample dialled, when the redirect exposes it
+    pub host: Option<String>,  // DNS name queried or HTTP Host header
+    pub request: Option<String>,  // DNS record type or HTTP request line
+    pub payload: String,  // Base64, first 64 KiB
+    pub payload_size: u64,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum NetworkProtocol {
+    Dns,
+    Tcp,
+    Http,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkFlow {
+    pub protocol: String,  // TCP, UDP, ICMP, ICMPv6 or the IP protocol number
+    pub source: String,  // The endpoint that sent the first packet
+    pub source_port: Option<u16>,
+    pub destination: String,
+    pub destination_port: Option<u16>,
+    pub packets: u64,
+    pub bytes_sent: u64,  // Source to destination, as captured on the wire
+    pub bytes_received: u64,
+    pub first_payload: Option<String>,  // Base64, first 64 bytes of application data
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PcapSummary {
+    pub artifact: String,
+    pub link_type: u32,
+    pub packets: u64,
+    pub flows: Vec<NetworkFlow>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunArtifact {
+    pub name: String,
+    pub kind: ArtifactKind,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ArtifactKind {
+    Output,
+    Pcap,
+    CoreDump,
+    Recording,  // asciicast v2
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StackFrame {
+    pub index: u32,
+    pub address: Option<String>,
+    pub function: Option<String>,
+    pub file: Option<String>,  // From DWARF line info
+    pub line: Option<u32>,
+    pub module: Option<String>,  // Shared object, when there is no line info
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileChange {
+    pub path: String,  // Relative to the run's workspace, or absolute under /tmp and in a rootfs image
+    pub kind: FileChangeKind,
+    pub renamed_from: Option<String>,
+    pub size_before: Option<u64>,
+    pub size_after: Option<u64>,
+    pub sha256_before: Option<String>,
+    pub sha256_after: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum FileChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Renamed,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -69,15 +449,422 @@ pub struct SyscallEntry {
     pub args: String,
     pub result: String,
     pub timestamp: DateTime<Utc>,
-    pub allowed: bool,
+    pub allowed: bool,  // False when the trace shows the filter refused the call
+    pub matched_rule: Option<String>,  // Policy rule that decides the call, from evaluating the trace
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessNode {
+    pub pid: u32,
+    pub parent_pid: Option<u32>,
+    pub argv: Vec<String>,
+    pub executable: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub core_dumped: bool,
+    pub resource_usage: ProcessResourceUsage,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessResourceUsage {
+    pub syscalls_count: u32,
+    pub user_time_ms: Option<f64>,
+    pub system_time_ms: Option<f64>,
+    pub max_rss_kb: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ResourceUsage {
-    pub memory_mb: f64,
-    pub cpu_percent: f64,
+    pub memory_mb: Option<f64>,  // Peak, when the sandbox or runtime measured it
+    pub cpu_percent: Option<f64>,  // CPU time over wall time, when the sandbox measured it
     pub execution_time_ms: i64,
     pub syscalls_count: i32,
+    #[serde(default)]
+    pub fuel_consumed: Option<u64>,  // Wasm runs only
+}
+
+// ============================================================================
+// Syscall Policy Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyscallPolicy {
+    pub name: String,
+    pub description: String,
+    pub mode: PolicyMode,
+    pub default_action: Option<PolicyAction>,  // Allowlist: Errno(EPERM), Denylist: Allow
+    pub rules: Vec<SyscallRule>,
+    pub builtin: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum PolicyMode {
+    Allowlist,
+    Denylist,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyscallRule {
+    pub name: Option<String>,
+    pub syscalls: Vec<String>,
+    #[serde(default)]
+    pub conditions: Vec<ArgCondition>,  // All must hold
+    pub action: PolicyAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ArgCondition {
+    pub index: u8,
+    pub op: ConditionOp,
+    pub value: u64,
+    pub value_two: Option<u64>,  // Expected value for MaskedEqual
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ConditionOp {
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    MaskedEqual,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum PolicyAction {
+    Allow,
+    Log,
+    Errno(i32),
+    Kill,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PolicyViolation {
+    pub policy: String,
+    pub syscall: String,
+    pub rule: String,
+    pub action: PolicyAction,
+    pub count: u64,
+    pub not_enforced: u64,  // Denied calls the trace shows went through anyway
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatePolicyRequest {
+    pub name: String,
+    pub description: String,
+    pub mode: PolicyMode,
+    pub default_action: Option<PolicyAction>,
+    pub rules: Vec<SyscallRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LearnPolicyRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub run: SandboxRunRequest,  // The known-good run to observe
+    pub iterations: Option<u32>,  // Defaults to 1
+    pub save: Option<bool>,  // Store the result as a reusable policy
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LearnedPolicy {
+    pub policy: SyscallPolicy,
+    pub run_ids: Vec<Uuid>,
+    pub learned_from: Vec<Uuid>,  // The runs that completed successfully
+    pub saved: bool,
+}
+
+// ============================================================================
+// Verdict Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VerdictRule {
+    pub id: String,
+    pub version: u32,  // Starts at 1, bumped on every change
+    pub description: String,
+    pub score: u32,  // Added to the run's score when the rule triggers
+    pub matcher: BehaviourMatcher,
+    pub enabled: bool,
+    pub builtin: bool,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum BehaviourMatcher {
+    // Calls to any of the syscalls whose arguments contain any of the strings (or any call if none)
+    Syscall {
+        syscalls: Vec<String>,
+        #[serde(default)]
+        args_contain: Vec<String>,
+        min_count: Option<u64>,  // Defaults to 1
+    },
+    // Files opened for writing, created, removed or renamed
+    FileWrite {
+        #[serde(default)]
+        outside_workdir: bool,
+        #[serde(default)]
+        paths: Vec<String>,  // Prefixes of absolute paths; empty matches any
+        #[serde(default)]
+        ignore: Vec<String>,
+    },
+    // Processes the target started, by executable name
+    Process {
+        #[serde(default)]
+        executables: Vec<String>,  // Empty matches any
+        min_count: Option<u64>,
+    },
+    // connect() and sendto() to inet addresses, and connections on the emulated network
+    Connection {
+        #[serde(default)]
+        include_loopback: bool,
+        #[serde(default)]
+        ports: Vec<u16>,  // Empty matches any
+    },
+    DnsQuery {
+        #[serde(default)]
+        hosts: Vec<String>,  // Name suffixes; empty matches any
+    },
+    PolicyViolation,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VerdictRuleRequest {
+    pub id: String,
+    pub description: String,
+    pub score: u32,
+    pub matcher: BehaviourMatcher,
+    pub enabled: Option<bool>,  // Defaults to true
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RunVerdict {
+    pub verdict: Verdict,
+    pub score: u32,
+    pub behaviours: Vec<TriggeredBehaviour>,
+    pub rules: BTreeMap<String, u32>,  // Version of every rule evaluated
+    pub scored_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Verdict {
+    Benign,
+    Suspicious,
+    Malicious,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriggeredBehaviour {
+    pub rule: String,
+    pub rule_version: u32,
+    pub description: String,
+    pub score: u32,
+    pub count: u64,
+    pub evidence: Vec<String>,  // The first few matching events
+}
+
+// ============================================================================
+// ATT&CK Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttackMapping {
+    pub attack_version: String,  // ATT&CK release the table follows, e.g. "v15"
+    pub techniques: Vec<TechniqueMapping>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TechniqueMapping {
+    pub technique_id: String,  // e.g. "T1055" or "T1053.003"; several entries may share one
+    pub name: String,
+    pub tactics: Vec<String>,
+    pub matcher: BehaviourMatcher,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttackMappingRequest {
+    pub attack_version: String,
+    pub techniques: Vec<TechniqueMapping>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AttackTechnique {
+    pub technique_id: String,
+    pub name: String,
+    pub tactics: Vec<String>,
+    pub count: u64,
+    pub evidence: Vec<String>,  // The first few matching events
+}
+
+// ============================================================================
+// Selftest Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SelftestRequest {
+    pub syscall_policy: Option<String>,  // Checked together with the sandbox itself
+    pub network_mode: Option<NetworkMode>,  // Defaults to None
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelftestReport {
+    pub id: Uuid,
+    pub passed: bool,  // Every probe was blocked
+    pub syscall_policy: Option<String>,
+    pub network_mode: NetworkMode,
+    pub sandbox_version: Option<String>,
+    pub probes: Vec<ProbeResult>,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeResult {
+    pub probe: String,
+    pub description: String,
+    pub outcome: ProbeOutcome,
+    pub detail: String,  // What the probe managed to do, or why it could not
+    pub run_id: Option<Uuid>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ProbeOutcome {
+    Blocked,
+    Escaped,
+    Error,  // The probe could not be run or said nothing
+}
+
+// ============================================================================
+// Root Filesystem Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RootfsImage {
+    pub name: String,
+    pub format: RootfsFormat,
+    pub digest: String,  // sha256 of the uploaded archive
+    pub os: Option<String>,  // From the OCI image config
+    pub architecture: Option<String>,
+    pub layers: usize,
+    pub size_bytes: u64,  // Unpacked
+    pub file_count: u64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum RootfsFormat {
+    Tarball,  // A root filesystem tree, as from `docker export`
+    OciLayout,  // An OCI image layout, as from `skopeo copy oci-archive:` or `docker save`
+}
+
+// ============================================================================
+// Binary Inspection Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InspectBinaryRequest {
+    pub sha256: String,  // Of a binary in the store
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BinaryInspection {
+    pub id: String,  // sha256 of the file
+    pub path: String,  // Where it was last inspected
+    pub size: u64,
+    pub class: String,  // ELF32 or ELF64
+    pub endianness: String,
+    pub architecture: String,
+    pub file_type: ElfFileType,
+    pub entry_point: u64,
+    pub interpreter: Option<String>,
+    pub statically_linked: bool,
+    pub libraries: Vec<String>,  // DT_NEEDED
+    pub rpaths: Vec<String>,  // DT_RPATH and DT_RUNPATH
+    pub sections: Vec<ElfSection>,
+    pub imported_symbols: Vec<String>,
+    pub exported_symbols: Vec<String>,
+    pub stripped: bool,  // No .symtab
+    pub hardening: Hardening,
+    pub inspected_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ElfFileType {
+    Executable,
+    PieExecutable,
+    SharedLibrary,
+    Relocatable,
+    Core,
+    Other,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ElfSection {
+    pub name: String,
+    pub section_type: String,
+    pub address: u64,
+    pub size: u64,
+    pub flags: String,  // W, A and X as in readelf
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hardening {
+    pub nx: bool,
+    pub pie: bool,
+    pub relro: Relro,
+    pub stack_canary: bool,
+    pub fortify: bool,
+    pub fortified_functions: Vec<String>,  // The __*_chk variants it calls
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Relro {
+    None,
+    Partial,
+    Full,
+}
+
+// ============================================================================
+// Binary Store Models
+// ============================================================================
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StoredBinary {
+    pub sha256: String,
+    pub name: String,  // As uploaded; runs refer to the binary by hash
+    pub size: u64,
+    pub uploader: Option<String>,
+    pub tags: Vec<String>,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+// Query string of an upload, whose body is the binary itself
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreUploadParams {
+    pub name: String,
+    pub uploader: Option<String>,
+    pub tags: Option<String>,  // Comma-separated
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct StoreGcRequest {
+    pub min_age: Option<String>,  // Grace period for new uploads, defaults to 1h
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoreGcReport {
+    pub removed: Vec<StoredBinary>,
+    pub freed_bytes: u64,
+    pub remaining: usize,
+    pub dry_run: bool,
 }
 
 // ============================================================================
@@ -86,7 +873,9 @@ pub struct ResourceUsage {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FuzzStartRequest {
-    pub target_binary: String,
+    pub target_binary: Option<String>,  // No longer accepted, rejected with a pointer to target_sha256
+    #[serde(default)]
+    pub target_sha256: String,  // A StoredBinary
     pub corpus_dir: String,
     pub crash_dir: String,
     pub timeout: Option<String>,
@@ -98,6 +887,7 @@ pub struct FuzzCampaign {
     pub name: String,
     pub status: FuzzStatus,
     pub target_binary: String,
+    pub target_sha256: Option<String>,
     pub stats: FuzzStats,
     pub started_at: DateTime<Utc>,
     pub stopped_at: Option<DateTime<Utc>>,
@@ -142,6 +932,8 @@ pub struct LogEntry {
     pub source: LogSource,
     pub message: String,
     pub details: Option<serde_json::Value>,
+    #[serde(default)]
+    pub techniques: Vec<String>,  // ATT&CK technique IDs the event is evidence of
     pub timestamp: DateTime<Utc>,
 }
 
@@ -0,0 +1,123 @@
+use crate::models::{ArtifactKind, RunArtifact};
+use crate::services::get_data_dir;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+// Artifacts the backend produces itself live under this name, which outputs may not use
+pub const SYSTEM_ARTIFACT_DIR: &str = ".aegis";
+pub const PCAP_ARTIFACT: &str = ".aegis/network.pcap";
+pub const RECORDING_ARTIFACT: &str = ".aegis/session.cast";
+
+pub fn run_artifact_dir(run_id: Uuid) -> PathBuf {
+    get_data_dir().join("runs").join(run_id.to_string())
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Copies `source` into the run's artifact directory under `name`.
+pub fn store_run_artifact(run_id: Uuid, name: &str, source: &Path, kind: ArtifactKind) -> io::Result<RunArtifact> {
+    let destination = run_artifact_dir(run_id).join(name);
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let size = std::fs::copy(source, &destination)?;
+
+    Ok(RunArtifact {
+        name: name.to_string(),
+        kind,
+        size,
+        sha256: sha256_file(&destination)?,
+    })
+}
+
+/// Collects every file under the requested output paths, naming each artifact
+/// by its path relative to `root`. Paths that do not exist or lead out of `root`
+/// are skipped.
+pub fn collect_outputs(run_id: Uuid, root: &Path, output_paths: &[PathBuf]) -> Vec<RunArtifact> {
+    let Ok(root) = root.canonicalize() else {
+        return Vec::new();
+    };
+    let mut files = Vec::new();
+    for output_path in output_paths {
+        walk_files(&root, &root.join(output_path), &mut files);
+    }
+
+    files
+        .iter()
+        .filter_map(|file| {
+            let relative = file.strip_prefix(&root).ok()?;
+            if relative.starts_with(SYSTEM_ARTIFACT_DIR) {
+                return None;
+            }
+            let name = relative.to_string_lossy().to_string();
+            match store_run_artifact(run_id, &name, file, ArtifactKind::Output) {
+                Ok(artifact) => Some(artifact),
+                Err(e) => {
+                    tracing::warn!("Failed to collect artifact {} for run {}: {}", name, run_id, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// `root` is canonical. A sample can replace any directory along an output path with a
+// link to host files, so every path is resolved and has to stay under `root`; links
+// themselves are never collected.
+fn walk_files(root: &Path, path: &Path, files: &mut Vec<PathBuf>) {
+    if !path.canonicalize().is_ok_and(|resolved| resolved.starts_with(root)) {
+        return;
+    }
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return;
+    };
+
+    if metadata.is_file() {
+        files.push(path.to_path_buf());
+    } else if metadata.is_dir() {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            let mut children: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            children.sort();
+            for child in children {
+                walk_files(root, &child, files);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn does_not_follow_links_out_of_the_root() {
+        let dir = std::env::temp_dir().join(format!("aegis-artifacts-{}", Uuid::new_v4()));
+        let root = dir.join("workspace");
+        let host = dir.join("host");
+        std::fs::create_dir_all(root.join("out")).unwrap();
+        std::fs::create_dir_all(&host).unwrap();
+        std::fs::write(host.join("passwd"), "secret").unwrap();
+        std::fs::write(root.join("out/result"), "ok").unwrap();
+        symlink(&host, root.join("linked")).unwrap();
+        symlink(host.join("passwd"), root.join("out/passwd")).unwrap();
+
+        let root = root.canonicalize().unwrap();
+        let mut files = Vec::new();
+        // Through a linked intermediate directory, a linked directory and a linked file
+        walk_files(&root, &root.join("linked/passwd"), &mut files);
+        walk_files(&root, &root.join("linked"), &mut files);
+        walk_files(&root, &root.join("out"), &mut files);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, vec![root.join("out/result")]);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::models::{AttackMapping, AttackMappingRequest, AttackTechnique, BehaviourMatcher, SandboxRun, TechniqueMapping};
+use crate::services::{get_data_dir, verdict};
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub const ATTACK_VERSION: &str = "v15";
+
+/// Where an edited table is kept; it replaces the built-in one at startup.
+pub fn mapping_path() -> PathBuf {
+    get_data_dir().join("attack-mapping.json")
+}
+
+pub fn builtin_mapping() -> AttackMapping {
+    let technique = |id: &str, name: &str, tactics: &[&str], matcher: BehaviourMatcher| TechniqueMapping {
+        technique_id: id.to_string(),
+        name: name.to_string(),
+        tactics: tactics.iter().map(|s| s.to_string()).collect(),
+        matcher,
+    };
+    let syscalls = |names: &[&str], args_contain: &[&str], min_count: Option<u64>| BehaviourMatcher::Syscall {
+        syscalls: names.iter().map(|s| s.to_string()).collect(),
+        args_contain: args_contain.iter().map(|s| s.to_string()).collect(),
+        min_count,
+    };
+    let writes = |paths: &[&str]| BehaviourMatcher::FileWrite {
+        outside_workdir: false,
+        paths: paths.iter().map(|s| s.to_string()).collect(),
+        ignore: vec![],
+    };
+    let opens = |paths: &[&str]| syscalls(&["open", "openat", "openat2"], paths, None);
+
+    const PERSISTENCE: &[&str] = &["Persistence", "Privilege Escalation"];
+    const DISCOVERY: &[&str] = &["Discovery"];
+
+    let techniques = vec![
+        // Persistence
+        technique(
+            "T1053.003",
+            "Scheduled Task/Job: Cron",
+            &["Execution", "Persistence", "Privilege Escalation"],
+            writes(&["/etc/cron", "/etc/anacrontab", "/var/spool/cron"]),
+        ),
+        technique(
+            "T1543.002",
+            "Create or Modify System Process: Systemd Service",
+            PERSISTENCE,
+            writes(&["/etc/systemd/", "/lib/systemd/", "/usr/lib/systemd/"]),
+        ),
+        technique(
+            "T1037.004",
+            "Boot or Logon Initialization Scripts: RC Scripts",
+            PERSISTENCE,
+            writes(&["/etc/rc.local", "/etc/init.d/", "/etc/rc.d/"]),
+        ),
+        technique(
+            "T1546.004",
+            "Event Triggered Execution: Unix Shell Configuration Modification",
+            PERSISTENCE,
+            writes(&["/etc/profile", "/etc/bash.bashrc", "/etc/zsh/", "/root/.bashrc", "/root/.profile"]),
+        ),
+        technique(
+            "T1098.004",
+            "Account Manipulation: SSH Authorized Keys",
+            PERSISTENCE,
+            writes(&["/root/.ssh/", "/etc/ssh/"]),
+        ),
+        technique(
+            "T1136.001",
+            "Create Account: Local Account",
+            &["Persistence"],
+            writes(&["/etc/passwd", "/etc/shadow", "/etc/group"]),
+        ),
+        technique(
+            "T1574.006",
+            "Hijack Execution Flow: Dynamic Linker Hijacking",
+            &["Persistence", "Privilege Escalation", "Defense Evasion"],
+            writes(&["/etc/ld.so.preload", "/etc/ld.so.conf"]),
+        ),
+        technique(
+            "T1547.006",
+            "Boot or Logon Autostart Execution: Kernel Modules and Extensions",
+            PERSISTENCE,
+            syscalls(&["init_module", "finit_module"], &[], None),
+        ),
+        // Process injection and evasion
+        technique(
+            "T1055",
+            "Process Injection",
+            &["Defense Evasion", "Privilege Escalation"],
+            syscalls(&["ptrace"], &["PTRACE_ATTACH", "PTRACE_SEIZE", "PTRACE_POKETEXT", "PTRACE_POKEDATA", "PTRACE_SETREGS"], None),
+        ),
+        technique(
+            "T1055",
+            "Process Injection",
+            &["Defense Evasion", "Privilege Escalation"],
+            syscalls(&["process_vm_writev"], &[], None),
+        ),
+        technique(
+            "T1055.009",
+            "Process Injection: Proc Memory",
+            &["Defense Evasion", "Privilege Escalation"],
+            opens(&["/mem\""]),
+        ),
+        technique(
+            "T1622",
+            "Debugger Evasion",
+            &["Defense Evasion", "Discovery"],
+            syscalls(&["ptrace"], &["PTRACE_TRACEME"], None),
+        ),
+        technique("T1620", "Reflective Code Loading", &["Defense Evasion"], syscalls(&["memfd_create"], &[], None)),
+        technique(
+            "T1611",
+            "Escape to Host",
+            &["Privilege Escalation"],
+            syscalls(&["setns", "mount", "pivot_root"], &[], None),
+        ),
+        // Execution
+        technique(
+            "T1059.004",
+            "Command and Scripting Interpreter: Unix Shell",
+            &["Execution"],
+            BehaviourMatcher::Process {
+                executables: ["sh", "bash", "dash", "zsh", "ksh", "busybox"].iter().map(|s| s.to_string()).collect(),
+                min_count: None,
+            },
+        ),
+        // Discovery
+        technique("T1046", "Network Service Discovery", DISCOVERY, syscalls(&["connect"], &[], Some(20))),
+        technique(
+            "T1016",
+            "System Network Configuration Discovery",
+            DISCOVERY,
+            opens(&["/proc/net/", "/sys/class/net"]),
+        ),
+        technique(
+            "T1016",
+            "System Network Configuration Discovery",
+            DISCOVERY,
+            syscalls(&["ioctl"], &["SIOCGIFCONF", "SIOCGIFADDR", "SIOCGIFHWADDR"], None),
+        ),
+        technique("T1057", "Process Discovery", DISCOVERY, opens(&["\"/proc\""])),
+        technique(
+            "T1082",
+            "System Information Discovery",
+            DISCOVERY,
+            opens(&["/proc/version", "/etc/os-release", "/proc/cpuinfo"]),
+        ),
+        technique(
+            "T1040",
+            "Network Sniffing",
+            &["Credential Access", "Discovery"],
+            syscalls(&["socket"], &["AF_PACKET"], None),
+        ),
+        // Command and control
+        technique(
+            "T1071.001",
+            "Application Layer Protocol: Web Protocols",
+            &["Command and Control"],
+            BehaviourMatcher::Connection { include_loopback: false, ports: vec![80, 443, 8080, 8443] },
+        ),
+        technique(
+            "T1095",
+            "Non-Application Layer Protocol",
+            &["Command and Control"],
+            syscalls(&["socket"], &["SOCK_RAW"], None),
+        ),
+    ];
+
+    AttackMapping {
+        attack_version: ATTACK_VERSION.to_string(),
+        techniques,
+        updated_at: Utc::now(),
+    }
+}
+
+/// The saved table if there is one, otherwise the built-in table.
+pub fn load_mapping() -> AttackMapping {
+    let path = mapping_path();
+    let Ok(text) = std::fs::read_to_string(&path) else {
+        return builtin_mapping();
+    };
+
+    match serde_json::from_str(&text) {
+        Ok(mapping) => mapping,
+        Err(e) => {
+            tracing::warn!("Ignoring ATT&CK mapping {}: {}", path.display(), e);
+            builtin_mapping()
+        }
+    }
+}
+
+/// Checks and stores a replacement table, returning what it should be rejected for.
+pub fn save_mapping(req: AttackMappingRequest) -> Result<AttackMapping, String> {
+    if let Some(entry) = req.techniques.iter().find(|entry| !valid_technique_id(&entry.technique_id)) {
+        return Err(format!("Invalid ATT&CK technique ID '{}'", entry.technique_id));
+    }
+    if let Some(entry) = req.techniques.iter().find(|entry| entry.name.trim().is_empty()) {
+        return Err(format!("Technique {} needs a name", entry.technique_id));
+    }
+
+    let mapping = AttackMapping {
+        attack_version: req.attack_version,
+        techniques: req.techniques,
+        updated_at: Utc::now(),
+    };
+
+    let path = mapping_path();
+    let json = serde_json::to_string_pretty(&mapping).map_err(|e| e.to_string())?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    std::fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    Ok(mapping)
+}
+
+/// "T" and four digits, optionally followed by a three-digit sub-technique.
+fn valid_technique_id(id: &str) -> bool {
+    let (technique, sub) = match id.split_once('.') {
+        Some((technique, sub)) => (technique, Some(sub)),
+        None => (id, None),
+    };
+    let digits = |s: &str, n: usize| s.len() == n && s.bytes().all(|b| b.is_ascii_digit());
+
+    technique.strip_prefix('T').is_some_and(|number| digits(number, 4)) && sub.is_none_or(|sub| digits(sub, 3))
+}
+
+/// The techniques a finished run showed, by ID. Entries sharing an ID are merged.
+pub fn map_run(run: &SandboxRun, mapping: &AttackMapping) -> Vec<AttackTechnique> {
+    let mut observed: BTreeMap<&str, AttackTechnique> = BTreeMap::new();
+
+    for entry in &mapping.techniques {
+        let (count, evidence) = verdict::match_behaviour(run, &entry.matcher);
+        if count == 0 {
+            continue;
+        }
+
+        let technique = observed.entry(&entry.technique_id).or_insert_with(|| AttackTechnique {
+            technique_id: entry.technique_id.clone(),
+            name: entry.name.clone(),
+            tactics: vec![],
+            count: 0,
+            evidence: vec![],
+        });
+        technique.count += count;
+        for tactic in &entry.tactics {
+            if !technique.tactics.contains(tactic) {
+                technique.tactics.push(tactic.clone());
+            }
+        }
+        for item in evidence {
+            if technique.evidence.len() < verdict::MAX_EVIDENCE && !technique.evidence.contains(&item) {
+                technique.evidence.push(item);
+            }
+        }
+    }
+
+    observed.into_values().collect()
+}
+
+/// IDs of the run's techniques that some entry of the table with a matcher the
+/// event is about maps to, for tagging timeline events.
+pub fn tags(
+    techniques: &[AttackTechnique],
+    mapping: &AttackMapping,
+    relevant: impl Fn(&BehaviourMatcher) -> bool,
+) -> Vec<String> {
+    techniques
+        .iter()
+        .filter(|technique| {
+            mapping
+                .techniques
+                .iter()
+                .any(|entry| entry.technique_id == technique.technique_id && relevant(&entry.matcher))
+        })
+        .map(|technique| technique.technique_id.clone())
+        .collect()
+}
+
+/// All of the run's technique IDs.
+pub fn ids(techniques: &[AttackTechnique]) -> Vec<String> {
+    techniques.iter().map(|technique| technique.technique_id.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{SandboxRunRequest, SyscallEntry};
+    use crate::services::sandbox;
+
+    fn run_calling(calls: &[(&str, &str)]) -> SandboxRun {
+        let syscall_log = calls
+            .iter()
+            .map(|(syscall, args)| SyscallEntry {
+                syscall: syscall.to_string(),
+                args: args.to_string(),
+                result: "0".to_string(),
+                timestamp: Utc::now(),
+                allowed: true,
+                matched_rule: None,
+                pid: None,
+            })
+            .collect();
+        SandboxRun { syscall_log, ..sandbox::new_run(&SandboxRunRequest::default()) }
+    }
+
+    #[test]
+    fn entries_sharing_an_id_are_merged() {
+        let run = run_calling(&[
+            ("ptrace", "PTRACE_ATTACH, 42"),
+            ("process_vm_writev", "42, [{iov_base=0x1000, iov_len=8}], 1, [{iov_base=0x2000, iov_len=8}], 1, 0"),
+            ("ptrace", "PTRACE_TRACEME"),
+        ]);
+        let techniques = map_run(&run, &builtin_mapping());
+
+        assert_eq!(ids(&techniques), ["T1055", "T1622"]);
+        let injection = &techniques[0];
+        assert_eq!(injection.count, 2);
+        assert_eq!(injection.tactics, ["Defense Evasion", "Privilege Escalation"]);
+        assert_eq!(injection.evidence.len(), 2);
+    }
+
+    #[test]
+    fn matchers_look_at_arguments_and_counts() {
+        let mapping = builtin_mapping();
+        let run = run_calling(&[
+            ("openat", "AT_FDCWD, \"/etc/os-release\", O_RDONLY"),
+            ("openat", "AT_FDCWD, \"/proc/1/mem\", O_RDWR"),
+            ("openat", "AT_FDCWD, \"/proc/1/status\", O_RDONLY"),
+            ("socket", "AF_INET, SOCK_STREAM, IPPROTO_TCP"),
+            ("connect", "3, {sa_family=AF_INET, sin_port=htons(22), sin_addr=inet_addr(\"10.0.0.1\")}, 16"),
+        ]);
+        // One connect is not a port scan and a stream socket is not raw
+        assert_eq!(ids(&map_run(&run, &mapping)), ["T1055.009", "T1082"]);
+
+        let scan: Vec<(&str, &str)> = (0..20)
+            .map(|_| ("connect", "3, {sa_family=AF_INET, sin_port=htons(22), sin_addr=inet_addr(\"10.0.0.1\")}, 16"))
+            .collect();
+        assert!(ids(&map_run(&run_calling(&scan), &mapping)).contains(&"T1046".to_string()));
+    }
+
+    #[test]
+    fn tags_only_techniques_whose_matcher_is_relevant() {
+        let mapping = builtin_mapping();
+        let run = run_calling(&[("memfd_create", "\"payload\", MFD_CLOEXEC"), ("openat", "AT_FDCWD, \"/proc/version\", O_RDONLY")]);
+        let techniques = map_run(&run, &mapping);
+
+        let opens = |matcher: &BehaviourMatcher| {
+            matches!(matcher, BehaviourMatcher::Syscall { syscalls, .. } if syscalls.iter().any(|name| name == "openat"))
+        };
+        assert_eq!(tags(&techniques, &mapping, opens), ["T1082"]);
+    }
+
+    #[test]
+    fn validates_technique_ids() {
+        for id in ["T1055", "T1055.009"] {
+            assert!(valid_technique_id(id), "{id}");
+        }
+        for id in ["", "T105", "T10555", "t1055", "T1055.", "T1055.09", "T1055.0091", "TA0001"] {
+            assert!(!valid_technique_id(id), "{id}");
+        }
+    }
+}
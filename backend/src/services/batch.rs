@@ -0,0 +1,388 @@
+use crate::models::{
+    BatchCell, BatchOutcome, BatchRunRequest, BatchStatus, LogLevel, LogSource, RunStatus, SandboxBatch, SandboxRunRequest,
+};
+use crate::services::{logger, sandbox, store, AppState};
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use uuid::Uuid;
+
+// Keeps one request from monopolising the queue for hours
+pub const MAX_BATCH_CELLS: usize = 256;
+
+pub enum BatchError {
+    NoTarget,
+    TooLarge { cells: usize },
+    PolicyNotFound(String),
+    BinaryNotFound(String),
+    HostBinaryPath(String),
+}
+
+/// Expands the matrix into one run per combination, records the batch and starts
+/// scheduling it in the background. The returned batch has every cell `Pending`.
+pub fn start_batch(state: &AppState, req: BatchRunRequest) -> Result<SandboxBatch, BatchError> {
+    let requests = expand(&req)?;
+
+    {
+        let policies = state.syscall_policies.lock().unwrap();
+        if let Some(missing) = requests
+            .iter()
+            .filter_map(|(_, run)| run.syscall_policy.as_ref())
+            .find(|name| !policies.contains_key(*name))
+        {
+            return Err(BatchError::PolicyNotFound(missing.clone()));
+        }
+    }
+    if let Some(missing) = requests
+        .iter()
+        .filter_map(|(_, run)| run.binary_sha256.as_ref())
+        .find(|sha256| store::get_binary(sha256).is_none())
+    {
+        return Err(BatchError::BinaryNotFound(missing.clone()));
+    }
+    if let Some((_, run)) = requests.iter().find(|(_, run)| sandbox::check_binary_path(run).is_err()) {
+        return Err(BatchError::HostBinaryPath(run.binary_path.clone().unwrap_or_default()));
+    }
+
+    let batch = SandboxBatch {
+        id: Uuid::new_v4(),
+        name: req.name,
+        status: BatchStatus::Running,
+        cells: requests.iter().map(|(cell, _)| cell.clone()).collect(),
+        outcomes: vec![],
+        created_at: Utc::now(),
+        completed_at: None,
+    };
+    state.sandbox_batches.lock().unwrap().insert(batch.id, batch.clone());
+
+    logger::add_log(
+        &state.logs,
+        LogLevel::Info,
+        LogSource::Sandbox,
+        format!("Sandbox batch {} started with {} runs", batch.id, batch.cells.len()),
+        Some(json!({
+            "batch_id": batch.id,
+            "name": batch.name
+        })),
+    );
+
+    let state = state.clone();
+    let batch_id = batch.id;
+    tokio::spawn(async move {
+        run_batch(&state, batch_id, requests.into_iter().map(|(_, run)| run).collect()).await;
+    });
+
+    Ok(batch)
+}
+
+// What a cell runs, and the label it is shown under
+struct Target {
+    label: String,
+    binary_path: Option<String>,
+    binary_sha256: Option<String>,
+    code: Option<String>,
+}
+
+impl Target {
+    fn path(binary: &str) -> Self {
+        Target { label: binary.to_string(), binary_path: Some(binary.to_string()), binary_sha256: None, code: None }
+    }
+
+    fn stored(sha256: &str) -> Self {
+        let sha256 = sha256.to_lowercase();
+        Target { label: format!("sha256:{}", sha256), binary_path: None, binary_sha256: Some(sha256), code: None }
+    }
+
+    fn code(label: String, code: &str) -> Self {
+        Target { label, binary_path: None, binary_sha256: None, code: Some(code.to_string()) }
+    }
+}
+
+/// One request per combination of the matrix axes, targets varying slowest. The
+/// number of combinations is checked before any of them is built.
+fn expand(req: &BatchRunRequest) -> Result<Vec<(BatchCell, SandboxRunRequest)>, BatchError> {
+    let matrix = &req.matrix;
+    let base = &req.base;
+
+    let mut targets: Vec<Target> = matrix
+        .binaries
+        .iter()
+        .map(|binary| Target::path(binary))
+        .chain(matrix.stored_binaries.iter().map(|sha256| Target::stored(sha256)))
+        .chain(
+            matrix
+                .code
+                .iter()
+                .enumerate()
+                .map(|(i, code)| Target::code(format!("code #{}", i + 1), code)),
+        )
+        .collect();
+    if targets.is_empty() {
+        match (&base.binary_path, &base.binary_sha256, &base.code) {
+            (_, _, Some(code)) => targets.push(Target::code("code".to_string(), code)),
+            (_, Some(sha256), None) => targets.push(Target::stored(sha256)),
+            (Some(binary), None, None) => targets.push(Target::path(binary)),
+            (None, None, None) => return Err(BatchError::NoTarget),
+        }
+    }
+
+    let cells = [
+        targets.len(),
+        matrix.memory_limits.len(),
+        matrix.timeouts.len(),
+        matrix.network_modes.len(),
+        matrix.syscall_policies.len(),
+    ]
+    .into_iter()
+    .try_fold(1usize, |cells, axis| cells.checked_mul(axis.max(1)))
+    .unwrap_or(usize::MAX);
+    if cells > MAX_BATCH_CELLS {
+        return Err(BatchError::TooLarge { cells });
+    }
+
+    let axis = |values: &[String], default: &Option<String>| -> Vec<Option<String>> {
+        if values.is_empty() { vec![default.clone()] } else { values.iter().cloned().map(Some).collect() }
+    };
+    let memory_limits = axis(&matrix.memory_limits, &base.memory_limit);
+    let timeouts = axis(&matrix.timeouts, &base.timeout);
+    let network_modes = if matrix.network_modes.is_empty() {
+        vec![base.network_mode.clone()]
+    } else {
+        matrix.network_modes.iter().cloned().map(Some).collect()
+    };
+    let policies = if matrix.syscall_policies.is_empty() {
+        vec![base.syscall_policy.clone()]
+    } else {
+        matrix.syscall_policies.clone()
+    };
+
+    let mut requests = Vec::new();
+    for target in &targets {
+        for memory_limit in &memory_limits {
+            for timeout in &timeouts {
+                for network_mode in &network_modes {
+                    for policy in &policies {
+                        let run = SandboxRunRequest {
+                            binary_path: target.binary_path.clone(),
+                            binary_sha256: target.binary_sha256.clone(),
+                            code: target.code.clone(),
+                            memory_limit: memory_limit.clone(),
+                            timeout: timeout.clone(),
+                            network_mode: network_mode.clone(),
+                            syscall_policy: policy.clone(),
+                            rerun_of: None,
+                            ..base.clone()
+                        };
+                        let cell = BatchCell {
+                            target: target.label.clone(),
+                            memory_limit: memory_limit.clone(),
+                            timeout: timeout.clone(),
+                            network_mode: network_mode.clone(),
+                            syscall_policy: policy.clone(),
+                            status: RunStatus::Pending,
+                            run_id: None,
+                            error: None,
+                            exit_code: None,
+                            signal: None,
+                            limits_hit: vec![],
+                            execution_time_ms: None,
+                            memory_mb: None,
+                            syscalls_count: None,
+                            policy_violations: 0,
+                            network_requests: 0,
+                            outcome: None,
+                        };
+                        requests.push((cell, run));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(requests)
+}
+
+/// Feeds the cells through the sandbox queue, never holding more of them in it
+/// than the queue can run at once so other users' runs still get in.
+async fn run_batch(state: &AppState, batch_id: Uuid, requests: Vec<SandboxRunRequest>) {
+    let slots = Arc::new(Semaphore::new(state.sandbox_queue.config().max_concurrent));
+    let mut tasks = JoinSet::new();
+
+    for (index, req) in requests.into_iter().enumerate() {
+        let state = state.clone();
+        let slots = slots.clone();
+        tasks.spawn(async move {
+            let _slot = slots.acquire_owned().await;
+            let queued = |run_id| update_cell(&state, batch_id, index, |cell| cell.run_id = Some(run_id));
+            let result = sandbox::submit_run_queued(&state, req, queued).await;
+            (index, result)
+        });
+    }
+
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((index, result)) = joined else {
+            continue;
+        };
+
+        update_cell(state, batch_id, index, |cell| match result {
+            Ok(run) => {
+                cell.status = run.status;
+                cell.run_id = Some(run.id);
+                cell.exit_code = run.exit_code;
+                cell.signal = run.signal;
+                cell.limits_hit = run.limits_hit;
+                cell.execution_time_ms = Some(run.resource_usage.execution_time_ms);
+                cell.memory_mb = run.resource_usage.memory_mb;
+                cell.syscalls_count = Some(run.resource_usage.syscalls_count);
+                cell.policy_violations = run.policy_violations.len();
+                cell.network_requests = run.network_activity.len();
+            }
+            Err(e) => {
+                cell.status = RunStatus::Failed;
+                cell.error = Some(e.to_string());
+            }
+        });
+    }
+
+    let batch = {
+        let mut batches = state.sandbox_batches.lock().unwrap();
+        let Some(batch) = batches.get_mut(&batch_id) else {
+            return;
+        };
+        batch.status = BatchStatus::Completed;
+        batch.completed_at = Some(Utc::now());
+        batch.clone()
+    };
+
+    logger::add_log(
+        &state.logs,
+        LogLevel::Info,
+        LogSource::Sandbox,
+        format!(
+            "Sandbox batch {} completed {} runs with {} distinct outcomes",
+            batch_id,
+            batch.cells.len(),
+            batch.outcomes.len()
+        ),
+        Some(json!({
+            "batch_id": batch_id,
+            "outcomes": batch.outcomes
+        })),
+    );
+}
+
+/// The batch with each cell that is still in the queue or running showing its run's
+/// current status.
+pub fn current(state: &AppState, mut batch: SandboxBatch) -> SandboxBatch {
+    let runs = state.sandbox_runs.lock().unwrap();
+    for cell in batch.cells.iter_mut().filter(|cell| cell.status == RunStatus::Pending) {
+        if let Some(run) = cell.run_id.and_then(|run_id| runs.get(&run_id)) {
+            if run.status == RunStatus::Running {
+                cell.status = RunStatus::Running;
+            }
+        }
+    }
+    batch
+}
+
+fn update_cell(state: &AppState, batch_id: Uuid, index: usize, update: impl FnOnce(&mut BatchCell)) {
+    let mut batches = state.sandbox_batches.lock().unwrap();
+    let Some(batch) = batches.get_mut(&batch_id) else {
+        return;
+    };
+    if let Some(cell) = batch.cells.get_mut(index) {
+        update(cell);
+    }
+    group_outcomes(batch);
+}
+
+/// Groups finished cells by status, exit code, signal and limits hit, so the
+/// point in a sweep where a sample starts behaving differently stands out.
+fn group_outcomes(batch: &mut SandboxBatch) {
+    let mut outcomes: Vec<BatchOutcome> = Vec::new();
+
+    for cell in &mut batch.cells {
+        if matches!(cell.status, RunStatus::Pending | RunStatus::Running) {
+            cell.outcome = None;
+            continue;
+        }
+
+        let same = |seen: &BatchOutcome| {
+            seen.status == cell.status
+                && seen.exit_code == cell.exit_code
+                && seen.signal == cell.signal
+                && seen.limits_hit == cell.limits_hit
+        };
+        let index = match outcomes.iter().position(same) {
+            Some(index) => index,
+            None => {
+                outcomes.push(BatchOutcome {
+                    status: cell.status.clone(),
+                    exit_code: cell.exit_code,
+                    signal: cell.signal,
+                    limits_hit: cell.limits_hit.clone(),
+                    cells: 0,
+                });
+                outcomes.len() - 1
+            }
+        };
+        outcomes[index].cells += 1;
+        cell.outcome = Some(index);
+    }
+
+    batch.outcomes = outcomes;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{BatchMatrix, NetworkMode};
+
+    fn request(matrix: BatchMatrix) -> BatchRunRequest {
+        BatchRunRequest {
+            name: None,
+            base: SandboxRunRequest { binary_sha256: Some("ab".repeat(32)), ..Default::default() },
+            matrix,
+        }
+    }
+
+    #[test]
+    fn expands_every_combination_with_targets_varying_slowest() {
+        let requests = expand(&request(BatchMatrix {
+            code: vec!["a".to_string(), "b".to_string()],
+            memory_limits: vec!["64m".to_string(), "128m".to_string(), "256m".to_string()],
+            network_modes: vec![NetworkMode::None, NetworkMode::Loopback],
+            ..Default::default()
+        }))
+        .unwrap_or_else(|_| panic!("expand failed"));
+
+        assert_eq!(requests.len(), 12);
+        assert!(requests[..6].iter().all(|(cell, _)| cell.target == "code #1"));
+        assert_eq!(requests[0].1.memory_limit.as_deref(), Some("64m"));
+        assert_eq!(requests[1].1.network_mode, Some(NetworkMode::Loopback));
+        // The base target is replaced by the matrix's
+        assert!(requests.iter().all(|(_, run)| run.binary_sha256.is_none() && run.code.is_some()));
+    }
+
+    #[test]
+    fn refuses_oversized_matrices_before_expanding() {
+        let values: Vec<String> = (0..1000).map(|i| i.to_string()).collect();
+        let result = expand(&request(BatchMatrix {
+            memory_limits: values.clone(),
+            timeouts: values,
+            ..Default::default()
+        }));
+        assert!(matches!(result, Err(BatchError::TooLarge { cells: 1_000_000 })));
+
+        let result = expand(&request(BatchMatrix { memory_limits: vec!["64m".to_string(); 256], ..Default::default() }));
+        assert_eq!(result.map(|requests| requests.len()).ok(), Some(256));
+    }
+
+    #[test]
+    fn needs_a_target() {
+        let req = BatchRunRequest { name: None, base: SandboxRunRequest::default(), matrix: BatchMatrix::default() };
+        assert!(matches!(expand(&req), Err(BatchError::NoTarget)));
+    }
+}
@@ -0,0 +1,370 @@
+use crate::models::{
+    Aligned, ArtifactKind, DiffOp, FieldDiff, FileChange, NetworkFlow, ReproductionReport, RunComparison, RunDiff,
+    RunDifference, SandboxRun, SyscallDivergence, SyscallEntry,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+// Above this many table cells sequences are paired by position instead of aligned
+const MAX_ALIGNMENT_CELLS: usize = 4_000_000;
+
+/// Checks whether `rerun` reproduced `original`: same stdout, same exit status
+/// (exit code and signal) and the same sequence of syscalls.
+pub fn reproduction(original: &SandboxRun, rerun: &SandboxRun) -> ReproductionReport {
+    let stdout_matches = original.stdout == rerun.stdout;
+    let exit_status_matches = original.exit_code == rerun.exit_code && original.signal == rerun.signal;
+
+    // Arguments carry pointers and fds, so the sequence is compared by name
+    let first_syscall_divergence = first_divergence(original, rerun);
+    let syscalls_match = first_syscall_divergence.is_none() && original.syscall_counts == rerun.syscall_counts;
+
+    ReproductionReport {
+        original_run: original.id,
+        reproduced: stdout_matches && exit_status_matches && syscalls_match,
+        stdout_matches,
+        exit_status_matches,
+        syscalls_match,
+        settings_match: original.deterministic == rerun.deterministic,
+        first_syscall_divergence,
+    }
+}
+
+fn first_divergence(original: &SandboxRun, rerun: &SandboxRun) -> Option<SyscallDivergence> {
+    let len = original.syscall_log.len().max(rerun.syscall_log.len());
+
+    (0..len).find_map(|index| {
+        let before = original.syscall_log.get(index).map(|entry| &entry.syscall);
+        let after = rerun.syscall_log.get(index).map(|entry| &entry.syscall);
+        (before != after).then(|| SyscallDivergence {
+            index,
+            original: before.cloned(),
+            rerun: after.cloned(),
+        })
+    })
+}
+
+/// Field-by-field comparison of a replay with its original. Timestamps, pids and
+/// other values that differ on every run are left out.
+pub fn compare_runs(original: &SandboxRun, replay: &SandboxRun) -> RunComparison {
+    let mut setup_differences = Vec::new();
+    difference(&mut setup_differences, "toolchain", &original.toolchain, &replay.toolchain);
+    difference(&mut setup_differences, "policy", &original.policy_snapshot, &replay.policy_snapshot);
+    difference(&mut setup_differences, "rootfs", &original.rootfs_image, &replay.rootfs_image);
+    difference(&mut setup_differences, "deterministic", &original.deterministic, &replay.deterministic);
+
+    let mut outcome_differences = Vec::new();
+    let outcome = &mut outcome_differences;
+    difference(outcome, "status", &original.status, &replay.status);
+    difference(outcome, "exit_code", &original.exit_code, &replay.exit_code);
+    difference(outcome, "signal", &original.signal, &replay.signal);
+    difference(outcome, "stdout", &original.stdout, &replay.stdout);
+    difference(outcome, "stderr", &original.stderr, &replay.stderr);
+    difference(outcome, "limits_hit", &original.limits_hit, &replay.limits_hit);
+    difference(outcome, "syscall_counts", &original.syscall_counts, &replay.syscall_counts);
+    difference(outcome, "policy_violations", &original.policy_violations, &replay.policy_violations);
+    difference(outcome, "network_activity", &network_summary(original), &network_summary(replay));
+    difference(outcome, "filesystem_changes", &original.filesystem_changes, &replay.filesystem_changes);
+    difference(outcome, "artifacts", &output_hashes(original), &output_hashes(replay));
+
+    RunComparison {
+        original_run: original.id,
+        replay_run: replay.id,
+        identical: outcome_differences.is_empty(),
+        setup_differences,
+        outcome_differences,
+    }
+}
+
+fn difference<T: Serialize>(differences: &mut Vec<RunDifference>, field: &str, original: &T, replay: &T) {
+    let (original, replay) = (json!(original), json!(replay));
+    if original != replay {
+        differences.push(RunDifference {
+            field: field.to_string(),
+            original,
+            replay,
+        });
+    }
+}
+
+fn network_summary(run: &SandboxRun) -> Vec<serde_json::Value> {
+    run.network_activity
+        .iter()
+        .map(|event| json!([event.protocol, event.destination, event.host, event.request, event.payload]))
+        .collect()
+}
+
+// Packet captures and core dumps embed timestamps and pids, so only outputs are compared
+fn output_hashes(run: &SandboxRun) -> BTreeMap<&str, &str> {
+    run.artifacts
+        .iter()
+        .filter(|artifact| artifact.kind == ArtifactKind::Output)
+        .map(|artifact| (artifact.name.as_str(), artifact.sha256.as_str()))
+        .collect()
+}
+
+// ============================================================================
+// Side-by-side diff
+// ============================================================================
+
+/// Diffs two arbitrary runs. Syscalls are aligned by name, so an extra or missing
+/// call shows up as one insertion instead of shifting everything after it.
+pub fn diff_runs(left: &SandboxRun, right: &SandboxRun, left_flows: &[NetworkFlow], right_flows: &[NetworkFlow]) -> RunDiff {
+    let exit_status = vec![
+        field_diff("status", &left.status, &right.status),
+        field_diff("exit_code", &left.exit_code, &right.exit_code),
+        field_diff("signal", &left.signal, &right.signal),
+        field_diff("limits_hit", &left.limits_hit, &right.limits_hit),
+    ];
+
+    let (l, r) = (&left.resource_usage, &right.resource_usage);
+    let resource_usage = vec![
+        field_diff("memory_mb", &l.memory_mb, &r.memory_mb),
+        field_diff("cpu_percent", &l.cpu_percent, &r.cpu_percent),
+        field_diff("execution_time_ms", &l.execution_time_ms, &r.execution_time_ms),
+        field_diff("syscalls_count", &l.syscalls_count, &r.syscalls_count),
+    ];
+
+    let (syscalls, syscalls_aligned) = align_by(&left.syscall_log, &right.syscall_log, |entry| &entry.syscall, same_call);
+    let first_divergence = syscalls.iter().position(|aligned| aligned.op != DiffOp::Equal);
+
+    RunDiff {
+        left_run: left.id,
+        right_run: right.id,
+        exit_status,
+        resource_usage,
+        stdout: diff_lines(&left.stdout, &right.stdout),
+        stderr: diff_lines(&left.stderr, &right.stderr),
+        syscalls,
+        syscalls_aligned,
+        first_divergence,
+        filesystem_changes: match_by_key(&left.filesystem_changes, &right.filesystem_changes, |change| change.path.clone(), same_change),
+        network_flows: match_by_key(left_flows, right_flows, flow_key, same_flow),
+    }
+}
+
+fn field_diff<T: Serialize>(field: &str, left: &T, right: &T) -> FieldDiff {
+    let (left, right) = (json!(left), json!(right));
+    FieldDiff {
+        field: field.to_string(),
+        changed: left != right,
+        left,
+        right,
+    }
+}
+
+fn diff_lines(left: &str, right: &str) -> Vec<Aligned<String>> {
+    let left: Vec<String> = left.lines().map(str::to_string).collect();
+    let right: Vec<String> = right.lines().map(str::to_string).collect();
+    align_by(&left, &right, |line| line, |a, b| a == b).0
+}
+
+// Pointers differ between runs even when the behaviour does not
+fn same_call(left: &SyscallEntry, right: &SyscallEntry) -> bool {
+    without_pointers(&left.args) == without_pointers(&right.args)
+        && without_pointers(&left.result) == without_pointers(&right.result)
+        && left.allowed == right.allowed
+}
+
+/// Replaces every hex literal such as `0x7ffd3a2c` with `0x_`. Small constants are
+/// traced in decimal or symbolically, so hex values are almost always addresses.
+fn without_pointers(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut normalised = String::with_capacity(text.len());
+    let mut start = 0;
+    let mut i = 0;
+
+    while i + 2 < bytes.len() {
+        let at_boundary = i == 0 || !bytes[i - 1].is_ascii_alphanumeric();
+        if at_boundary && bytes[i] == b'0' && bytes[i + 1] == b'x' && bytes[i + 2].is_ascii_hexdigit() {
+            let end = (i + 2..bytes.len()).find(|&k| !bytes[k].is_ascii_hexdigit()).unwrap_or(bytes.len());
+            normalised.push_str(&text[start..i]);
+            normalised.push_str("0x_");
+            start = end;
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+
+    normalised.push_str(&text[start..]);
+    normalised
+}
+
+fn same_change(left: &FileChange, right: &FileChange) -> bool {
+    json!(left) == json!(right)
+}
+
+// Source ports are ephemeral, so flows are matched on where they went
+fn flow_key(flow: &NetworkFlow) -> (String, String, Option<u16>) {
+    (flow.protocol.clone(), flow.destination.clone(), flow.destination_port)
+}
+
+fn same_flow(left: &NetworkFlow, right: &NetworkFlow) -> bool {
+    left.packets == right.packets
+        && left.bytes_sent == right.bytes_sent
+        && left.bytes_received == right.bytes_received
+        && left.first_payload == right.first_payload
+}
+
+/// Aligns two sequences on the longest common subsequence of their keys; paired
+/// items whose details differ are `Changed`. Returns false as the second value
+/// when the sequences were too long to align and were paired by position.
+fn align_by<T: Clone, K: PartialEq + ?Sized>(
+    left: &[T],
+    right: &[T],
+    key: impl Fn(&T) -> &K,
+    same: impl Fn(&T, &T) -> bool,
+) -> (Vec<Aligned<T>>, bool) {
+    let prefix = left.iter().zip(right).take_while(|(a, b)| key(a) == key(b)).count();
+    let suffix = left[prefix..]
+        .iter()
+        .rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(a, b)| key(a) == key(b))
+        .count();
+    let (a, b) = (&left[prefix..left.len() - suffix], &right[prefix..right.len() - suffix]);
+
+    let mut pairs: Vec<(Option<usize>, Option<usize>)> = (0..prefix).map(|i| (Some(i), Some(i))).collect();
+    let aligned = (a.len() + 1) * (b.len() + 1) <= MAX_ALIGNMENT_CELLS;
+
+    if aligned {
+        // lcs[i][j] is the LCS length of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u16; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if key(&a[i]) == key(&b[j]) {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && key(&a[i]) == key(&b[j]) {
+                pairs.push((Some(prefix + i), Some(prefix + j)));
+                i += 1;
+                j += 1;
+            } else if j == b.len() || (i < a.len() && lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                pairs.push((Some(prefix + i), None));
+                i += 1;
+            } else {
+                pairs.push((None, Some(prefix + j)));
+                j += 1;
+            }
+        }
+    } else {
+        for index in 0..a.len().max(b.len()) {
+            pairs.push(((index < a.len()).then_some(prefix + index), (index < b.len()).then_some(prefix + index)));
+        }
+    }
+
+    pairs.extend((0..suffix).map(|k| (Some(left.len() - suffix + k), Some(right.len() - suffix + k))));
+
+    let result = pairs
+        .into_iter()
+        .map(|(l, r)| {
+            let (l, r) = (l.map(|i| &left[i]), r.map(|j| &right[j]));
+            let op = match (l, r) {
+                (Some(l), Some(r)) if key(l) == key(r) && same(l, r) => DiffOp::Equal,
+                (Some(_), Some(_)) => DiffOp::Changed,
+                (Some(_), None) => DiffOp::Removed,
+                _ => DiffOp::Added,
+            };
+            Aligned { op, left: l.cloned(), right: r.cloned() }
+        })
+        .collect();
+
+    (result, aligned)
+}
+
+/// Pairs items with the same key, in order of first appearance; repeated keys pair up by occurrence.
+fn match_by_key<T: Clone, K: Ord + Clone>(
+    left: &[T],
+    right: &[T],
+    key: impl Fn(&T) -> K,
+    same: impl Fn(&T, &T) -> bool,
+) -> Vec<Aligned<T>> {
+    let mut groups: BTreeMap<K, (Vec<&T>, Vec<&T>)> = BTreeMap::new();
+    for item in left {
+        groups.entry(key(item)).or_default().0.push(item);
+    }
+    for item in right {
+        groups.entry(key(item)).or_default().1.push(item);
+    }
+
+    groups
+        .into_values()
+        .flat_map(|(l, r)| {
+            (0..l.len().max(r.len()))
+                .map(|i| {
+                    let (l, r) = (l.get(i).copied(), r.get(i).copied());
+                    let op = match (l, r) {
+                        (Some(l), Some(r)) if same(l, r) => DiffOp::Equal,
+                        (Some(_), Some(_)) => DiffOp::Changed,
+                        (Some(_), None) => DiffOp::Removed,
+                        _ => DiffOp::Added,
+                    };
+                    Aligned { op, left: l.cloned(), right: r.cloned() }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn call(syscall: &str, args: &str, result: &str) -> SyscallEntry {
+        SyscallEntry {
+            syscall: syscall.to_string(),
+            args: args.to_string(),
+            result: result.to_string(),
+            timestamp: Utc::now(),
+            allowed: true,
+            matched_rule: None,
+            pid: Some(100),
+        }
+    }
+
+    #[test]
+    fn ignores_pointers_when_aligning_syscalls() {
+        let left = vec![
+            call("brk", "NULL", "0x5581a000"),
+            call("mmap", "NULL, 8192, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0", "0x7f1c2000"),
+            call("read", "3, \"abc\", 16", "3"),
+            call("write", "1, \"0xff\\n\", 5", "5"),
+            call("exit_group", "0", "?"),
+        ];
+        let right = vec![
+            call("brk", "NULL", "0x55d3b000"),
+            call("mmap", "NULL, 8192, PROT_READ|PROT_WRITE, MAP_PRIVATE|MAP_ANONYMOUS, -1, 0", "0x7fa94000"),
+            call("getpid", "", "101"),
+            call("read", "3, \"abd\", 16", "3"),
+            call("write", "1, \"0xff\\n\", 5", "5"),
+            call("exit_group", "0", "?"),
+        ];
+
+        let (aligned, complete) = align_by(&left, &right, |entry| &entry.syscall, same_call);
+        assert!(complete);
+
+        let ops: Vec<DiffOp> = aligned.iter().map(|pair| pair.op.clone()).collect();
+        assert_eq!(
+            ops,
+            vec![DiffOp::Equal, DiffOp::Equal, DiffOp::Added, DiffOp::Changed, DiffOp::Equal, DiffOp::Equal]
+        );
+        assert_eq!(aligned[2].right.as_ref().unwrap().syscall, "getpid");
+    }
+
+    #[test]
+    fn normalises_only_whole_hex_literals() {
+        assert_eq!(without_pointers("0x7ffd3a2c, 0x10, 0"), "0x_, 0x_, 0");
+        assert_eq!(without_pointers("{sa_handler=0x55a1b2, sa_mask=[]}"), "{sa_handler=0x_, sa_mask=[]}");
+        assert_eq!(without_pointers("x0x1f, 0xg, 0x"), "x0x1f, 0xg, 0x");
+    }
+}
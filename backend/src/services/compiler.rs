@@ -62,7 +62,7 @@ pub async fn compile_code(req: CompileRequest) -> CompileResponse {
     }
 }
 
-fn parse_ast_from_output(output: &str) -> Option<serde_json::Value> {
+fn parse_ast_from_output(_output: &str) -> Option<serde_json::Value> {
     // For now, return a placeholder AST structure
     // In a real implementation, we'd parse the actual AST from the compiler output
     Some(serde_json::json!({
@@ -0,0 +1,142 @@
+use crate::models::{ArtifactKind, ProcessNode, RunArtifact, StackFrame};
+use crate::services::artifacts::{store_run_artifact, SYSTEM_ARTIFACT_DIR};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+use uuid::Uuid;
+
+const GDB_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+pub struct CoreDumps {
+    pub artifacts: Vec<RunArtifact>,
+    pub backtrace: Vec<StackFrame>,
+}
+
+/// Stores every core file `aegis-sandbox` wrote to `core_dir` (named `core.<pid>`)
+/// and backtraces the one belonging to the process the trace saw dump core.
+/// `host_path` maps the executable's path as the target saw it to the host.
+pub async fn collect(
+    run_id: Uuid,
+    core_dir: &Path,
+    processes: &[ProcessNode],
+    fallback_executable: Option<&str>,
+    host_path: impl Fn(&str) -> Option<PathBuf>,
+) -> CoreDumps {
+    let mut cores: Vec<PathBuf> = match std::fs::read_dir(core_dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()).collect(),
+        Err(_) => return CoreDumps::default(),
+    };
+    cores.sort();
+
+    let artifacts = cores
+        .iter()
+        .filter_map(|core| {
+            let name = format!("{}/cores/{}", SYSTEM_ARTIFACT_DIR, core.file_name()?.to_string_lossy());
+            match store_run_artifact(run_id, &name, core, ArtifactKind::CoreDump) {
+                Ok(artifact) => Some(artifact),
+                Err(e) => {
+                    tracing::warn!("Failed to store core dump {} for run {}: {}", name, run_id, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let process_of = |core: &PathBuf| {
+        let pid: u32 = core.extension()?.to_str()?.parse().ok()?;
+        processes.iter().find(|process| process.pid == pid)
+    };
+    let crashed = cores
+        .iter()
+        .find(|core| process_of(core).is_some_and(|process| process.core_dumped))
+        .or(cores.first());
+
+    let Some(core) = crashed else {
+        return CoreDumps { artifacts, backtrace: vec![] };
+    };
+
+    let executable = process_of(core)
+        .and_then(|process| process.executable.as_deref())
+        .or(fallback_executable)
+        .and_then(host_path);
+
+    let backtrace = match executable {
+        Some(executable) => backtrace(&executable, core).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to backtrace core dump for run {}: {}", run_id, e);
+            vec![]
+        }),
+        None => vec![],
+    };
+
+    CoreDumps { artifacts, backtrace }
+}
+
+/// Asks gdb for the crashing thread's stack. gdb reads the executable's DWARF
+/// (or its separate debug file) when there is one, and falls back to ELF symbols.
+/// gdb runs on the host over files the sample controls, so it loads no scripts they
+/// name and fetches nothing from debuginfod.
+pub async fn backtrace(executable: &Path, core: &Path) -> Result<Vec<StackFrame>, String> {
+    let output = Command::new("gdb")
+        .args(["--batch", "--nx", "-iex", "set auto-load off", "-iex", "set debuginfod enabled off"])
+        .args(["-ex", "set pagination off", "-ex", "bt"])
+        .arg(executable)
+        .arg(core)
+        .kill_on_drop(true)
+        .output();
+
+    let output = tokio::time::timeout(GDB_TIMEOUT, output)
+        .await
+        .map_err(|_| "gdb timed out".to_string())?
+        .map_err(|e| format!("Failed to run gdb: {}", e))?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).lines().filter_map(parse_frame).collect())
+}
+
+/// Parses one line of gdb's `bt` output, e.g.
+/// `#1  0x00005555555551a9 in parse (buf=0x0) at src/parse.c:42` or
+/// `#3  0x00007ffff7c29d90 in __libc_start_call_main () from /lib/x86_64-linux-gnu/libc.so.6`.
+fn parse_frame(line: &str) -> Option<StackFrame> {
+    let rest = line.strip_prefix('#')?;
+    let (index, rest) = rest.split_once(char::is_whitespace)?;
+    let index: u32 = index.parse().ok()?;
+    let rest = rest.trim_start();
+
+    // The innermost frame omits the address when the pc is at the start of a line
+    let (address, rest) = match rest.strip_prefix("0x") {
+        Some(_) => {
+            let (address, rest) = rest.split_once(" in ")?;
+            (Some(address.to_string()), rest)
+        }
+        None => (None, rest),
+    };
+
+    let function = rest
+        .split(" (")
+        .next()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && *name != "??")
+        .map(str::to_string);
+
+    let (mut file, mut line_number, mut module) = (None, None, None);
+    if let Some((_, location)) = rest.rsplit_once(" at ") {
+        match location.rsplit_once(':') {
+            Some((path, number)) if number.parse::<u32>().is_ok() => {
+                file = Some(path.to_string());
+                line_number = number.parse().ok();
+            }
+            _ => file = Some(location.to_string()),
+        }
+    } else if let Some((_, library)) = rest.rsplit_once(" from ") {
+        module = Some(library.to_string());
+    }
+
+    Some(StackFrame {
+        index,
+        address,
+        function,
+        file,
+        line: line_number,
+        module,
+    })
+}
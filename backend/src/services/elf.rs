@@ -0,0 +1,194 @@
+use crate::models::{BinaryInspection, ElfFileType, ElfSection, Hardening, Relro};
+use chrono::Utc;
+use goblin::elf::dynamic::{DF_1_NOW, DF_1_PIE, DF_BIND_NOW, DT_BIND_NOW};
+use goblin::elf::header::{self, ET_CORE, ET_DYN, ET_EXEC, ET_REL};
+use goblin::elf::program_header::{PF_X, PT_GNU_RELRO, PT_GNU_STACK};
+use goblin::elf::section_header::{self, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHN_UNDEF};
+use goblin::elf::sym::{STB_GLOBAL, STB_WEAK, STV_DEFAULT};
+use goblin::elf::Elf;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+// Larger files are not read into memory for inspection
+pub const MAX_INSPECT_SIZE: u64 = 512 << 20;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const CANARY_SYMBOLS: &[&str] = &["__stack_chk_fail", "__stack_chk_guard", "__intel_security_cookie"];
+
+pub fn is_elf(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    std::fs::File::open(path)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+        .is_ok_and(|_| magic == ELF_MAGIC)
+}
+
+/// Parses an ELF file and reports its layout, dependencies, symbols and the
+/// hardening it was built with, the way `readelf` and `checksec` would.
+pub fn inspect_file(path: &Path) -> Result<BinaryInspection, String> {
+    let size = std::fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?.len();
+    if size > MAX_INSPECT_SIZE {
+        return Err(format!("{} is too large to inspect", path.display()));
+    }
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if !bytes.starts_with(ELF_MAGIC) {
+        return Err(format!("{} is not an ELF binary", path.display()));
+    }
+    let elf = Elf::parse(&bytes).map_err(|e| format!("Invalid ELF binary: {}", e))?;
+
+    let (flags, flags_1) = elf
+        .dynamic
+        .as_ref()
+        .map(|dynamic| (dynamic.info.flags, dynamic.info.flags_1))
+        .unwrap_or_default();
+    let is_pie = elf.header.e_type == ET_DYN && (elf.interpreter.is_some() || flags_1 & DF_1_PIE != 0);
+    let has_segment = |kind: u32| elf.program_headers.iter().any(|ph| ph.p_type == kind);
+    let bind_now_tag = elf
+        .dynamic
+        .as_ref()
+        .is_some_and(|dynamic| dynamic.dyns.iter().any(|entry| entry.d_tag == DT_BIND_NOW));
+
+    let sections = elf
+        .section_headers
+        .iter()
+        .filter(|sh| sh.sh_type != section_header::SHT_NULL)
+        .map(|sh| ElfSection {
+            name: elf.shdr_strtab.get_at(sh.sh_name).unwrap_or_default().to_string(),
+            section_type: section_header::sht_to_str(sh.sh_type).trim_start_matches("SHT_").to_string(),
+            address: sh.sh_addr,
+            size: sh.sh_size,
+            flags: [(SHF_WRITE, 'W'), (SHF_ALLOC, 'A'), (SHF_EXECINSTR, 'X')]
+                .iter()
+                .filter(|(flag, _)| sh.sh_flags & *flag as u64 != 0)
+                .map(|(_, letter)| *letter)
+                .collect(),
+        })
+        .collect();
+
+    // Dynamic symbols are what the loader resolves, so they are what the binary imports and exports
+    let mut imported = BTreeSet::new();
+    let mut exported = BTreeSet::new();
+    for sym in elf.dynsyms.iter() {
+        let Some(name) = elf.dynstrtab.get_at(sym.st_name).filter(|name| !name.is_empty()) else {
+            continue;
+        };
+        if !matches!(sym.st_bind(), STB_GLOBAL | STB_WEAK) {
+            continue;
+        }
+        if sym.st_shndx == SHN_UNDEF as usize {
+            imported.insert(name.to_string());
+        } else if sym.st_visibility() == STV_DEFAULT {
+            exported.insert(name.to_string());
+        }
+    }
+
+    // A static binary has no imports, so its own symbol table shows what it calls, if it kept one
+    let all_symbols: BTreeSet<&str> = elf
+        .dynsyms
+        .iter()
+        .filter_map(|sym| elf.dynstrtab.get_at(sym.st_name))
+        .chain(elf.syms.iter().filter_map(|sym| elf.strtab.get_at(sym.st_name)))
+        .collect();
+    let fortified_functions: Vec<String> = all_symbols
+        .iter()
+        .filter(|name| name.starts_with("__") && name.ends_with("_chk") && !CANARY_SYMBOLS.contains(name))
+        .map(|name| name.to_string())
+        .collect();
+
+    let relro = if !has_segment(PT_GNU_RELRO) {
+        Relro::None
+    } else if flags & DF_BIND_NOW != 0 || flags_1 & DF_1_NOW != 0 || bind_now_tag {
+        Relro::Full
+    } else {
+        Relro::Partial
+    };
+
+    Ok(BinaryInspection {
+        id: hex::encode(Sha256::digest(&bytes)),
+        path: path.display().to_string(),
+        size,
+        class: if elf.is_64 { "ELF64" } else { "ELF32" }.to_string(),
+        endianness: if elf.little_endian { "little" } else { "big" }.to_string(),
+        architecture: architecture(elf.header.e_machine),
+        file_type: match elf.header.e_type {
+            ET_EXEC => ElfFileType::Executable,
+            ET_DYN if is_pie => ElfFileType::PieExecutable,
+            ET_DYN => ElfFileType::SharedLibrary,
+            ET_REL => ElfFileType::Relocatable,
+            ET_CORE => ElfFileType::Core,
+            _ => ElfFileType::Other,
+        },
+        entry_point: elf.entry,
+        interpreter: elf.interpreter.map(str::to_string),
+        statically_linked: elf.interpreter.is_none() && elf.dynamic.is_none(),
+        libraries: elf.libraries.iter().map(|lib| lib.to_string()).collect(),
+        rpaths: elf.rpaths.iter().chain(&elf.runpaths).map(|path| path.to_string()).collect(),
+        sections,
+        imported_symbols: imported.into_iter().collect(),
+        exported_symbols: exported.into_iter().collect(),
+        stripped: elf.syms.is_empty(),
+        hardening: Hardening {
+            // Without a PT_GNU_STACK header the kernel gives the process an executable stack
+            nx: elf.program_headers.iter().any(|ph| ph.p_type == PT_GNU_STACK && ph.p_flags & PF_X == 0),
+            pie: is_pie,
+            relro,
+            stack_canary: all_symbols.iter().any(|name| CANARY_SYMBOLS.contains(name)),
+            fortify: !fortified_functions.is_empty(),
+            fortified_functions,
+        },
+        inspected_at: Utc::now(),
+    })
+}
+
+/// The usual short name of a machine type, e.g. "x86_64" rather than "EM_X86_64".
+fn architecture(machine: u16) -> String {
+    match machine {
+        header::EM_X86_64 => "x86_64".to_string(),
+        header::EM_386 => "x86".to_string(),
+        header::EM_AARCH64 => "aarch64".to_string(),
+        header::EM_ARM => "arm".to_string(),
+        header::EM_RISCV => "riscv".to_string(),
+        header::EM_MIPS => "mips".to_string(),
+        header::EM_PPC64 => "ppc64".to_string(),
+        header::EM_PPC => "ppc".to_string(),
+        header::EM_S390 => "s390x".to_string(),
+        other => header::machine_to_str(other).trim_start_matches("EM_").to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inspects_the_running_test_binary() {
+        let exe = std::env::current_exe().unwrap();
+        assert!(is_elf(&exe));
+
+        let inspection = inspect_file(&exe).unwrap();
+        assert_eq!(inspection.architecture, std::env::consts::ARCH);
+        assert_eq!(inspection.class, "ELF64");
+        assert!(inspection.libraries.iter().any(|library| library.starts_with("libc.so")));
+        assert!(inspection.sections.iter().any(|section| section.name == ".text" && section.flags.contains('X')));
+        assert!(inspection.hardening.nx);
+        assert_eq!(inspection.hardening.pie, inspection.file_type == ElfFileType::PieExecutable);
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_elf() {
+        let path = std::env::temp_dir().join(format!("aegis-elf-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "#!/bin/sh\necho hi\n").unwrap();
+        let (elf, inspection) = (is_elf(&path), inspect_file(&path));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(!elf);
+        assert!(inspection.is_err_and(|e| e.ends_with("is not an ELF binary")));
+    }
+
+    #[test]
+    fn names_machines_the_short_way() {
+        assert_eq!(architecture(header::EM_X86_64), "x86_64");
+        assert_eq!(architecture(header::EM_AARCH64), "aarch64");
+        assert_eq!(architecture(header::EM_SPARCV9), "sparcv9");
+    }
+}
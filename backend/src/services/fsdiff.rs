@@ -0,0 +1,168 @@
+use crate::models::{FileChange, FileChangeKind};
+use crate::services::artifacts::sha256_file;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Metadata;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileState {
+    pub size: u64,
+    pub sha256: String,
+}
+
+pub type Snapshot = BTreeMap<String, FileState>;
+
+/// Records the size and hash of every file below `root`, keyed by relative path.
+/// Symlinks are hashed by their target so a retargeted link shows up as modified.
+pub fn snapshot(root: &Path) -> Snapshot {
+    let mut files = Snapshot::new();
+    walk(root, root, &mut files, &mut vec![]);
+    files
+}
+
+/// Like `snapshot`, with every path made absolute under `mount_point`, which is
+/// where the sandbox shows `root` to the target.
+pub fn snapshot_mounted(root: &Path, mount_point: &str) -> Snapshot {
+    snapshot(root)
+        .into_iter()
+        .map(|(path, state)| (format!("{}/{}", mount_point.trim_end_matches('/'), path), state))
+        .collect()
+}
+
+/// What the target changed in a read-only image, from the overlay upper directory
+/// its writes went to. Files that shadow one in `lower` are modifications, others
+/// are new, and whiteouts delete what they cover. Paths are absolute in the image.
+/// Lower files hidden by an opaque directory are not reported.
+pub fn overlay_changes(lower: &Path, upper: &Path) -> Vec<FileChange> {
+    let mut written = Snapshot::new();
+    let mut whiteouts = Vec::new();
+    walk(upper, upper, &mut written, &mut whiteouts);
+
+    // The image's version of every path the target touched; the rest is unchanged.
+    // Links in the image resolve inside it, never to the host's files.
+    let lower_root = lower.canonicalize().unwrap_or_else(|_| lower.to_path_buf());
+    let contained = |path: &Path| path.parent().and_then(|parent| parent.canonicalize().ok()).is_some_and(|parent| parent.starts_with(&lower_root));
+    let lower_state = |path: &Path| {
+        let metadata = std::fs::symlink_metadata(path).ok().filter(|_| contained(path))?;
+        file_state(path, &metadata)
+    };
+    let mut shadowed = Snapshot::new();
+    for path in written.keys() {
+        if let Some(state) = lower_state(&lower.join(path)) {
+            shadowed.insert(path.clone(), state);
+        }
+    }
+    for path in whiteouts {
+        let covered = lower.join(&path);
+        if contained(&covered) && std::fs::symlink_metadata(&covered).is_ok_and(|meta| meta.is_dir()) {
+            shadowed.extend(snapshot(&covered).into_iter().map(|(inner, state)| (format!("{}/{}", path, inner), state)));
+        } else if let Some(state) = lower_state(&covered) {
+            shadowed.insert(path, state);
+        }
+    }
+
+    let absolute = |snapshot: Snapshot| -> Snapshot { snapshot.into_iter().map(|(path, state)| (format!("/{}", path), state)).collect() };
+    diff(&absolute(shadowed), &absolute(written))
+}
+
+// Overlayfs marks a deleted lower file with a 0:0 character device in its place
+fn is_whiteout(metadata: &Metadata) -> bool {
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+fn file_state(path: &Path, metadata: &Metadata) -> Option<FileState> {
+    if metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(path).map(|t| t.to_string_lossy().to_string()).unwrap_or_default();
+        Some(FileState {
+            size: target.len() as u64,
+            sha256: hex::encode(Sha256::digest(target.as_bytes())),
+        })
+    } else if metadata.is_file() {
+        sha256_file(path).ok().map(|sha256| FileState { size: metadata.len(), sha256 })
+    } else {
+        None
+    }
+}
+
+fn walk(root: &Path, dir: &Path, files: &mut Snapshot, whiteouts: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().to_string();
+
+        if metadata.is_dir() {
+            walk(root, &path, files, whiteouts);
+        } else if is_whiteout(&metadata) {
+            whiteouts.push(relative);
+        } else if let Some(state) = file_state(&path, &metadata) {
+            files.insert(relative, state);
+        }
+    }
+}
+
+/// Compares two snapshots. A deleted file whose exact content reappears under a
+/// new path is reported as a single rename instead of a delete plus a create.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<FileChange> {
+    let deleted: Vec<(&String, &FileState)> = before.iter().filter(|(path, _)| !after.contains_key(*path)).collect();
+    let created: Vec<(&String, &FileState)> = after.iter().filter(|(path, _)| !before.contains_key(*path)).collect();
+
+    let mut changes = Vec::new();
+    let mut renamed_from = HashSet::new();
+
+    for (path, state) in &created {
+        let source = deleted
+            .iter()
+            .find(|(old_path, old_state)| old_state == state && !renamed_from.contains(*old_path));
+
+        if let Some((old_path, old_state)) = source {
+            renamed_from.insert(*old_path);
+            changes.push(change(path, FileChangeKind::Renamed, Some(old_path), Some(old_state), Some(state)));
+        } else {
+            changes.push(change(path, FileChangeKind::Created, None, None, Some(state)));
+        }
+    }
+
+    for (path, old_state) in before {
+        match after.get(path) {
+            Some(state) if state != old_state => {
+                changes.push(change(path, FileChangeKind::Modified, None, Some(old_state), Some(state)));
+            }
+            None if !renamed_from.contains(path) => {
+                changes.push(change(path, FileChangeKind::Deleted, None, Some(old_state), None));
+            }
+            _ => {}
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+fn change(
+    path: &str,
+    kind: FileChangeKind,
+    renamed_from: Option<&str>,
+    before: Option<&FileState>,
+    after: Option<&FileState>,
+) -> FileChange {
+    FileChange {
+        path: path.to_string(),
+        kind,
+        renamed_from: renamed_from.map(|p| p.to_string()),
+        size_before: before.map(|s| s.size),
+        size_after: after.map(|s| s.size),
+        sha256_before: before.map(|s| s.sha256.clone()),
+        sha256_after: after.map(|s| s.sha256.clone()),
+    }
+}
@@ -1,21 +1,20 @@
 use crate::models::{FuzzStartRequest, FuzzCampaign, FuzzStats, FuzzStatus, CrashInfo};
-use crate::services::get_aegis_binary_path;
-use std::process::Command;
+use crate::services::store;
 use uuid::Uuid;
 use chrono::Utc;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
 
 pub async fn start_fuzzing(req: FuzzStartRequest) -> FuzzCampaign {
     let campaign_id = Uuid::new_v4();
     let started_at = Utc::now();
+    let target_binary = store::blob_path(&req.target_sha256).display().to_string();
 
     // Create the campaign
     let campaign = FuzzCampaign {
         id: campaign_id,
         name: format!("Campaign-{}", campaign_id),
         status: FuzzStatus::Running,
-        target_binary: req.target_binary.clone(),
+        target_binary: target_binary.clone(),
+        target_sha256: Some(req.target_sha256.clone()),
         stats: FuzzStats {
             executions: 0,
             crashes: 0,
@@ -31,7 +30,7 @@ pub async fn start_fuzzing(req: FuzzStartRequest) -> FuzzCampaign {
     // Start the fuzzing process in background
     // For this demo, we'll simulate fuzzing progress
     tokio::spawn(async move {
-        simulate_fuzzing(campaign_id, req.target_binary).await;
+        simulate_fuzzing(campaign_id, target_binary).await;
     });
 
     campaign
@@ -49,6 +48,7 @@ pub async fn get_campaign_status(campaign_id: Uuid) -> Option<FuzzCampaign> {
         name: format!("Campaign-{}", campaign_id),
         status: FuzzStatus::Running,
         target_binary: "test_target".to_string(),
+        target_sha256: None,
         stats: FuzzStats {
             executions: 15234,
             crashes: 3,
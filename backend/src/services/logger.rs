@@ -8,6 +8,18 @@ pub fn add_log(
     source: LogSource,
     message: String,
     details: Option<serde_json::Value>,
+) {
+    add_tagged_log(logs, level, source, message, details, vec![]);
+}
+
+/// Like `add_log`, for events that are evidence of ATT&CK techniques.
+pub fn add_tagged_log(
+    logs: &std::sync::Arc<std::sync::Mutex<Vec<LogEntry>>>,
+    level: LogLevel,
+    source: LogSource,
+    message: String,
+    details: Option<serde_json::Value>,
+    techniques: Vec<String>,
 ) {
     let entry = LogEntry {
         id: Uuid::new_v4(),
@@ -15,6 +27,7 @@ pub fn add_log(
         source,
         message,
         details,
+        techniques,
         timestamp: Utc::now(),
     };
 
@@ -36,7 +49,7 @@ pub fn get_timeline(
     let mut entries = logs_guard.clone();
 
     // Sort by timestamp descending
-    entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.timestamp));
 
     // Apply limit
     if let Some(limit) = limit {
@@ -48,15 +61,3 @@ pub fn get_timeline(
         entries,
     }
 }
-
-pub fn get_logs_by_source(
-    logs: &std::sync::Arc<std::sync::Mutex<Vec<LogEntry>>>,
-    source: LogSource,
-) -> Vec<LogEntry> {
-    let logs_guard = logs.lock().unwrap();
-    logs_guard
-        .iter()
-        .filter(|log| log.source == source)
-        .cloned()
-        .collect()
-}
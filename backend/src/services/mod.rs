@@ -1,14 +1,32 @@
 use crate::models::*;
-use std::process::Command;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use tokio::task::spawn_blocking;
 use uuid::Uuid;
 
 pub mod compiler;
 pub mod sandbox;
 pub mod fuzzer;
 pub mod logger;
+pub mod artifacts;
+pub mod fsdiff;
+pub mod trace;
+pub mod queue;
+pub mod policy;
+pub mod netemu;
+pub mod pcap;
+pub mod coredump;
+pub mod compare;
+pub mod toolchain;
+pub mod batch;
+pub mod pty;
+pub mod recording;
+pub mod verdict;
+pub mod attack;
+pub mod selftest;
+pub mod wasm;
+pub mod rootfs;
+pub mod elf;
+pub mod store;
 
 // ============================================================================
 // AEGIS Binary Paths
@@ -33,6 +51,22 @@ pub fn get_aegis_binary_path(binary_name: &str) -> String {
     binary_name.to_string()
 }
 
+// ============================================================================
+// Sandbox Workspaces & Data Directory
+// ============================================================================
+
+pub fn get_data_dir() -> std::path::PathBuf {
+    std::env::var("AEGIS_DATA_DIR")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|_| std::path::PathBuf::from("aegis-data"))
+}
+
+pub fn get_sandbox_workspace_root() -> std::path::PathBuf {
+    std::env::var("AEGIS_SANDBOX_ROOT")
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(|_| std::env::temp_dir().join("aegis-sandbox"))
+}
+
 // ============================================================================
 // In-Memory State Store (for demo purposes)
 // ============================================================================
@@ -41,6 +75,16 @@ pub fn get_aegis_binary_path(binary_name: &str) -> String {
 pub struct AppState {
     pub projects: Arc<Mutex<Vec<Project>>>,
     pub sandbox_runs: Arc<Mutex<HashMap<Uuid, SandboxRun>>>,
+    pub active_sandboxes: sandbox::ActiveSandboxes,
+    pub sandbox_queue: Arc<queue::SandboxQueue>,
+    pub syscall_policies: Arc<Mutex<HashMap<String, SyscallPolicy>>>,
+    pub sandbox_batches: Arc<Mutex<HashMap<Uuid, SandboxBatch>>>,
+    pub verdict_rules: Arc<Mutex<HashMap<String, Vec<VerdictRule>>>>,  // Every version of each rule, oldest first
+    pub attack_mapping: Arc<Mutex<AttackMapping>>,
+    pub selftest_reports: Arc<Mutex<Vec<SelftestReport>>>,
+    pub binaries: Arc<Mutex<HashMap<String, BinaryInspection>>>,  // Keyed by sha256
+    pub store_pins: store::Pins,
+    pub rootfs_pins: store::Pins,  // Keyed by image name
     pub fuzz_campaigns: Arc<Mutex<HashMap<Uuid, FuzzCampaign>>>,
     pub logs: Arc<Mutex<Vec<LogEntry>>>,
 }
@@ -58,6 +102,20 @@ impl AppState {
                 }
             ])),
             sandbox_runs: Arc::new(Mutex::new(HashMap::new())),
+            active_sandboxes: Arc::new(Mutex::new(HashMap::new())),
+            sandbox_queue: Arc::new(queue::SandboxQueue::new(queue::QueueConfig::from_env())),
+            syscall_policies: Arc::new(Mutex::new(
+                policy::builtin_policies().into_iter().map(|p| (p.name.clone(), p)).collect(),
+            )),
+            sandbox_batches: Arc::new(Mutex::new(HashMap::new())),
+            verdict_rules: Arc::new(Mutex::new(
+                verdict::builtin_rules().into_iter().map(|r| (r.id.clone(), vec![r])).collect(),
+            )),
+            attack_mapping: Arc::new(Mutex::new(attack::load_mapping())),
+            selftest_reports: Arc::new(Mutex::new(Vec::new())),
+            binaries: Arc::new(Mutex::new(HashMap::new())),
+            store_pins: Arc::new(Mutex::new(HashMap::new())),
+            rootfs_pins: Arc::new(Mutex::new(HashMap::new())),
             fuzz_campaigns: Arc::new(Mutex::new(HashMap::new())),
             logs: Arc::new(Mutex::new(Vec::new())),
         }
@@ -0,0 +1,392 @@
+use crate::models::{NetworkEvent, NetworkProtocol};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::Utc;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::os::fd::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::task::{JoinHandle, JoinSet};
+
+// Every name resolves here; aegis-sandbox routes the whole address space to the sinks
+pub const SINKHOLE_ADDR: Ipv4Addr = Ipv4Addr::new(10, 66, 0, 1);
+
+const MAX_PAYLOAD: usize = 64 * 1024;
+const MAX_EVENTS: usize = 4096;
+// Connections handled at once; more are closed on accept
+const MAX_CONNECTIONS: usize = 256;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(2);
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+// First pause after a failed receive or accept, doubled for each failure in a row
+const ERROR_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_BACKOFF_DOUBLINGS: u32 = 7;
+
+const HTTP_METHODS: &[&str] = &["GET ", "POST ", "PUT ", "HEAD ", "DELETE ", "OPTIONS ", "PATCH ", "CONNECT "];
+const HTTP_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// What the services saw, and whether a cap dropped any of it.
+#[derive(Default)]
+pub struct Capture {
+    pub events: Vec<NetworkEvent>,
+    pub truncated: bool,
+}
+
+type Events = Arc<Mutex<Capture>>;
+
+/// The fake services behind an emulated network: a resolver that answers every
+/// query with the sinkhole, and a TCP sink that accepts anything and speaks just
+/// enough HTTP to keep a beacon talking. Both record what they see.
+pub struct FakeNetwork {
+    pub dns_addr: SocketAddr,
+    pub sink_addr: SocketAddr,
+    events: Events,
+    connections: Arc<Mutex<JoinSet<()>>>,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl FakeNetwork {
+    pub async fn start() -> io::Result<Self> {
+        let dns = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let sink = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let events: Events = Arc::new(Mutex::new(Capture::default()));
+        let connections = Arc::new(Mutex::new(JoinSet::new()));
+
+        Ok(Self {
+            dns_addr: dns.local_addr()?,
+            sink_addr: sink.local_addr()?,
+            listeners: vec![
+                tokio::spawn(serve_dns(dns, events.clone())),
+                tokio::spawn(serve_sink(sink, events.clone(), connections.clone())),
+            ],
+            events,
+            connections,
+        })
+    }
+
+    /// Stops the services and returns everything they recorded, oldest first.
+    pub async fn finish(self) -> Capture {
+        for listener in &self.listeners {
+            listener.abort();
+        }
+
+        // The sandbox is gone, so open connections are at EOF; let them record their last reads
+        let mut connections = std::mem::take(&mut *self.connections.lock().unwrap());
+        let _ = tokio::time::timeout(DRAIN_TIMEOUT, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+/// Returns the event's index, or None once the capture is full.
+fn record(events: &Events, event: NetworkEvent) -> Option<usize> {
+    let mut capture = events.lock().unwrap();
+    if capture.events.len() >= MAX_EVENTS {
+        capture.truncated = true;
+        return None;
+    }
+    capture.events.push(event);
+    Some(capture.events.len() - 1)
+}
+
+fn mark_truncated(events: &Events) {
+    events.lock().unwrap().truncated = true;
+}
+
+// Keeps a persistent error (such as running out of descriptors) from spinning the task
+async fn back_off(failures: &mut u32) {
+    tokio::time::sleep(ERROR_BACKOFF * 2u32.pow((*failures).min(MAX_BACKOFF_DOUBLINGS))).await;
+    *failures += 1;
+}
+
+// ============================================================================
+// DNS
+// ============================================================================
+
+async fn serve_dns(socket: UdpSocket, events: Events) {
+    let mut buf = [0u8; 512];
+    let mut failures = 0;
+    loop {
+        let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+            back_off(&mut failures).await;
+            continue;
+        };
+        failures = 0;
+        let packet = &buf[..len];
+        let Some(query) = parse_dns_query(packet) else {
+            continue;
+        };
+
+        record(&events, NetworkEvent {
+            protocol: NetworkProtocol::Dns,
+            destination: None,
+            host: Some(query.name.clone()),
+            request: Some(record_type_name(query.qtype)),
+            payload: BASE64.encode(packet),
+            payload_size: len as u64,
+            timestamp: Utc::now(),
+        });
+
+        let _ = socket.send_to(&dns_response(packet, &query), peer).await;
+    }
+}
+
+struct DnsQuery {
+    name: String,
+    qtype: u16,
+    question_end: usize,
+}
+
+/// Reads the first question of a DNS query. Responses and malformed packets are ignored.
+fn parse_dns_query(packet: &[u8]) -> Option<DnsQuery> {
+    if packet.len() < 12 || packet[2] & 0x80 != 0 || u16::from_be_bytes([packet[4], packet[5]]) == 0 {
+        return None;
+    }
+
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        // Compression pointers never appear in a question we need to answer
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(packet.get(pos..pos + len)?).to_string());
+        pos += len;
+    }
+
+    let qtype = u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]);
+    packet.get(pos + 2..pos + 4)?;
+
+    Some(DnsQuery {
+        name: labels.join("."),
+        qtype,
+        question_end: pos + 4,
+    })
+}
+
+/// Answers A queries with the sinkhole and everything else with an empty NOERROR.
+fn dns_response(packet: &[u8], query: &DnsQuery) -> Vec<u8> {
+    let mut response = packet[..query.question_end].to_vec();
+
+    // QR and AA set, opcode and RD echoed, RA set, RCODE 0
+    response[2] = 0x80 | (packet[2] & 0x78) | 0x04 | (packet[2] & 0x01);
+    response[3] = 0x80;
+
+    let answers: u16 = if query.qtype == 1 { 1 } else { 0 };
+    response[4..6].copy_from_slice(&1u16.to_be_bytes());
+    response[6..8].copy_from_slice(&answers.to_be_bytes());
+    response[8..12].fill(0);
+
+    if answers > 0 {
+        response.extend_from_slice(&[0xC0, 0x0C]); // Pointer to the question name
+        response.extend_from_slice(&1u16.to_be_bytes()); // A
+        response.extend_from_slice(&1u16.to_be_bytes()); // IN
+        response.extend_from_slice(&60u32.to_be_bytes()); // TTL
+        response.extend_from_slice(&4u16.to_be_bytes());
+        response.extend_from_slice(&SINKHOLE_ADDR.octets());
+    }
+
+    response
+}
+
+fn record_type_name(qtype: u16) -> String {
+    match qtype {
+        1 => "A".to_string(),
+        2 => "NS".to_string(),
+        5 => "CNAME".to_string(),
+        12 => "PTR".to_string(),
+        15 => "MX".to_string(),
+        16 => "TXT".to_string(),
+        28 => "AAAA".to_string(),
+        33 => "SRV".to_string(),
+        255 => "ANY".to_string(),
+        other => format!("TYPE{}", other),
+    }
+}
+
+// ============================================================================
+// TCP / HTTP sink
+// ============================================================================
+
+async fn serve_sink(listener: TcpListener, events: Events, connections: Arc<Mutex<JoinSet<()>>>) {
+    let mut failures = 0;
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            back_off(&mut failures).await;
+            continue;
+        };
+        failures = 0;
+
+        let mut connections = connections.lock().unwrap();
+        while connections.try_join_next().is_some() {}
+        if connections.len() >= MAX_CONNECTIONS {
+            mark_truncated(&events);
+            continue;
+        }
+        connections.spawn(handle_connection(stream, events.clone()));
+    }
+}
+
+async fn handle_connection(mut stream: TcpStream, events: Events) {
+    // Recorded on accept so a connection that never sends anything still shows up
+    let index = record(&events, NetworkEvent {
+        protocol: NetworkProtocol::Tcp,
+        destination: original_destination(&stream).map(|addr| addr.to_string()),
+        host: None,
+        request: None,
+        payload: String::new(),
+        payload_size: 0,
+        timestamp: Utc::now(),
+    });
+
+    let mut payload = Vec::new();
+    let mut total = 0u64;
+    let mut responded = false;
+    let mut buf = [0u8; 4096];
+
+    loop {
+        let read = match tokio::time::timeout(IDLE_TIMEOUT, stream.read(&mut buf)).await {
+            Ok(Ok(read)) if read > 0 => read,
+            _ => break,
+        };
+        total += read as u64;
+        let room = MAX_PAYLOAD.saturating_sub(payload.len());
+        payload.extend_from_slice(&buf[..read.min(room)]);
+
+        let http = parse_http_request(&payload);
+        if let Some(index) = index {
+            let mut capture = events.lock().unwrap();
+            let event = &mut capture.events[index];
+            event.payload = BASE64.encode(&payload);
+            event.payload_size = total;
+            if let Some((request_line, host)) = &http {
+                event.protocol = NetworkProtocol::Http;
+                event.request = Some(request_line.clone());
+                event.host = host.clone();
+            }
+        }
+
+        if http.is_some() && !responded && payload.windows(4).any(|w| w == b"\r\n\r\n") {
+            responded = stream.write_all(HTTP_RESPONSE).await.is_ok();
+        }
+    }
+}
+
+/// Returns the request line and Host header if the payload starts like an HTTP request.
+fn parse_http_request(payload: &[u8]) -> Option<(String, Option<String>)> {
+    if !HTTP_METHODS.iter().any(|method| payload.starts_with(method.as_bytes())) {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(payload);
+    let mut lines = text.split("\r\n");
+    let request_line = lines.next()?.to_string();
+    let host = lines
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("host").then(|| value.trim().to_string())
+        });
+
+    Some((request_line, host))
+}
+
+/// The address the sample actually dialled, recovered from the NAT table when
+/// aegis-sandbox redirected the connection.
+fn original_destination(stream: &TcpStream) -> Option<SocketAddr> {
+    let mut addr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_IP,
+            libc::SO_ORIGINAL_DST,
+            &mut addr as *mut libc::sockaddr_in as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return None;
+    }
+
+    let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+    Some(SocketAddr::from((ip, u16::from_be(addr.sin_port))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = id.to_be_bytes().to_vec();
+        packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]); // RD, one question
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&1u16.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn answers_a_queries_with_the_sinkhole() {
+        let packet = query(0x1234, "c2.evil.example", 1);
+        let parsed = parse_dns_query(&packet).unwrap();
+        assert_eq!((parsed.name.as_str(), parsed.qtype, parsed.question_end), ("c2.evil.example", 1, packet.len()));
+
+        let response = dns_response(&packet, &parsed);
+        assert_eq!(response[..2], [0x12, 0x34]);
+        assert_eq!(response[2] & 0x81, 0x81); // A response, recursion desired echoed
+        assert_eq!(response[6..8], [0, 1]);
+        assert_eq!(response[response.len() - 4..], SINKHOLE_ADDR.octets());
+    }
+
+    #[test]
+    fn other_record_types_get_an_empty_answer() {
+        let packet = query(7, "evil.example", 28);
+        let parsed = parse_dns_query(&packet).unwrap();
+        assert_eq!(record_type_name(parsed.qtype), "AAAA");
+
+        let response = dns_response(&packet, &parsed);
+        assert_eq!(response.len(), packet.len());
+        assert_eq!(response[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn ignores_responses_and_malformed_queries() {
+        let mut response = query(1, "example", 1);
+        response[2] |= 0x80;
+        assert!(parse_dns_query(&response).is_none());
+
+        let packet = query(1, "example", 1);
+        assert!(parse_dns_query(&packet[..packet.len() - 3]).is_none());
+        assert!(parse_dns_query(&packet[..8]).is_none());
+
+        let mut compressed = packet[..12].to_vec();
+        compressed.extend_from_slice(&[0xC0, 0x0C, 0, 1, 0, 1]);
+        assert!(parse_dns_query(&compressed).is_none());
+    }
+
+    #[test]
+    fn reads_the_request_line_and_host_of_http_requests() {
+        let request = b"GET /beacon HTTP/1.1\r\nUser-Agent: x\r\nhost: c2.example:8080 \r\n\r\nHost: body";
+        assert_eq!(
+            parse_http_request(request),
+            Some(("GET /beacon HTTP/1.1".to_string(), Some("c2.example:8080".to_string())))
+        );
+        assert_eq!(parse_http_request(b"POST / HTTP/1.0\r\n\r\n"), Some(("POST / HTTP/1.0".to_string(), None)));
+        assert_eq!(parse_http_request(b"\x16\x03\x01\x02\x00"), None);
+    }
+}
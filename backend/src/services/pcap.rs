@@ -0,0 +1,333 @@
+use crate::models::{ArtifactKind, NetworkFlow, SandboxRun};
+use crate::services::artifacts::run_artifact_dir;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Bytes of application data kept from the first packet that carries any
+const FIRST_PAYLOAD_BYTES: usize = 64;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+pub struct Capture {
+    pub link_type: u32,
+    pub packets: u64,
+    pub flows: Vec<NetworkFlow>,
+}
+
+struct Packet<'a> {
+    protocol: u8,
+    source: IpAddr,
+    destination: IpAddr,
+    ports: Option<(u16, u16)>,
+    payload: &'a [u8],
+}
+
+type FlowKey = (u8, IpAddr, Option<u16>, IpAddr, Option<u16>);
+
+/// Groups the packets of a classic libpcap file into bidirectional flows. The
+/// endpoint that sent the first packet of a flow is its source.
+pub fn summarize(data: &[u8]) -> Result<Capture, String> {
+    let header = data.get(..24).ok_or("File is too short to be a pcap capture")?;
+    let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let (little_endian, nanos) = match magic {
+        0xa1b2c3d4 => (true, false),
+        0xa1b23c4d => (true, true),
+        0xd4c3b2a1 => (false, false),
+        0x4d3cb2a1 => (false, true),
+        _ => return Err("Not a libpcap file (pcapng is not supported)".to_string()),
+    };
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+    };
+
+    let link_type = read_u32(&header[20..24]) & 0x0fff_ffff;
+    let mut flows: Vec<NetworkFlow> = Vec::new();
+    let mut index: HashMap<FlowKey, usize> = HashMap::new();
+    let mut packets = 0;
+    let mut pos = 24;
+
+    while let Some(record) = data.get(pos..pos + 16) {
+        let seconds = read_u32(&record[0..4]) as i64;
+        let fraction = read_u32(&record[4..8]);
+        let captured = read_u32(&record[8..12]) as usize;
+        let original = read_u32(&record[12..16]) as u64;
+        pos += 16;
+
+        // A capture cut off mid-record still yields the packets before it
+        let Some(frame) = data.get(pos..pos + captured) else {
+            break;
+        };
+        pos += captured;
+        packets += 1;
+
+        let Some(packet) = decode_frame(link_type, frame) else {
+            continue;
+        };
+        let timestamp = packet_time(seconds, fraction, nanos);
+
+        let (source_port, destination_port) = packet.ports.unzip();
+        let forward = (packet.protocol, packet.source, source_port, packet.destination, destination_port);
+        let reverse = (packet.protocol, packet.destination, destination_port, packet.source, source_port);
+
+        let (flow, outbound) = if let Some(&i) = index.get(&forward) {
+            (&mut flows[i], true)
+        } else if let Some(&i) = index.get(&reverse) {
+            (&mut flows[i], false)
+        } else {
+            index.insert(forward, flows.len());
+            flows.push(NetworkFlow {
+                protocol: protocol_name(packet.protocol),
+                source: packet.source.to_string(),
+                source_port,
+                destination: packet.destination.to_string(),
+                destination_port,
+                packets: 0,
+                bytes_sent: 0,
+                bytes_received: 0,
+                first_payload: None,
+                first_seen: timestamp,
+                last_seen: timestamp,
+            });
+            (flows.last_mut().unwrap(), true)
+        };
+
+        flow.packets += 1;
+        flow.last_seen = timestamp;
+        if outbound {
+            flow.bytes_sent += original;
+        } else {
+            flow.bytes_received += original;
+        }
+        if flow.first_payload.is_none() && !packet.payload.is_empty() {
+            let len = packet.payload.len().min(FIRST_PAYLOAD_BYTES);
+            flow.first_payload = Some(BASE64.encode(&packet.payload[..len]));
+        }
+    }
+
+    Ok(Capture { link_type, packets, flows })
+}
+
+/// Flows in a run's packet capture; empty if it has none or it cannot be read.
+pub async fn run_flows(run: &SandboxRun) -> Vec<NetworkFlow> {
+    let Some(capture) = run.artifacts.iter().find(|artifact| artifact.kind == ArtifactKind::Pcap) else {
+        return vec![];
+    };
+
+    match tokio::fs::read(run_artifact_dir(run.id).join(&capture.name)).await {
+        Ok(bytes) => summarize(&bytes).map(|capture| capture.flows).unwrap_or_default(),
+        Err(_) => vec![],
+    }
+}
+
+fn packet_time(seconds: i64, fraction: u32, nanos: bool) -> DateTime<Utc> {
+    let nanoseconds = if nanos { fraction } else { fraction.saturating_mul(1000) };
+    Utc.timestamp_opt(seconds, nanoseconds).single().unwrap_or_default()
+}
+
+/// Strips the link-layer header and decodes the IP packet underneath, if any.
+fn decode_frame(link_type: u32, frame: &[u8]) -> Option<Packet<'_>> {
+    let ip = match link_type {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            let mut offset = 14;
+            // 802.1Q VLAN tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                ethertype = u16::from_be_bytes([*frame.get(offset + 2)?, *frame.get(offset + 3)?]);
+                offset += 4;
+            }
+            if ethertype != 0x0800 && ethertype != 0x86dd {
+                return None;
+            }
+            frame.get(offset..)?
+        }
+        LINKTYPE_LINUX_SLL => frame.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => frame.get(20..)?,
+        LINKTYPE_NULL => frame.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => frame,
+        _ => return None,
+    };
+
+    match ip.first()? >> 4 {
+        4 => decode_ipv4(ip),
+        6 => decode_ipv6(ip),
+        _ => None,
+    }
+}
+
+fn decode_ipv4(ip: &[u8]) -> Option<Packet<'_>> {
+    let header_len = ((ip.first()? & 0x0f) as usize) * 4;
+    if header_len > ip.len() {
+        return None;
+    }
+    let total_len = u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]) as usize;
+    let protocol = *ip.get(9)?;
+    let source = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(12..16)?).ok()?);
+    let destination = Ipv4Addr::from(<[u8; 4]>::try_from(ip.get(16..20)?).ok()?);
+    let body = ip.get(header_len..total_len.min(ip.len()).max(header_len))?;
+
+    // Only the first fragment carries the transport header
+    let fragment_offset = u16::from_be_bytes([*ip.get(6)?, *ip.get(7)?]) & 0x1fff;
+    let (ports, payload) = if fragment_offset == 0 { decode_transport(protocol, body) } else { (None, body) };
+
+    Some(Packet {
+        protocol,
+        source: source.into(),
+        destination: destination.into(),
+        ports,
+        payload,
+    })
+}
+
+fn decode_ipv6(ip: &[u8]) -> Option<Packet<'_>> {
+    let payload_len = u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]) as usize;
+    let protocol = *ip.get(6)?;
+    let source = Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(8..24)?).ok()?);
+    let destination = Ipv6Addr::from(<[u8; 16]>::try_from(ip.get(24..40)?).ok()?);
+    let body = ip.get(40..(40 + payload_len).min(ip.len()))?;
+    let (ports, payload) = decode_transport(protocol, body);
+
+    Some(Packet {
+        protocol,
+        source: source.into(),
+        destination: destination.into(),
+        ports,
+        payload,
+    })
+}
+
+/// Returns the ports and application payload of a TCP or UDP segment.
+fn decode_transport(protocol: u8, body: &[u8]) -> (Option<(u16, u16)>, &[u8]) {
+    let ports = |body: &[u8]| -> Option<(u16, u16)> {
+        Some((
+            u16::from_be_bytes([*body.first()?, *body.get(1)?]),
+            u16::from_be_bytes([*body.get(2)?, *body.get(3)?]),
+        ))
+    };
+
+    match protocol {
+        6 => {
+            let offset = body.get(12).map(|b| ((b >> 4) as usize) * 4).unwrap_or(body.len());
+            (ports(body), body.get(offset..).unwrap_or(&[]))
+        }
+        17 => (ports(body), body.get(8..).unwrap_or(&[])),
+        _ => (None, &[]),
+    }
+}
+
+fn protocol_name(protocol: u8) -> String {
+    match protocol {
+        1 => "ICMP".to_string(),
+        6 => "TCP".to_string(),
+        17 => "UDP".to_string(),
+        58 => "ICMPv6".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp(source: [u8; 4], destination: [u8; 4], ports: (u16, u16), payload: &[u8]) -> Vec<u8> {
+        let total = (20 + 8 + payload.len()) as u16;
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0, 0, 64, 17, 0, 0];
+        packet[2..4].copy_from_slice(&total.to_be_bytes());
+        packet.extend_from_slice(&source);
+        packet.extend_from_slice(&destination);
+        packet.extend_from_slice(&ports.0.to_be_bytes());
+        packet.extend_from_slice(&ports.1.to_be_bytes());
+        packet.extend_from_slice(&(8 + payload.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0]);
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    /// A raw-IP capture with the given magic, written in the byte order it implies.
+    fn capture(magic: u32, big_endian: bool, packets: &[(u32, u32, Vec<u8>)]) -> Vec<u8> {
+        let u32_bytes = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let u16_bytes = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+
+        let mut data = u32_bytes(magic).to_vec();
+        data.extend_from_slice(&u16_bytes(2));
+        data.extend_from_slice(&u16_bytes(4));
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(&u32_bytes(65535));
+        data.extend_from_slice(&u32_bytes(LINKTYPE_RAW));
+
+        for (seconds, fraction, frame) in packets {
+            data.extend_from_slice(&u32_bytes(*seconds));
+            data.extend_from_slice(&u32_bytes(*fraction));
+            data.extend_from_slice(&u32_bytes(frame.len() as u32));
+            data.extend_from_slice(&u32_bytes(frame.len() as u32));
+            data.extend_from_slice(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn keeps_the_packets_before_a_truncated_record() {
+        let query = udp([10, 0, 0, 2], [10, 66, 0, 1], (40000, 53), b"query");
+        let reply = udp([10, 66, 0, 1], [10, 0, 0, 2], (53, 40000), b"answer");
+        let mut data = capture(0xa1b2c3d4, false, &[(1, 0, query.clone()), (2, 0, reply), (3, 0, query)]);
+        // Cut the last packet short, then cut off part of its record header as well
+        data.truncate(data.len() - 10);
+        let summary = summarize(&data).unwrap();
+        assert_eq!(summary.packets, 2);
+
+        data.truncate(data.len() - 30);
+        let summary = summarize(&data).unwrap();
+        assert_eq!(summary.packets, 2);
+        assert_eq!(summary.flows.len(), 1);
+
+        let flow = &summary.flows[0];
+        assert_eq!(flow.protocol, "UDP");
+        assert_eq!((flow.source.as_str(), flow.source_port), ("10.0.0.2", Some(40000)));
+        assert_eq!((flow.destination.as_str(), flow.destination_port), ("10.66.0.1", Some(53)));
+        assert_eq!(flow.packets, 2);
+        assert_eq!((flow.bytes_sent, flow.bytes_received), (33, 34));
+        assert_eq!(flow.first_payload.as_deref(), Some(BASE64.encode(b"query").as_str()));
+    }
+
+    #[test]
+    fn reads_big_endian_nanosecond_captures() {
+        let packet = udp([10, 0, 0, 2], [10, 66, 0, 1], (40000, 53), b"");
+        let data = capture(0xa1b23c4d, true, &[(1_700_000_000, 123_456_789, packet.clone())]);
+        let summary = summarize(&data).unwrap();
+        assert_eq!(summary.link_type, LINKTYPE_RAW);
+        assert_eq!(summary.flows[0].first_seen, Utc.timestamp_opt(1_700_000_000, 123_456_789).unwrap());
+
+        // The same fraction in a microsecond capture
+        let data = capture(0xa1b2c3d4, true, &[(1_700_000_000, 123_456, packet)]);
+        let summary = summarize(&data).unwrap();
+        assert_eq!(summary.flows[0].first_seen, Utc.timestamp_opt(1_700_000_000, 123_456_000).unwrap());
+    }
+
+    #[test]
+    fn skips_packets_whose_header_is_longer_than_the_packet() {
+        let mut packet = udp([10, 0, 0, 2], [10, 66, 0, 1], (40000, 53), b"query");
+        // IHL 15 claims a 60-byte header in a 33-byte packet
+        packet[0] = 0x4f;
+        let data = capture(0xa1b2c3d4, false, &[(1, 0, packet)]);
+        let summary = summarize(&data).unwrap();
+        assert_eq!(summary.packets, 1);
+        assert!(summary.flows.is_empty());
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        // pcapng section header block
+        let mut data = vec![0x0a, 0x0d, 0x0d, 0x0a];
+        data.resize(28, 0);
+        assert!(summarize(&data).is_err());
+        assert!(summarize(&data[..20]).is_err());
+    }
+}
@@ -0,0 +1,657 @@
+use crate::models::{
+    ArgCondition, ConditionOp, LearnPolicyRequest, LearnedPolicy, LogLevel, LogSource, PolicyAction, PolicyMode,
+    PolicyViolation, ProcessNode, RunStatus, SandboxRun, SyscallEntry, SyscallPolicy, SyscallRule,
+};
+use crate::services::{logger, sandbox, AppState};
+use chrono::Utc;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+pub const MAX_LEARNING_RUNS: u32 = 10;
+
+// Syscalls whose behaviour hinges on one argument, and which argument that is
+const PINNED_ARGS: &[(&str, u8)] = &[
+    ("socket", 0),
+    ("personality", 0),
+    ("prctl", 0),
+    ("arch_prctl", 0),
+    ("ioctl", 1),
+    ("fcntl", 1),
+];
+
+// Above this many distinct values the syscall is allowed without conditions
+const MAX_PINNED_VALUES: usize = 8;
+
+// ============================================================================
+// Evaluation
+// ============================================================================
+
+/// What the seccomp filter built from a policy decides for one call.
+pub struct Decision {
+    pub action: PolicyAction,
+    pub rule: String,
+}
+
+/// Mirrors the kernel filter: every matching rule is considered and the most
+/// restrictive action wins (kill > errno > log > allow), not the first match.
+pub fn evaluate(policy: &SyscallPolicy, syscall: &str, args: &[Option<u64>]) -> Decision {
+    let matched = policy
+        .rules
+        .iter()
+        .enumerate()
+        .filter(|(_, rule)| rule.syscalls.iter().any(|name| name == syscall))
+        .filter(|(_, rule)| rule.conditions.iter().all(|condition| condition_matches(condition, args)))
+        .max_by_key(|(_, rule)| severity(&rule.action));
+
+    match matched {
+        Some((index, rule)) => Decision {
+            action: rule.action.clone(),
+            rule: rule_label(rule, index),
+        },
+        None => Decision {
+            action: default_action(policy),
+            rule: "default".to_string(),
+        },
+    }
+}
+
+pub fn default_action(policy: &SyscallPolicy) -> PolicyAction {
+    policy.default_action.clone().unwrap_or(match policy.mode {
+        PolicyMode::Allowlist => PolicyAction::Errno(libc::EPERM),
+        PolicyMode::Denylist => PolicyAction::Allow,
+    })
+}
+
+/// Fills in `allowed` and `matched_rule` on every traced call and summarises the
+/// calls the policy did not simply allow. `matched_rule` comes from evaluating the
+/// policy against the trace; `allowed` is what the trace shows the filter did, since
+/// the exported profile cannot express every condition the evaluation can.
+pub fn apply(policy: &SyscallPolicy, syscalls: &mut [SyscallEntry], processes: &[ProcessNode]) -> Vec<PolicyViolation> {
+    let mut violations: BTreeMap<(String, String), PolicyViolation> = BTreeMap::new();
+    let killed_by_filter: HashSet<u32> = processes
+        .iter()
+        .filter(|process| process.signal == Some(libc::SIGSYS))
+        .map(|process| process.pid)
+        .collect();
+
+    for entry in syscalls.iter_mut() {
+        let args = parse_args(&entry.args);
+        let decision = evaluate(policy, &entry.syscall, &args);
+
+        entry.allowed = !refused(&decision.action, entry, &killed_by_filter);
+        entry.matched_rule = Some(decision.rule.clone());
+
+        if decision.action != PolicyAction::Allow {
+            let violation = violations
+                .entry((entry.syscall.clone(), decision.rule.clone()))
+                .or_insert_with(|| PolicyViolation {
+                    policy: policy.name.clone(),
+                    syscall: entry.syscall.clone(),
+                    rule: decision.rule.clone(),
+                    action: decision.action.clone(),
+                    count: 0,
+                    not_enforced: 0,
+                });
+            violation.count += 1;
+            if entry.allowed && matches!(decision.action, PolicyAction::Errno(_) | PolicyAction::Kill) {
+                violation.not_enforced += 1;
+            }
+        }
+    }
+
+    violations.into_values().collect()
+}
+
+/// Whether the trace shows the call refused the way `action` refuses it: failing with
+/// the rule's errno, or never returning in a process the kernel killed with SIGSYS.
+fn refused(action: &PolicyAction, entry: &SyscallEntry, killed_by_filter: &HashSet<u32>) -> bool {
+    match action {
+        PolicyAction::Errno(errno) => failed_with(&entry.result, *errno),
+        PolicyAction::Kill => entry.result.starts_with('?') && entry.pid.is_some_and(|pid| killed_by_filter.contains(&pid)),
+        PolicyAction::Allow | PolicyAction::Log => false,
+    }
+}
+
+// strace prints failures as `-1 EPERM (Operation not permitted)`; the description is
+// the one piece that maps back to the errno number without a table of names
+fn failed_with(result: &str, errno: i32) -> bool {
+    let Some((_, description)) = result.strip_prefix("-1 ").and_then(|rest| rest.split_once(" (")) else {
+        return false;
+    };
+    let expected = std::io::Error::from_raw_os_error(errno).to_string();
+    let expected = expected.split(" (os error").next().unwrap_or_default();
+    description.trim_end_matches(')') == expected
+}
+
+fn severity(action: &PolicyAction) -> u8 {
+    match action {
+        PolicyAction::Allow => 0,
+        PolicyAction::Log => 1,
+        PolicyAction::Errno(_) => 2,
+        PolicyAction::Kill => 3,
+    }
+}
+
+fn rule_label(rule: &SyscallRule, index: usize) -> String {
+    rule.name.clone().unwrap_or_else(|| format!("rule-{}", index))
+}
+
+fn condition_matches(condition: &ArgCondition, args: &[Option<u64>]) -> bool {
+    // Arguments the trace only shows symbolically (and that are not in the table below) never match
+    let Some(Some(arg)) = args.get(condition.index as usize) else {
+        return false;
+    };
+
+    match condition.op {
+        ConditionOp::Equal => *arg == condition.value,
+        ConditionOp::NotEqual => *arg != condition.value,
+        ConditionOp::LessThan => *arg < condition.value,
+        ConditionOp::LessOrEqual => *arg <= condition.value,
+        ConditionOp::GreaterThan => *arg > condition.value,
+        ConditionOp::GreaterOrEqual => *arg >= condition.value,
+        ConditionOp::MaskedEqual => *arg & condition.value == condition.value_two.unwrap_or(0),
+    }
+}
+
+/// Splits a traced argument list at top-level commas and decodes each argument to
+/// the value the filter saw, where that is recoverable from the trace.
+pub fn parse_args(args: &str) -> Vec<Option<u64>> {
+    let mut values = Vec::new();
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut current = String::new();
+
+    for c in args.chars() {
+        if in_string {
+            current.push(c);
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match c {
+            '"' => {
+                in_string = true;
+                current.push(c);
+            }
+            '[' | '{' | '(' => {
+                depth += 1;
+                current.push(c);
+            }
+            ']' | '}' | ')' => {
+                depth -= 1;
+                current.push(c);
+            }
+            ',' if depth == 0 => {
+                values.push(parse_value(current.trim()));
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+
+    if !current.trim().is_empty() {
+        values.push(parse_value(current.trim()));
+    }
+
+    values
+}
+
+fn parse_value(arg: &str) -> Option<u64> {
+    if arg.is_empty() {
+        return None;
+    }
+
+    // Flag sets such as O_RDONLY|O_CLOEXEC
+    if arg.contains('|') {
+        return arg.split('|').map(|part| parse_value(part.trim())).try_fold(0u64, |acc, v| Some(acc | v?));
+    }
+
+    if let Some(hex) = arg.strip_prefix("0x") {
+        return u64::from_str_radix(hex, 16).ok();
+    }
+    // File modes are traced in octal
+    if arg.len() > 1 && arg.starts_with('0') && arg.chars().all(|c| c.is_ascii_digit()) {
+        return u64::from_str_radix(&arg[1..], 8).ok();
+    }
+    if let Ok(value) = arg.parse::<i64>() {
+        return Some(value as u64);
+    }
+
+    let value: i64 = match arg {
+        "NULL" => 0,
+        "AT_FDCWD" => libc::AT_FDCWD as i64,
+        "AF_UNIX" => libc::AF_UNIX as i64,
+        "AF_INET" => libc::AF_INET as i64,
+        "AF_INET6" => libc::AF_INET6 as i64,
+        "AF_NETLINK" => libc::AF_NETLINK as i64,
+        "AF_PACKET" => libc::AF_PACKET as i64,
+        "SOCK_STREAM" => libc::SOCK_STREAM as i64,
+        "SOCK_DGRAM" => libc::SOCK_DGRAM as i64,
+        "SOCK_RAW" => libc::SOCK_RAW as i64,
+        "SOCK_NONBLOCK" => libc::SOCK_NONBLOCK as i64,
+        "SOCK_CLOEXEC" => libc::SOCK_CLOEXEC as i64,
+        "O_RDONLY" => libc::O_RDONLY as i64,
+        "O_WRONLY" => libc::O_WRONLY as i64,
+        "O_RDWR" => libc::O_RDWR as i64,
+        "O_CREAT" => libc::O_CREAT as i64,
+        "O_EXCL" => libc::O_EXCL as i64,
+        "O_TRUNC" => libc::O_TRUNC as i64,
+        "O_APPEND" => libc::O_APPEND as i64,
+        "O_NONBLOCK" => libc::O_NONBLOCK as i64,
+        "O_DIRECTORY" => libc::O_DIRECTORY as i64,
+        "O_CLOEXEC" => libc::O_CLOEXEC as i64,
+        "PROT_NONE" => libc::PROT_NONE as i64,
+        "PROT_READ" => libc::PROT_READ as i64,
+        "PROT_WRITE" => libc::PROT_WRITE as i64,
+        "PROT_EXEC" => libc::PROT_EXEC as i64,
+        "PTRACE_TRACEME" => libc::PTRACE_TRACEME as i64,
+        "PTRACE_ATTACH" => libc::PTRACE_ATTACH as i64,
+        "PTRACE_SEIZE" => libc::PTRACE_SEIZE as i64,
+        "PTRACE_POKETEXT" => libc::PTRACE_POKETEXT as i64,
+        "PTRACE_POKEDATA" => libc::PTRACE_POKEDATA as i64,
+        _ => return None,
+    };
+
+    Some(value as u64)
+}
+
+// ============================================================================
+// Learning
+// ============================================================================
+
+pub enum LearnError {
+    Submit(sandbox::SubmitError),
+    NothingObserved,
+    BuiltinName,
+}
+
+/// Runs the request unconfined `iterations` times and turns everything the
+/// completed runs did into an allowlist.
+pub async fn learn_policy(state: &AppState, req: LearnPolicyRequest) -> Result<LearnedPolicy, LearnError> {
+    let save = req.save.unwrap_or(false);
+    if save && state.syscall_policies.lock().unwrap().get(&req.name).is_some_and(|p| p.builtin) {
+        return Err(LearnError::BuiltinName);
+    }
+
+    let mut runs = Vec::new();
+    for _ in 0..req.iterations.unwrap_or(1).clamp(1, MAX_LEARNING_RUNS) {
+        let mut run_req = req.run.clone();
+        run_req.syscall_policy = None;
+        runs.push(sandbox::submit_run(state, run_req).await.map_err(LearnError::Submit)?);
+    }
+
+    let completed: Vec<SandboxRun> = runs
+        .iter()
+        .filter(|run| matches!(run.status, RunStatus::Completed) && !run.syscall_counts.is_empty())
+        .cloned()
+        .collect();
+    if completed.is_empty() {
+        return Err(LearnError::NothingObserved);
+    }
+
+    let description = req
+        .description
+        .unwrap_or_else(|| format!("Learned from {} run(s)", completed.len()));
+    let policy = learn(&req.name, &description, &completed);
+
+    if save {
+        state.syscall_policies.lock().unwrap().insert(policy.name.clone(), policy.clone());
+    }
+
+    logger::add_log(
+        &state.logs,
+        LogLevel::Info,
+        LogSource::Sandbox,
+        format!("Learned syscall policy {} from {} run(s)", policy.name, completed.len()),
+        Some(json!({
+            "policy": policy.name,
+            "syscalls": policy.rules.iter().map(|rule| rule.syscalls.len()).sum::<usize>(),
+            "saved": save
+        })),
+    );
+
+    Ok(LearnedPolicy {
+        policy,
+        run_ids: runs.iter().map(|run| run.id).collect(),
+        learned_from: completed.iter().map(|run| run.id).collect(),
+        saved: save,
+    })
+}
+
+/// Builds the smallest allowlist that permits every call the runs made. Syscalls in
+/// `PINNED_ARGS` are narrowed to the argument values seen, but only when every call
+/// to them is in the (capped) log and the values were all decodable.
+pub fn learn(name: &str, description: &str, runs: &[SandboxRun]) -> SyscallPolicy {
+    let mut counts: BTreeMap<&str, u64> = BTreeMap::new();
+    let mut logged: BTreeMap<&str, u64> = BTreeMap::new();
+    let mut values: BTreeMap<&str, BTreeSet<Option<u64>>> = BTreeMap::new();
+
+    for run in runs {
+        for (syscall, count) in &run.syscall_counts {
+            *counts.entry(syscall).or_default() += count;
+        }
+        for entry in &run.syscall_log {
+            *logged.entry(&entry.syscall).or_default() += 1;
+            if let Some(index) = pinned_arg(&entry.syscall) {
+                let value = parse_args(&entry.args).get(index as usize).copied().flatten();
+                values.entry(&entry.syscall).or_default().insert(value);
+            }
+        }
+    }
+
+    let mut unconditional = Vec::new();
+    let mut pinned = Vec::new();
+
+    for (syscall, count) in &counts {
+        let seen = values.get(syscall).filter(|seen| {
+            logged.get(syscall) == Some(count) && seen.len() <= MAX_PINNED_VALUES && seen.iter().all(Option::is_some)
+        });
+
+        match (pinned_arg(syscall), seen) {
+            (Some(index), Some(seen)) => {
+                for value in seen.iter().flatten() {
+                    pinned.push(SyscallRule {
+                        name: Some(format!("learned-{}", syscall)),
+                        syscalls: vec![syscall.to_string()],
+                        conditions: vec![ArgCondition {
+                            index,
+                            op: ConditionOp::Equal,
+                            value: *value,
+                            value_two: None,
+                        }],
+                        action: PolicyAction::Allow,
+                    });
+                }
+            }
+            _ => unconditional.push(syscall.to_string()),
+        }
+    }
+
+    let mut rules = Vec::new();
+    if !unconditional.is_empty() {
+        rules.push(SyscallRule {
+            name: Some("learned".to_string()),
+            syscalls: unconditional,
+            conditions: vec![],
+            action: PolicyAction::Allow,
+        });
+    }
+    rules.extend(pinned);
+
+    SyscallPolicy {
+        name: name.to_string(),
+        description: description.to_string(),
+        mode: PolicyMode::Allowlist,
+        default_action: None,
+        rules,
+        builtin: false,
+        updated_at: Utc::now(),
+    }
+}
+
+fn pinned_arg(syscall: &str) -> Option<u8> {
+    PINNED_ARGS.iter().find(|(name, _)| *name == syscall).map(|(_, index)| *index)
+}
+
+// ============================================================================
+// OCI / Docker seccomp export
+// ============================================================================
+
+/// Renders a policy in the OCI runtime-spec / Docker seccomp profile format.
+/// This is the profile handed to `aegis-sandbox --seccomp`.
+pub fn to_oci_seccomp(policy: &SyscallPolicy) -> Value {
+    let (default_action, default_errno) = oci_action(&default_action(policy));
+
+    let syscalls: Vec<Value> = policy
+        .rules
+        .iter()
+        .map(|rule| {
+            let (action, errno) = oci_action(&rule.action);
+            let mut entry = json!({
+                "names": rule.syscalls,
+                "action": action,
+                "args": rule.conditions.iter().map(|condition| json!({
+                    "index": condition.index,
+                    "value": condition.value,
+                    "valueTwo": condition.value_two.unwrap_or(0),
+                    "op": oci_op(&condition.op),
+                })).collect::<Vec<_>>(),
+            });
+            if let Some(errno) = errno {
+                entry["errnoRet"] = json!(errno);
+            }
+            entry
+        })
+        .collect();
+
+    let mut profile = json!({
+        "defaultAction": default_action,
+        "architectures": oci_architectures(),
+        "syscalls": syscalls,
+    });
+    if let Some(errno) = default_errno {
+        profile["defaultErrnoRet"] = json!(errno);
+    }
+
+    profile
+}
+
+fn oci_action(action: &PolicyAction) -> (&'static str, Option<i32>) {
+    match action {
+        PolicyAction::Allow => ("SCMP_ACT_ALLOW", None),
+        PolicyAction::Log => ("SCMP_ACT_LOG", None),
+        PolicyAction::Errno(errno) => ("SCMP_ACT_ERRNO", Some(*errno)),
+        PolicyAction::Kill => ("SCMP_ACT_KILL_PROCESS", None),
+    }
+}
+
+fn oci_op(op: &ConditionOp) -> &'static str {
+    match op {
+        ConditionOp::Equal => "SCMP_CMP_EQ",
+        ConditionOp::NotEqual => "SCMP_CMP_NE",
+        ConditionOp::LessThan => "SCMP_CMP_LT",
+        ConditionOp::LessOrEqual => "SCMP_CMP_LE",
+        ConditionOp::GreaterThan => "SCMP_CMP_GT",
+        ConditionOp::GreaterOrEqual => "SCMP_CMP_GE",
+        ConditionOp::MaskedEqual => "SCMP_CMP_MASKED_EQ",
+    }
+}
+
+fn oci_architectures() -> Vec<&'static str> {
+    match std::env::consts::ARCH {
+        "x86_64" => vec!["SCMP_ARCH_X86_64", "SCMP_ARCH_X86", "SCMP_ARCH_X32"],
+        "aarch64" => vec!["SCMP_ARCH_AARCH64", "SCMP_ARCH_ARM"],
+        _ => vec![],
+    }
+}
+
+// ============================================================================
+// Built-in Policies
+// ============================================================================
+
+pub fn builtin_policies() -> Vec<SyscallPolicy> {
+    let rule = |name: &str, syscalls: &[&str], conditions: Vec<ArgCondition>, action: PolicyAction| SyscallRule {
+        name: Some(name.to_string()),
+        syscalls: syscalls.iter().map(|s| s.to_string()).collect(),
+        conditions,
+        action,
+    };
+
+    vec![SyscallPolicy {
+        name: "deny-dangerous".to_string(),
+        description: "Blocks kernel, mount and debugging interfaces; kills on raw packet sockets".to_string(),
+        mode: PolicyMode::Denylist,
+        default_action: None,
+        rules: vec![
+            rule(
+                "no-kernel-modules",
+                &["init_module", "finit_module", "delete_module", "kexec_load", "kexec_file_load"],
+                vec![],
+                PolicyAction::Kill,
+            ),
+            rule(
+                "no-mounts",
+                &["mount", "umount2", "pivot_root", "swapon", "swapoff"],
+                vec![],
+                PolicyAction::Errno(libc::EPERM),
+            ),
+            rule("no-ptrace", &["ptrace", "process_vm_writev"], vec![], PolicyAction::Errno(libc::EPERM)),
+            rule("no-reboot", &["reboot"], vec![], PolicyAction::Kill),
+            rule(
+                "no-packet-sockets",
+                &["socket"],
+                vec![ArgCondition {
+                    index: 0,
+                    op: ConditionOp::Equal,
+                    value: libc::AF_PACKET as u64,
+                    value_two: None,
+                }],
+                PolicyAction::Kill,
+            ),
+            rule("log-exec", &["execve", "execveat"], vec![], PolicyAction::Log),
+        ],
+        builtin: true,
+        updated_at: Utc::now(),
+    }]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, syscall: &str, conditions: Vec<ArgCondition>, action: PolicyAction) -> SyscallRule {
+        SyscallRule {
+            name: Some(name.to_string()),
+            syscalls: vec![syscall.to_string()],
+            conditions,
+            action,
+        }
+    }
+
+    fn policy(mode: PolicyMode, rules: Vec<SyscallRule>) -> SyscallPolicy {
+        SyscallPolicy {
+            name: "test".to_string(),
+            description: String::new(),
+            mode,
+            default_action: None,
+            rules,
+            builtin: false,
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn entry(syscall: &str, args: &str, result: &str, pid: u32) -> SyscallEntry {
+        SyscallEntry {
+            syscall: syscall.to_string(),
+            args: args.to_string(),
+            result: result.to_string(),
+            timestamp: Utc::now(),
+            allowed: true,
+            matched_rule: None,
+            pid: Some(pid),
+        }
+    }
+
+    #[test]
+    fn parses_traced_arguments() {
+        let args = parse_args("AT_FDCWD, \"/etc/a, b\", O_RDONLY|O_CLOEXEC, 0644");
+        assert_eq!(
+            args,
+            vec![
+                Some(libc::AT_FDCWD as i64 as u64),
+                None,
+                Some((libc::O_RDONLY | libc::O_CLOEXEC) as u64),
+                Some(0o644),
+            ]
+        );
+
+        // Commas inside structures and arrays don't split arguments
+        let args = parse_args("3, {st_mode=S_IFREG|0644, st_size=12}, [1, 2], 0x7ffc0000, -1, NULL");
+        assert_eq!(args, vec![Some(3), None, None, Some(0x7ffc0000), Some(u64::MAX), Some(0)]);
+
+        // One unknown flag makes the whole set unknown rather than a wrong value
+        assert_eq!(parse_args("O_RDONLY|O_MADEUP"), vec![None]);
+        assert!(parse_args("").is_empty());
+    }
+
+    #[test]
+    fn most_restrictive_matching_rule_wins() {
+        let socket_raw = ArgCondition {
+            index: 1,
+            op: ConditionOp::Equal,
+            value: libc::SOCK_RAW as u64,
+            value_two: None,
+        };
+        let policy = policy(
+            PolicyMode::Denylist,
+            vec![
+                rule("allow-socket", "socket", vec![], PolicyAction::Allow),
+                rule("log-socket", "socket", vec![], PolicyAction::Log),
+                rule("kill-raw", "socket", vec![socket_raw], PolicyAction::Kill),
+                rule("deny-socket", "socket", vec![], PolicyAction::Errno(libc::EACCES)),
+            ],
+        );
+
+        // Kill only matches raw sockets; a later errno rule still beats earlier allow and log rules
+        let decision = evaluate(&policy, "socket", &parse_args("AF_INET, SOCK_STREAM, 0"));
+        assert_eq!(decision.action, PolicyAction::Errno(libc::EACCES));
+        assert_eq!(decision.rule, "deny-socket");
+
+        let decision = evaluate(&policy, "socket", &parse_args("AF_INET, SOCK_RAW, 0"));
+        assert_eq!(decision.action, PolicyAction::Kill);
+        assert_eq!(decision.rule, "kill-raw");
+
+        let decision = evaluate(&policy, "read", &parse_args("3, \"\", 0"));
+        assert_eq!(decision.action, PolicyAction::Allow);
+        assert_eq!(decision.rule, "default");
+
+        let allowlist = self::policy(PolicyMode::Allowlist, vec![]);
+        assert_eq!(evaluate(&allowlist, "read", &[]).action, PolicyAction::Errno(libc::EPERM));
+    }
+
+    #[test]
+    fn allowed_comes_from_the_traced_result() {
+        let policy = policy(
+            PolicyMode::Denylist,
+            vec![
+                rule("deny-unlink", "unlink", vec![], PolicyAction::Errno(libc::EPERM)),
+                rule("kill-ptrace", "ptrace", vec![], PolicyAction::Kill),
+            ],
+        );
+        let mut syscalls = vec![
+            entry("unlink", "\"/tmp/a\"", "-1 EPERM (Operation not permitted)", 100),
+            entry("unlink", "\"/tmp/b\"", "0", 100),
+            entry("ptrace", "PTRACE_TRACEME", "?", 101),
+            entry("ptrace", "PTRACE_TRACEME", "?", 100),
+        ];
+        let processes: Vec<ProcessNode> = serde_json::from_value(json!([{
+            "pid": 101, "parent_pid": 100, "argv": [], "executable": null,
+            "started_at": Utc::now(), "ended_at": null, "exit_code": null,
+            "signal": libc::SIGSYS, "core_dumped": false,
+            "resource_usage": { "syscalls_count": 1, "user_time_ms": null, "system_time_ms": null, "max_rss_kb": null }
+        }]))
+        .unwrap();
+
+        let violations = apply(&policy, &mut syscalls, &processes);
+
+        assert!(!syscalls[0].allowed);
+        // Denied by the policy, but the trace shows the unlink succeeded
+        assert!(syscalls[1].allowed);
+        assert_eq!(syscalls[1].matched_rule.as_deref(), Some("deny-unlink"));
+        assert!(!syscalls[2].allowed);
+        // Process 100 was not killed, so its unfinished ptrace was not the filter's doing
+        assert!(syscalls[3].allowed);
+
+        let unlink = violations.iter().find(|v| v.syscall == "unlink").unwrap();
+        assert_eq!((unlink.count, unlink.not_enforced), (2, 1));
+        let ptrace = violations.iter().find(|v| v.syscall == "ptrace").unwrap();
+        assert_eq!((ptrace.count, ptrace.not_enforced), (2, 1));
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+pub enum TerminalInput {
+    Data(Vec<u8>),
+    Resize { cols: u16, rows: u16 },
+}
+
+pub enum TerminalEvent {
+    Started { run_id: Uuid },
+    Output(Vec<u8>),
+}
+
+/// The client's end of an interactive run: keystrokes and resizes come in, the
+/// target's terminal output goes out. Dropping the input sender ends the session.
+pub struct Terminal {
+    pub input: mpsc::Receiver<TerminalInput>,
+    pub events: mpsc::Sender<TerminalEvent>,
+    pub cols: u16,
+    pub rows: u16,
+}
+
+pub struct Pty {
+    pub master: AsyncFd<OwnedFd>,
+    pub slave: OwnedFd,
+}
+
+pub fn open(cols: u16, rows: u16) -> io::Result<Pty> {
+    let (mut master, mut slave) = (0, 0);
+    let size = window_size(cols, rows);
+    let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    // Only the copies installed as the target's stdio may survive exec
+    set_flags(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC)?;
+    set_flags(slave.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC)?;
+    set_flags(master.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK)?;
+
+    Ok(Pty {
+        master: AsyncFd::new(master)?,
+        slave,
+    })
+}
+
+pub fn resize(master: &AsyncFd<OwnedFd>, cols: u16, rows: u16) -> io::Result<()> {
+    let size = window_size(cols, rows);
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Runs in the child before exec: a new session whose controlling terminal is the
+/// pty on stdin, so ^C and ^Z reach the target's foreground process group.
+pub fn make_controlling_terminal() -> io::Result<()> {
+    unsafe {
+        if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Ignored signals survive exec; a backend started in the background would pass on SIGINT and SIGQUIT
+        for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU] {
+            libc::signal(signal, libc::SIG_DFL);
+        }
+    }
+    Ok(())
+}
+
+/// Reads terminal output. Returns 0 once every process holding the slave has gone,
+/// which Linux reports as EIO.
+pub async fn read(master: &AsyncFd<OwnedFd>, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let mut guard = master.readable().await?;
+        match guard.try_io(|fd| cvt(unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) })) {
+            Ok(Err(e)) if e.raw_os_error() == Some(libc::EIO) => return Ok(0),
+            Ok(result) => return result,
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+pub async fn write_all(master: &AsyncFd<OwnedFd>, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        let mut guard = master.writable().await?;
+        match guard.try_io(|fd| cvt(unsafe { libc::write(fd.as_raw_fd(), data.as_ptr().cast(), data.len()) })) {
+            Ok(written) => data = &data[written?..],
+            Err(_would_block) => continue,
+        }
+    }
+    Ok(())
+}
+
+fn window_size(cols: u16, rows: u16) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn set_flags(fd: RawFd, command: libc::c_int, flag: libc::c_int) -> io::Result<()> {
+    let get = if command == libc::F_SETFD { libc::F_GETFD } else { libc::F_GETFL };
+    unsafe {
+        let flags = libc::fcntl(fd, get);
+        if flags < 0 || libc::fcntl(fd, command, flags | flag) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn cvt(result: isize) -> io::Result<usize> {
+    if result < 0 { Err(io::Error::last_os_error()) } else { Ok(result as usize) }
+}
@@ -0,0 +1,264 @@
+use crate::models::RunPriority;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    pub max_concurrent: usize,
+    pub max_per_project: usize,
+    pub max_queued: usize,
+}
+
+impl QueueConfig {
+    pub fn from_env() -> Self {
+        let read = |name: &str, default: usize| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .filter(|value| *value > 0)
+                .unwrap_or(default)
+        };
+
+        Self {
+            max_concurrent: read("AEGIS_MAX_CONCURRENT_RUNS", 4),
+            max_per_project: read("AEGIS_MAX_RUNS_PER_PROJECT", 2),
+            max_queued: read("AEGIS_MAX_QUEUED_RUNS", 64),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct QueueFull {
+    pub queued: usize,
+}
+
+struct QueuedJob {
+    run_id: Uuid,
+    project_id: Option<Uuid>,
+    priority: RunPriority,
+}
+
+#[derive(Default)]
+struct QueueState {
+    // Kept in dispatch order: highest priority first, then first come first served
+    waiting: Vec<QueuedJob>,
+    running: HashMap<Uuid, Option<Uuid>>,
+}
+
+enum Dispatch {
+    Started,
+    Waiting,
+    Removed,
+}
+
+/// Bounded queue in front of the sandbox. Runs wait here until a global slot and a
+/// slot for their project are free; once the waiting list is full, new runs are rejected.
+pub struct SandboxQueue {
+    config: QueueConfig,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+impl SandboxQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn config(&self) -> &QueueConfig {
+        &self.config
+    }
+
+    pub fn enqueue(&self, run_id: Uuid, project_id: Option<Uuid>, priority: RunPriority) -> Result<(), QueueFull> {
+        let mut state = self.state.lock().unwrap();
+        if state.waiting.len() >= self.config.max_queued {
+            return Err(QueueFull { queued: state.waiting.len() });
+        }
+
+        let index = state
+            .waiting
+            .iter()
+            .position(|job| job.priority < priority)
+            .unwrap_or(state.waiting.len());
+        state.waiting.insert(index, QueuedJob { run_id, project_id, priority });
+
+        Ok(())
+    }
+
+    /// Waits until the run may start. Returns false if it was removed from the queue first.
+    pub async fn wait_for_slot(&self, run_id: Uuid) -> bool {
+        loop {
+            let notified = self.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            match self.try_dispatch(run_id) {
+                Dispatch::Started => {
+                    // Capacity may remain for the runs queued behind this one
+                    self.notify.notify_waiters();
+                    return true;
+                }
+                Dispatch::Removed => return false,
+                Dispatch::Waiting => {}
+            }
+
+            notified.await;
+        }
+    }
+
+    fn try_dispatch(&self, run_id: Uuid) -> Dispatch {
+        let mut state = self.state.lock().unwrap();
+        if !state.waiting.iter().any(|job| job.run_id == run_id) {
+            return Dispatch::Removed;
+        }
+        if state.running.len() >= self.config.max_concurrent {
+            return Dispatch::Waiting;
+        }
+
+        // Skip over runs whose project is at its limit so they don't block other projects
+        let next = state.waiting.iter().position(|job| match job.project_id {
+            Some(project) => {
+                state.running.values().filter(|running| **running == Some(project)).count() < self.config.max_per_project
+            }
+            None => true,
+        });
+
+        match next {
+            Some(index) if state.waiting[index].run_id == run_id => {
+                let job = state.waiting.remove(index);
+                state.running.insert(job.run_id, job.project_id);
+                Dispatch::Started
+            }
+            _ => Dispatch::Waiting,
+        }
+    }
+
+    pub fn release(&self, run_id: Uuid) {
+        self.state.lock().unwrap().running.remove(&run_id);
+        self.notify.notify_waiters();
+    }
+
+    /// Drops a run that has not started yet. Returns false if it is not waiting.
+    pub fn remove_waiting(&self, run_id: Uuid) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.waiting.len();
+        state.waiting.retain(|job| job.run_id != run_id);
+        let removed = state.waiting.len() != before;
+        drop(state);
+
+        if removed {
+            self.notify.notify_waiters();
+        }
+        removed
+    }
+
+    /// 1-based position of a waiting run, `None` once it has started.
+    pub fn position(&self, run_id: Uuid) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        state.waiting.iter().position(|job| job.run_id == run_id).map(|index| index + 1)
+    }
+
+    pub fn queued_count(&self) -> usize {
+        self.state.lock().unwrap().waiting.len()
+    }
+
+    pub fn running_count(&self) -> usize {
+        self.state.lock().unwrap().running.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_concurrent: usize, max_per_project: usize, max_queued: usize) -> SandboxQueue {
+        SandboxQueue::new(QueueConfig { max_concurrent, max_per_project, max_queued })
+    }
+
+    #[test]
+    fn orders_by_priority_then_arrival() {
+        let queue = queue(1, 1, 8);
+        let (first, second, urgent, background) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        queue.enqueue(first, None, RunPriority::Normal).unwrap();
+        queue.enqueue(background, None, RunPriority::Low).unwrap();
+        queue.enqueue(second, None, RunPriority::Normal).unwrap();
+        queue.enqueue(urgent, None, RunPriority::High).unwrap();
+
+        assert_eq!(queue.position(urgent), Some(1));
+        assert_eq!(queue.position(first), Some(2));
+        assert_eq!(queue.position(second), Some(3));
+        assert_eq!(queue.position(background), Some(4));
+    }
+
+    #[test]
+    fn only_the_head_of_the_queue_starts_and_only_while_slots_are_free() {
+        let queue = queue(1, 1, 8);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        queue.enqueue(first, None, RunPriority::Normal).unwrap();
+        queue.enqueue(second, None, RunPriority::Normal).unwrap();
+
+        assert!(matches!(queue.try_dispatch(second), Dispatch::Waiting));
+        assert!(matches!(queue.try_dispatch(first), Dispatch::Started));
+        assert!(matches!(queue.try_dispatch(second), Dispatch::Waiting));
+        assert_eq!((queue.running_count(), queue.position(first)), (1, None));
+
+        queue.release(first);
+        assert!(matches!(queue.try_dispatch(second), Dispatch::Started));
+    }
+
+    #[test]
+    fn a_project_at_its_limit_does_not_block_other_projects() {
+        let queue = queue(4, 1, 8);
+        let project = Some(Uuid::new_v4());
+        let (running, blocked, other) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        queue.enqueue(running, project, RunPriority::Normal).unwrap();
+        queue.enqueue(blocked, project, RunPriority::Normal).unwrap();
+        queue.enqueue(other, None, RunPriority::Normal).unwrap();
+
+        assert!(matches!(queue.try_dispatch(running), Dispatch::Started));
+        assert!(matches!(queue.try_dispatch(blocked), Dispatch::Waiting));
+        assert!(matches!(queue.try_dispatch(other), Dispatch::Started));
+
+        queue.release(running);
+        assert!(matches!(queue.try_dispatch(blocked), Dispatch::Started));
+    }
+
+    #[test]
+    fn rejects_runs_once_the_waiting_list_is_full() {
+        let queue = queue(1, 1, 2);
+        let waiting = Uuid::new_v4();
+        queue.enqueue(waiting, None, RunPriority::Normal).unwrap();
+        queue.enqueue(Uuid::new_v4(), None, RunPriority::Normal).unwrap();
+        assert!(matches!(queue.enqueue(Uuid::new_v4(), None, RunPriority::High), Err(QueueFull { queued: 2 })));
+
+        assert!(queue.remove_waiting(waiting));
+        assert!(!queue.remove_waiting(waiting));
+        assert!(matches!(queue.try_dispatch(waiting), Dispatch::Removed));
+        assert!(queue.enqueue(Uuid::new_v4(), None, RunPriority::Normal).is_ok());
+    }
+
+    #[tokio::test]
+    async fn waiting_runs_start_when_a_slot_is_released() {
+        let queue = std::sync::Arc::new(queue(1, 1, 8));
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        queue.enqueue(first, None, RunPriority::Normal).unwrap();
+        queue.enqueue(second, None, RunPriority::Normal).unwrap();
+        assert!(queue.wait_for_slot(first).await);
+
+        let waiter = tokio::spawn({
+            let queue = queue.clone();
+            async move { queue.wait_for_slot(second).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        queue.release(first);
+        assert!(waiter.await.unwrap());
+        assert_eq!(queue.running_count(), 1);
+    }
+}
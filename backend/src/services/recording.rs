@@ -0,0 +1,195 @@
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Size of the virtual terminal a piped run is played back in
+pub const DEFAULT_WIDTH: u16 = 80;
+pub const DEFAULT_HEIGHT: u16 = 24;
+
+// Event data kept per recording; a run that prints more is cut off at this point
+const MAX_RECORDED_BYTES: usize = 16 << 20;
+const TRUNCATED_NOTICE: &str = "\r\n[recording truncated]\r\n";
+
+pub type SharedRecording = Arc<Mutex<Recording>>;
+
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Stdout = 0,
+    Stderr = 1,
+}
+
+/// Everything a run's terminal showed, with the time it showed it, kept for an
+/// asciicast v2 recording. Output of piped runs has its newlines turned into
+/// CRLF, which a real terminal's line discipline would have done. Recording
+/// stops after `MAX_RECORDED_BYTES`, with a notice in the cast and a `truncated`
+/// header field.
+pub struct Recording {
+    started: Instant,
+    timestamp: i64,
+    width: u16,
+    height: u16,
+    piped: bool,
+    events: Vec<(f64, &'static str, String)>,
+    recorded: usize,
+    truncated: bool,
+    // Trailing bytes of a UTF-8 sequence split across reads
+    pending: [Vec<u8>; 2],
+}
+
+impl Recording {
+    pub fn piped() -> SharedRecording {
+        Self::new(DEFAULT_WIDTH, DEFAULT_HEIGHT, true)
+    }
+
+    pub fn terminal(width: u16, height: u16) -> SharedRecording {
+        Self::new(width, height, false)
+    }
+
+    fn new(width: u16, height: u16, piped: bool) -> SharedRecording {
+        Arc::new(Mutex::new(Self {
+            started: Instant::now(),
+            timestamp: chrono::Utc::now().timestamp(),
+            width,
+            height,
+            piped,
+            events: Vec::new(),
+            recorded: 0,
+            truncated: false,
+            pending: [Vec::new(), Vec::new()],
+        }))
+    }
+
+    pub fn output(&mut self, stream: Stream, bytes: &[u8]) {
+        if self.truncated {
+            return;
+        }
+        let pending = &mut self.pending[stream as usize];
+        pending.extend_from_slice(bytes);
+
+        let complete = match std::str::from_utf8(pending) {
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            _ => pending.len(),
+        };
+        let rest = pending.split_off(complete);
+        let text = String::from_utf8_lossy(&std::mem::replace(pending, rest)).to_string();
+
+        if !text.is_empty() {
+            let text = if self.piped { text.replace('\n', "\r\n") } else { text };
+            self.push("o", text);
+        }
+    }
+
+    pub fn input(&mut self, bytes: &[u8]) {
+        self.push("i", String::from_utf8_lossy(bytes).to_string());
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.push("r", format!("{}x{}", cols, rows));
+    }
+
+    fn push(&mut self, code: &'static str, data: String) {
+        if self.truncated {
+            return;
+        }
+        let time = self.started.elapsed().as_secs_f64();
+        if self.recorded + data.len() > MAX_RECORDED_BYTES {
+            self.truncated = true;
+            self.events.push((time, "o", TRUNCATED_NOTICE.to_string()));
+            return;
+        }
+        self.recorded += data.len();
+        self.events.push((time, code, data));
+    }
+
+    /// The recording as an asciicast v2 file: a header line, then one JSON array per event.
+    pub fn to_asciicast(&self) -> String {
+        let mut header = json!({
+            "version": 2,
+            "width": self.width,
+            "height": self.height,
+            "timestamp": self.timestamp,
+            "env": { "TERM": "xterm-256color" }
+        });
+        // Players ignore header fields they don't know
+        if self.truncated {
+            header["truncated"] = json!(true);
+        }
+
+        let mut cast = header.to_string();
+        cast.push('\n');
+        for (time, code, data) in &self.events {
+            cast.push_str(&json!([(time * 1e6).round() / 1e6, code, data]).to_string());
+            cast.push('\n');
+        }
+        cast
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn lines(recording: &SharedRecording) -> Vec<Value> {
+        let cast = recording.lock().unwrap().to_asciicast();
+        cast.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn piped_output_is_recorded_with_crlf_and_whole_characters() {
+        let recording = Recording::piped();
+        {
+            let mut recording = recording.lock().unwrap();
+            let snowman = "☃".as_bytes();
+            recording.output(Stream::Stdout, b"hi\n");
+            recording.output(Stream::Stderr, &snowman[..1]);
+            recording.output(Stream::Stdout, b"ok\n");
+            recording.output(Stream::Stderr, &snowman[1..]);
+        }
+
+        let lines = lines(&recording);
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!((lines[0]["width"].as_u64(), lines[0]["height"].as_u64()), (Some(80), Some(24)));
+        let data: Vec<&str> = lines[1..].iter().map(|event| event[2].as_str().unwrap()).collect();
+        assert_eq!(data, ["hi\r\n", "ok\r\n", "☃"]);
+    }
+
+    #[test]
+    fn terminal_output_is_kept_as_is_with_input_and_resizes() {
+        let recording = Recording::terminal(120, 40);
+        {
+            let mut recording = recording.lock().unwrap();
+            recording.output(Stream::Stdout, b"$ ");
+            recording.input(b"ls\r");
+            recording.resize(100, 30);
+            recording.output(Stream::Stdout, b"a\nb\n");
+        }
+
+        let events: Vec<(String, String)> = lines(&recording)[1..]
+            .iter()
+            .map(|event| (event[1].as_str().unwrap().to_string(), event[2].as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(
+            events,
+            [("o", "$ "), ("i", "ls\r"), ("r", "100x30"), ("o", "a\nb\n")].map(|(code, data)| (code.to_string(), data.to_string()))
+        );
+    }
+
+    #[test]
+    fn stops_recording_past_the_limit() {
+        let recording = Recording::piped();
+        {
+            let mut recording = recording.lock().unwrap();
+            let chunk = vec![b'x'; 1 << 20];
+            for _ in 0..17 {
+                recording.output(Stream::Stdout, &chunk);
+            }
+            recording.output(Stream::Stdout, b"after");
+        }
+
+        let lines = lines(&recording);
+        assert_eq!(lines[0]["truncated"], true);
+        assert_eq!(lines.len(), 1 + 16 + 1);
+        assert_eq!(lines.last().unwrap()[2], TRUNCATED_NOTICE);
+    }
+}
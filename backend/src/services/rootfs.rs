@@ -0,0 +1,568 @@
+use crate::models::{RootfsFormat, RootfsImage, RunStatus};
+use crate::services::artifacts::sha256_file;
+use crate::services::{get_data_dir, store, AppState};
+use chrono::Utc;
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
+use uuid::Uuid;
+
+// Largest archive accepted for upload
+pub const MAX_UPLOAD_SIZE: u64 = 4 << 30;
+// What one import may unpack, counted across the layout and all of its layers, so a
+// small compressed archive cannot fill the disk
+const MAX_UNPACKED_SIZE: u64 = 16 << 30;
+const MAX_UNPACKED_ENTRIES: u64 = 1 << 20;
+
+const METADATA_FILE: &str = "image.json";
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+// Index nesting followed before giving up on finding a manifest
+const MAX_INDEX_DEPTH: usize = 4;
+
+pub enum ImportError {
+    InvalidName,
+    Exists,
+    Invalid(String),  // The archive is not a usable image
+    Storage(String),
+}
+
+/// What is left of an import's unpacking allowance.
+struct Budget {
+    bytes: u64,
+    entries: u64,
+}
+
+impl Budget {
+    fn new() -> Self {
+        Self {
+            bytes: MAX_UNPACKED_SIZE,
+            entries: MAX_UNPACKED_ENTRIES,
+        }
+    }
+
+    /// Charges an entry before it is read; the header size is what the reader will consume.
+    fn charge<R: Read>(&mut self, entry: &tar::Entry<'_, R>) -> Result<(), String> {
+        self.entries = self
+            .entries
+            .checked_sub(1)
+            .ok_or(format!("The archive has more than {} entries", MAX_UNPACKED_ENTRIES))?;
+        self.bytes = self
+            .bytes
+            .checked_sub(entry.header().entry_size().unwrap_or(u64::MAX))
+            .ok_or(format!("The archive unpacks to more than {} GiB", MAX_UNPACKED_SIZE >> 30))?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct Descriptor {
+    digest: String,
+    platform: Option<Platform>,
+}
+
+#[derive(Deserialize)]
+struct Platform {
+    os: String,
+    architecture: String,
+}
+
+// An image index or an image manifest; index.json is always the former
+#[derive(Deserialize)]
+struct IndexOrManifest {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+#[derive(Deserialize)]
+struct ImageConfig {
+    os: Option<String>,
+    architecture: Option<String>,
+}
+
+pub fn images_dir() -> PathBuf {
+    get_data_dir().join("rootfs")
+}
+
+/// The unpacked tree a run with this image pivots into. Absolute, since the data
+/// directory may be given relative to the backend's working directory.
+pub fn rootfs_path(name: &str) -> PathBuf {
+    let path = images_dir().join(name).join("rootfs");
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+/// Letters, digits, '.', '_' and '-', not starting with '.', so a name is always a
+/// single path component and never collides with an import in progress.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && !name.starts_with('.')
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+pub fn get_image(name: &str) -> Option<RootfsImage> {
+    if !valid_name(name) {
+        return None;
+    }
+    let text = fs::read_to_string(images_dir().join(name).join(METADATA_FILE)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+pub fn list_images() -> Vec<RootfsImage> {
+    let Ok(entries) = fs::read_dir(images_dir()) else {
+        return vec![];
+    };
+
+    let mut images: Vec<RootfsImage> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| get_image(&entry.file_name().to_string_lossy()))
+        .collect();
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+    images
+}
+
+/// Pins an image for a run, or returns None if there is no such image.
+pub fn pin(pins: &store::Pins, name: &str) -> Option<store::Pin> {
+    store::hold(pins, name, || get_image(name).is_some())
+}
+
+/// Refuses while a queued or running run still needs the image.
+pub fn delete_image(state: &AppState, name: &str) -> std::io::Result<store::Deletion> {
+    let pins = state.rootfs_pins.lock().unwrap();
+    let in_use = state.sandbox_runs.lock().unwrap().values().find_map(|run| {
+        (matches!(run.status, RunStatus::Pending | RunStatus::Running) && run.request.rootfs.as_deref() == Some(name))
+            .then_some(run.id)
+    });
+    if let Some(run_id) = in_use {
+        return Ok(store::Deletion::InUse(format!("run {}", run_id)));
+    }
+    if pins.contains_key(name) {
+        return Ok(store::Deletion::InUse("a queued run".to_string()));
+    }
+
+    if get_image(name).is_none() {
+        return Ok(store::Deletion::NotFound);
+    }
+    fs::remove_dir_all(images_dir().join(name))?;
+    Ok(store::Deletion::Deleted)
+}
+
+/// Unpacks an uploaded archive into a new image. The archive is either an OCI image
+/// layout, whose layers are applied in order with their whiteouts, or a plain root
+/// filesystem tarball. Either may be gzipped. The image is unpacked next to the
+/// others under a hidden name and only renamed into place once complete.
+pub fn import_image(name: &str, upload: &Path) -> Result<RootfsImage, ImportError> {
+    if !valid_name(name) {
+        return Err(ImportError::InvalidName);
+    }
+    let target = images_dir().join(name);
+    if target.exists() {
+        return Err(ImportError::Exists);
+    }
+
+    let staging = images_dir().join(format!(".{}-{}", name, Uuid::new_v4()));
+    let result = unpack_image(name, upload, &staging).and_then(|image| {
+        let metadata = serde_json::to_vec_pretty(&image).map_err(|e| ImportError::Storage(e.to_string()))?;
+        fs::write(staging.join(METADATA_FILE), metadata)
+            .map_err(|e| ImportError::Storage(format!("Failed to write image metadata: {}", e)))?;
+
+        // Another upload of the same name may have finished in the meantime
+        if target.exists() {
+            return Err(ImportError::Exists);
+        }
+        fs::rename(&staging, &target).map_err(|e| ImportError::Storage(format!("Failed to store image: {}", e)))?;
+        Ok(image)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    result
+}
+
+fn unpack_image(name: &str, upload: &Path, staging: &Path) -> Result<RootfsImage, ImportError> {
+    let root = staging.join("rootfs");
+    fs::create_dir_all(&root).map_err(|e| ImportError::Storage(format!("Failed to create {}: {}", root.display(), e)))?;
+
+    let digest = format!("sha256:{}", sha256_file(upload).map_err(|e| ImportError::Storage(e.to_string()))?);
+    let invalid = ImportError::Invalid;
+    let mut budget = Budget::new();
+
+    let (format, layers, config) = if is_oci_layout(upload).map_err(invalid)? {
+        let layout = staging.join("layout");
+        fs::create_dir_all(&layout).map_err(|e| ImportError::Storage(e.to_string()))?;
+        unpack_layout(&layout, upload, &mut budget).map_err(invalid)?;
+
+        let (config, layers) = read_manifest(&layout).map_err(invalid)?;
+        for layer in &layers {
+            apply_layer(&root, layer, &mut budget).map_err(invalid)?;
+        }
+        let _ = fs::remove_dir_all(&layout);
+        (RootfsFormat::OciLayout, layers.len(), Some(config))
+    } else {
+        unpack_entries(&root, upload, false, &mut budget).map_err(invalid)?;
+        (RootfsFormat::Tarball, 1, None)
+    };
+
+    let (size_bytes, file_count) = tree_size(&root);
+    if file_count == 0 {
+        return Err(ImportError::Invalid("The archive contains no files".to_string()));
+    }
+
+    Ok(RootfsImage {
+        name: name.to_string(),
+        format,
+        digest,
+        os: config.as_ref().and_then(|config| config.os.clone()),
+        architecture: config.and_then(|config| config.architecture),
+        layers,
+        size_bytes,
+        file_count,
+        created_at: Utc::now(),
+    })
+}
+
+/// Opens a tar archive, gzipped or not. Files get the archive's permission bits
+/// without setuid, setgid or sticky, and belong to the backend's user: an image
+/// is untrusted, and a backend running as root must not leave setuid-root
+/// binaries from it on the host.
+fn open_archive(path: &Path) -> Result<Archive<Box<dyn Read>>, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic).map_err(|e| e.to_string())?;
+    file.rewind().map_err(|e| e.to_string())?;
+
+    let reader: Box<dyn Read> = match &magic[..read] {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(BufReader::new(file))),
+        [0x28, 0xb5, 0x2f, 0xfd] => return Err("zstd-compressed archives are not supported".to_string()),
+        _ => Box::new(BufReader::new(file)),
+    };
+
+    let mut archive = Archive::new(reader);
+    archive.set_preserve_permissions(false);
+    archive.set_preserve_ownerships(false);
+    archive.set_unpack_xattrs(false);
+    archive.set_overwrite(true);
+    Ok(archive)
+}
+
+/// An entry's path with leading "/" and "./" removed, or None if it climbs with "..".
+fn relative_path(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// `relative` under `root`, provided no directory on the way is a symlink, so
+/// removing or replacing it cannot reach outside the image.
+fn contained(root: &Path, relative: &Path) -> Option<PathBuf> {
+    let mut path = root.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        path.push(component);
+        if components.peek().is_some() && fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            return None;
+        }
+    }
+    Some(path)
+}
+
+fn remove_path(path: &Path) {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => {
+            let _ = fs::remove_dir_all(path);
+        }
+        Ok(_) => {
+            let _ = fs::remove_file(path);
+        }
+        Err(_) => {}
+    }
+}
+
+fn is_oci_layout(path: &Path) -> Result<bool, String> {
+    let mut archive = open_archive(path)?;
+    // Only reads headers, but decompressing costs the same; it gets an allowance of its own
+    let mut budget = Budget::new();
+    let (mut layout_file, mut index) = (false, false);
+    for entry in archive.entries().map_err(|e| format!("Invalid archive: {}", e))? {
+        let entry = entry.map_err(|e| format!("Invalid archive: {}", e))?;
+        budget.charge(&entry)?;
+        let path = entry.path().ok().and_then(|path| relative_path(&path));
+        match path.as_deref().and_then(Path::to_str) {
+            Some("oci-layout") => layout_file = true,
+            Some("index.json") => index = true,
+            _ => {}
+        }
+    }
+    Ok(layout_file && index)
+}
+
+/// Follows index.json down to a single image manifest, picking the host platform
+/// from multi-platform indexes, and returns its config and layer blobs.
+fn read_manifest(layout: &Path) -> Result<(ImageConfig, Vec<PathBuf>), String> {
+    let mut document: IndexOrManifest = read_json(&layout.join("index.json"))?;
+
+    for _ in 0..MAX_INDEX_DEPTH {
+        if let Some(config) = &document.config {
+            let config: ImageConfig = read_json(&blob_path(layout, &config.digest)?)?;
+            let layers = document
+                .layers
+                .iter()
+                .map(|layer| {
+                    let path = blob_path(layout, &layer.digest)?;
+                    verify_blob(&path, &layer.digest)?;
+                    Ok(path)
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            return Ok((config, layers));
+        }
+
+        let host = host_architecture();
+        let manifest = document
+            .manifests
+            .iter()
+            .find(|m| m.platform.as_ref().is_some_and(|p| p.os == "linux" && p.architecture == host))
+            // Attestation manifests carry an "unknown" platform
+            .or_else(|| document.manifests.iter().find(|m| m.platform.as_ref().is_none_or(|p| p.os != "unknown")))
+            .ok_or("The image index lists no manifests")?;
+        document = read_json(&blob_path(layout, &manifest.digest)?)?;
+    }
+
+    Err("The image index nests too deeply".to_string())
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", name, e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Invalid {}: {}", name, e))
+}
+
+fn blob_path(layout: &Path, digest: &str) -> Result<PathBuf, String> {
+    match digest.split_once(':') {
+        Some(("sha256", hex)) if hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            Ok(layout.join("blobs").join("sha256").join(hex))
+        }
+        _ => Err(format!("Unsupported blob digest '{}'", digest)),
+    }
+}
+
+fn verify_blob(path: &Path, digest: &str) -> Result<(), String> {
+    let actual = sha256_file(path).map_err(|e| format!("Missing blob {}: {}", digest, e))?;
+    if digest.strip_prefix("sha256:") != Some(actual.as_str()) {
+        return Err(format!("Blob {} does not match its digest", digest));
+    }
+    Ok(())
+}
+
+/// The OCI name of the architecture the backend was built for.
+fn host_architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        "x86" => "386",
+        arch => arch,
+    }
+}
+
+/// Unpacks the image layout itself: index.json, oci-layout and the blobs.
+fn unpack_layout(layout: &Path, upload: &Path, budget: &mut Budget) -> Result<(), String> {
+    let mut archive = open_archive(upload)?;
+    for entry in archive.entries().map_err(|e| format!("Invalid archive: {}", e))? {
+        let mut entry = entry.map_err(|e| format!("Invalid archive: {}", e))?;
+        budget.charge(&entry)?;
+        entry
+            .unpack_in(layout)
+            .map_err(|e| format!("Failed to unpack image layout: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Applies a layer: its whiteouts delete what the layers below left, then its
+/// entries are unpacked on top, charged to the import's budget.
+fn apply_layer(root: &Path, layer: &Path, budget: &mut Budget) -> Result<(), String> {
+    let mut archive = open_archive(layer)?;
+    // Like is_oci_layout, the whiteout pass only reads headers and has its own allowance
+    let mut scan_budget = Budget::new();
+    for entry in archive.entries().map_err(|e| format!("Invalid layer: {}", e))? {
+        let entry = entry.map_err(|e| format!("Invalid layer: {}", e))?;
+        scan_budget.charge(&entry)?;
+        let Some(path) = entry.path().ok().and_then(|path| relative_path(&path)) else {
+            continue;
+        };
+        let (Some(file_name), Some(parent)) = (path.file_name().and_then(|name| name.to_str()), path.parent()) else {
+            continue;
+        };
+
+        if file_name == OPAQUE_WHITEOUT {
+            let Some(dir) = contained(root, parent) else { continue };
+            if let Ok(children) = fs::read_dir(&dir) {
+                for child in children.filter_map(|child| child.ok()) {
+                    remove_path(&child.path());
+                }
+            }
+        } else if let Some(hidden) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+            if let Some(path) = contained(root, &parent.join(hidden)) {
+                remove_path(&path);
+            }
+        }
+    }
+
+    unpack_entries(root, layer, true, budget)
+}
+
+/// Unpacks every file, directory and link, skipping device nodes and FIFOs (the
+/// sandbox provides its own /dev) and, for layers, the whiteout markers.
+fn unpack_entries(root: &Path, archive_path: &Path, skip_whiteouts: bool, budget: &mut Budget) -> Result<(), String> {
+    let mut archive = open_archive(archive_path)?;
+    for entry in archive.entries().map_err(|e| format!("Invalid archive: {}", e))? {
+        let mut entry = entry.map_err(|e| format!("Invalid archive: {}", e))?;
+        budget.charge(&entry)?;
+        let kind = entry.header().entry_type();
+        if matches!(kind, EntryType::Char | EntryType::Block | EntryType::Fifo) {
+            continue;
+        }
+
+        let Some(path) = entry.path().ok().and_then(|path| relative_path(&path)) else {
+            continue;
+        };
+        if skip_whiteouts
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(WHITEOUT_PREFIX))
+        {
+            continue;
+        }
+
+        // A directory replacing a file or the other way round; tar only replaces files with files
+        if let Some(existing) = contained(root, &path) {
+            if fs::symlink_metadata(&existing).is_ok_and(|meta| meta.is_dir() != (kind == EntryType::Directory)) {
+                remove_path(&existing);
+            }
+        }
+
+        entry
+            .unpack_in(root)
+            .map_err(|e| format!("Failed to unpack {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Total size of the regular files in a tree and the number of entries in it.
+fn tree_size(dir: &Path) -> (u64, u64) {
+    let mut total = (0, 0);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return total;
+    };
+
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let Ok(meta) = entry.path().symlink_metadata() else { continue };
+        total.1 += 1;
+        if meta.is_dir() {
+            let (size, count) = tree_size(&entry.path());
+            total.0 += size;
+            total.1 += count;
+        } else if meta.is_file() {
+            total.0 += meta.len();
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn write_layer(path: &Path, files: &[(&str, &str)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn relative_paths_never_climb() {
+        assert_eq!(relative_path(Path::new("/etc/passwd")), Some(PathBuf::from("etc/passwd")));
+        assert_eq!(relative_path(Path::new("./usr/./bin/sh")), Some(PathBuf::from("usr/bin/sh")));
+        assert_eq!(relative_path(Path::new("usr/../../etc/passwd")), None);
+        assert_eq!(relative_path(Path::new("..")), None);
+    }
+
+    #[test]
+    fn containment_stops_at_linked_directories() {
+        let dir = std::env::temp_dir().join(format!("aegis-rootfs-{}", Uuid::new_v4()));
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("etc")).unwrap();
+        symlink(&dir, root.join("escape")).unwrap();
+
+        let etc = contained(&root, Path::new("etc/passwd"));
+        let through_link = contained(&root, Path::new("escape/host"));
+        // The link itself may be replaced or removed, just not looked through
+        let link = contained(&root, Path::new("escape"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(etc, Some(root.join("etc/passwd")));
+        assert_eq!(through_link, None);
+        assert_eq!(link, Some(root.join("escape")));
+    }
+
+    #[test]
+    fn layers_apply_whiteouts_then_their_own_files() {
+        let dir = std::env::temp_dir().join(format!("aegis-rootfs-{}", Uuid::new_v4()));
+        let (root, host) = (dir.join("root"), dir.join("host"));
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::create_dir_all(root.join("opt/app/lib")).unwrap();
+        fs::create_dir_all(&host).unwrap();
+        fs::write(root.join("etc/hidden"), "lower").unwrap();
+        fs::write(root.join("etc/kept"), "lower").unwrap();
+        fs::write(root.join("opt/app/lib/old.so"), "lower").unwrap();
+        fs::write(root.join("opt/app/config"), "lower").unwrap();
+        fs::write(host.join("victim"), "host").unwrap();
+        symlink(&host, root.join("escape")).unwrap();
+
+        let layer = dir.join("layer.tar");
+        write_layer(
+            &layer,
+            &[
+                ("etc/.wh.hidden", ""),
+                ("opt/app/.wh..wh..opq", ""),
+                ("opt/app/config", "upper"),
+                ("escape/.wh.victim", ""),
+            ],
+        );
+        let result = apply_layer(&root, &layer, &mut Budget::new());
+
+        let exists = |path: &str| root.join(path).symlink_metadata().is_ok();
+        let left: Vec<&str> = ["etc/hidden", "etc/kept", "etc/.wh.hidden", "opt/app/lib", "opt/app/.wh..wh..opq"]
+            .into_iter()
+            .filter(|path| exists(path))
+            .collect();
+        let config = fs::read_to_string(root.join("opt/app/config")).ok();
+        let victim = host.join("victim").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(left, ["etc/kept"]);
+        // The opaque directory keeps the layer's own copy
+        assert_eq!(config.as_deref(), Some("upper"));
+        assert!(victim);
+    }
+}
@@ -1,65 +1,889 @@
-use crate::models::{SandboxRunRequest, SandboxRun, RunStatus, SyscallEntry, ResourceUsage};
-use crate::services::get_aegis_binary_path;
-use std::process::Command;
+use crate::models::{
+    SandboxRunRequest, SandboxRun, RunStatus, SyscallEntry, ResourceUsage, ResourceLimit, RunArtifact, ArtifactKind, FileChange,
+    ProcessNode, LogLevel, LogSource, SyscallPolicy, PolicyViolation, PolicyAction, NetworkMode, NetworkEvent, StackFrame,
+    DeterministicSettings, RunComparison, Verdict, BehaviourMatcher, SandboxBackend, BinaryInspection,
+};
+use crate::services::{
+    artifacts, compare, coredump, elf, fsdiff, get_aegis_binary_path, get_sandbox_workspace_root, logger, netemu, policy, pty,
+    recording, rootfs, store, toolchain, trace, verdict, attack, wasm, AppState,
+};
+use serde::Deserialize;
+use serde_json::json;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::task::JoinHandle;
 use uuid::Uuid;
-use chrono::Utc;
+use chrono::{TimeZone, Utc};
 
-pub async fn run_sandbox(req: SandboxRunRequest) -> SandboxRun {
-    let run_id = Uuid::new_v4();
-    let created_at = Utc::now();
+// Extra time the sandbox gets to enforce its own timeout before the backend kills it
+const TIMEOUT_GRACE: Duration = Duration::from_secs(2);
 
-    // If code is provided, compile and run it
-    let (stdout, stderr, exit_code, syscall_log, resource_usage) = if let Some(code) = &req.code {
-        run_code_in_sandbox(code, &req).await
-    } else if let Some(binary_path) = &req.binary_path {
-        run_binary_in_sandbox(binary_path, &req).await
+// Descendants that escaped a kill can keep the output pipes open; stop waiting on them after this
+const PIPE_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct ActiveSandbox {
+    pub pgid: Option<u32>,
+    pub cancelled: bool,
+}
+
+pub type ActiveSandboxes = Arc<Mutex<HashMap<Uuid, ActiveSandbox>>>;
+
+#[derive(Debug)]
+pub enum SubmitError {
+    QueueFull { queued: usize },
+    ProjectNotFound,
+    PolicyNotFound(String),
+    RootfsNotFound(String),
+    BinaryNotFound(String),
+    HostBinaryPath(String),
+    StoredBinaryInRootfs,
+    RunNotFound(Uuid),
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::QueueFull { .. } => write!(f, "Sandbox queue is full"),
+            SubmitError::ProjectNotFound => write!(f, "Project not found"),
+            SubmitError::PolicyNotFound(name) => write!(f, "Syscall policy '{}' not found", name),
+            SubmitError::RootfsNotFound(name) => write!(f, "Root filesystem image '{}' not found", name),
+            SubmitError::BinaryNotFound(sha256) => write!(f, "Stored binary {} not found", sha256),
+            SubmitError::HostBinaryPath(path) => write!(f, "{} is not in the run's workspace or image", path),
+            SubmitError::StoredBinaryInRootfs => write!(f, "Stored binaries cannot run inside a root filesystem image"),
+            SubmitError::RunNotFound(id) => write!(f, "Run {} not found", id),
+        }
+    }
+}
+
+struct ExecutionOutcome {
+    stdout: String,
+    stderr: String,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    core_dumped: bool,
+    timed_out: bool,
+    limits_hit: Vec<ResourceLimit>,  // Enforced by the backend itself rather than detected afterwards
+    syscall_log: Vec<SyscallEntry>,
+    resource_usage: ResourceUsage,
+    artifacts: Vec<RunArtifact>,
+    filesystem_changes: Vec<FileChange>,
+    process_tree: Vec<ProcessNode>,
+    policy_violations: Vec<PolicyViolation>,
+    syscall_counts: BTreeMap<String, u64>,
+    network_activity: Vec<NetworkEvent>,
+    network_activity_truncated: bool,
+    backtrace: Vec<StackFrame>,
+    recording: Option<recording::SharedRecording>,
+}
+
+struct RunContext {
+    run_id: Uuid,
+    active: ActiveSandboxes,
+    workspace: PathBuf,
+    // Backend-owned files (trace, ...) kept outside the workspace the target can see
+    meta_dir: PathBuf,
+    workdir: PathBuf,
+    stdin: Option<Vec<u8>>,
+    output_paths: Vec<PathBuf>,
+    seccomp_profile: Option<PathBuf>,
+    network: Option<netemu::FakeNetwork>,
+    pcap: Option<PathBuf>,
+    core_dir: PathBuf,
+    tmp_dir: PathBuf,  // Mounted at /tmp in the sandbox
+    rootfs_upper: Option<PathBuf>,  // Where writes to a rootfs image go instead of the image
+    deterministic: Option<DeterministicSettings>,
+    // Taken by whichever spawn attaches the target to it
+    terminal: Mutex<Option<pty::Terminal>>,
+}
+
+// What `aegis-sandbox --status` writes on exit: the limits it enforced on the target
+// and, from its cgroup, what the target used
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SandboxStatus {
+    timed_out: bool,
+    limits_hit: Vec<ResourceLimit>,
+    peak_memory_bytes: Option<u64>,
+    cpu_time_ms: Option<u64>,
+}
+
+struct CollectedOutput {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    status: Option<ExitStatus>,
+    timed_out: bool,
+    recording: recording::SharedRecording,
+}
+
+/// Queues a run, executes it once the queue grants a slot, and stores and logs the result.
+/// The run record is visible (and cancellable) from the moment it is queued.
+pub async fn submit_run(state: &AppState, req: SandboxRunRequest) -> Result<SandboxRun, SubmitError> {
+    submit(state, req, None, |_| {}).await
+}
+
+/// Like `submit_run`, telling `queued` the run's id as soon as the run is queued,
+/// long before it finishes.
+pub async fn submit_run_queued(
+    state: &AppState,
+    req: SandboxRunRequest,
+    queued: impl FnOnce(Uuid) + Send,
+) -> Result<SandboxRun, SubmitError> {
+    submit(state, req, None, queued).await
+}
+
+/// Like `submit_run`, with the target attached to a pseudo-terminal that the
+/// client drives. The terminal transcript becomes the run's stdout.
+pub async fn submit_interactive(state: &AppState, req: SandboxRunRequest, terminal: pty::Terminal) -> Result<SandboxRun, SubmitError> {
+    submit(state, req, Some(terminal), |_| {}).await
+}
+
+async fn submit(
+    state: &AppState,
+    mut req: SandboxRunRequest,
+    terminal: Option<pty::Terminal>,
+    queued: impl FnOnce(Uuid) + Send,
+) -> Result<SandboxRun, SubmitError> {
+    if let Some(project_id) = req.project_id {
+        let projects = state.projects.lock().unwrap();
+        if !projects.iter().any(|project| project.id == project_id) {
+            return Err(SubmitError::ProjectNotFound);
+        }
+    }
+
+    let policy = resolve_policy(state, &req)?;
+    let image = pin_rootfs(state, &req)?;
+    check_binary_path(&req)?;
+    let binary = resolve_stored_binary(state, &mut req)?;
+    enqueue_run(state, req, policy, None, terminal, image.into_iter().chain(binary).collect(), queued).await
+}
+
+/// Re-executes a stored run with its recorded request and, unless asked otherwise,
+/// the policy snapshot it ran with. The new run is a re-run of the original, so it
+/// also gets a reproduction report.
+pub async fn replay_run(
+    state: &AppState,
+    original_id: Uuid,
+    use_current_policy: bool,
+) -> Result<(SandboxRun, RunComparison), SubmitError> {
+    let original = state
+        .sandbox_runs
+        .lock()
+        .unwrap()
+        .get(&original_id)
+        .cloned()
+        .ok_or(SubmitError::RunNotFound(original_id))?;
+
+    let mut req = original.request.clone();
+    req.rerun_of = Some(original_id);
+    let image = pin_rootfs(state, &req)?;
+    check_binary_path(&req)?;
+    let binary = resolve_stored_binary(state, &mut req)?;
+
+    let policy = if use_current_policy { resolve_policy(state, &req)? } else { original.policy_snapshot.clone() };
+    let pins = image.into_iter().chain(binary).collect();
+    let replay = enqueue_run(state, req, policy, Some(original_id), None, pins, |_| {}).await?;
+
+    Ok((replay.clone(), compare::compare_runs(&original, &replay)))
+}
+
+fn resolve_policy(state: &AppState, req: &SandboxRunRequest) -> Result<Option<SyscallPolicy>, SubmitError> {
+    match &req.syscall_policy {
+        Some(name) => {
+            let policies = state.syscall_policies.lock().unwrap();
+            Ok(Some(policies.get(name).cloned().ok_or_else(|| SubmitError::PolicyNotFound(name.clone()))?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Inspects an ELF `binary_path` before it runs and keeps the result with the other
+/// binaries seen. Scripts, Wasm modules and code runs have nothing to inspect.
+async fn inspect_target(state: &AppState, req: &SandboxRunRequest) -> Option<BinaryInspection> {
+    if req.code.is_some() || backend(req) != SandboxBackend::Native {
+        return None;
+    }
+    let path = host_binary_path(req)?;
+    if !elf::is_elf(&path) {
+        return None;
+    }
+
+    let shown = path.display().to_string();
+    let inspection = match tokio::task::spawn_blocking(move || elf::inspect_file(&path)).await {
+        Ok(Ok(inspection)) => inspection,
+        Ok(Err(e)) => {
+            tracing::warn!("Failed to inspect {}: {}", shown, e);
+            return None;
+        }
+        Err(_) => return None,
+    };
+
+    state.binaries.lock().unwrap().insert(inspection.id.clone(), inspection.clone());
+    Some(inspection)
+}
+
+/// Where the target lives on the host before the run starts: inside the run's image
+/// if it has one, or in the store. A file the run brings into its workspace does not
+/// exist yet. Links in the image that lead out of it are not followed.
+fn host_binary_path(req: &SandboxRunRequest) -> Option<PathBuf> {
+    let binary_path = req.binary_path.as_deref()?;
+    let Some(name) = &req.rootfs else {
+        return Some(PathBuf::from(binary_path)).filter(|path| path.is_absolute());
+    };
+
+    within_root(&rootfs::rootfs_path(name), binary_path)
+}
+
+/// Where a path the target saw lives on the host once the run is over. In an image,
+/// absolute paths outside the workspace are in the overlay if the run wrote them and
+/// in the image otherwise.
+fn host_path_after_run(req: &SandboxRunRequest, ctx: &RunContext, path: &str) -> Option<PathBuf> {
+    let resolved = ctx.workdir.join(path);
+    let Some(name) = &req.rootfs else {
+        return Some(resolved);
+    };
+    if resolved.starts_with(&ctx.workspace) {
+        return Some(resolved);
+    }
+
+    ctx.rootfs_upper
+        .iter()
+        .find_map(|upper| within_root(upper, path))
+        .or_else(|| within_root(&rootfs::rootfs_path(name), path))
+}
+
+// Links that lead out of the root are not followed
+fn within_root(root: &Path, path: &str) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let path = root.join(path.trim_start_matches('/')).canonicalize().ok()?;
+    path.starts_with(&root).then_some(path)
+}
+
+/// Outside an image, a `binary_path` names a file the run brings into its workspace.
+/// Anything else from the host has to go through the store.
+pub fn check_binary_path(req: &SandboxRunRequest) -> Result<(), SubmitError> {
+    if req.code.is_some() || req.binary_sha256.is_some() || req.rootfs.is_some() {
+        return Ok(());
+    }
+    match req.binary_path.as_deref() {
+        Some(path) if workspace_relative_path(path).is_err() => Err(SubmitError::HostBinaryPath(path.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Pins the run's image until the run is over, so it cannot be deleted under it.
+fn pin_rootfs(state: &AppState, req: &SandboxRunRequest) -> Result<Option<store::Pin>, SubmitError> {
+    match &req.rootfs {
+        Some(name) => rootfs::pin(&state.rootfs_pins, name).map(Some).ok_or_else(|| SubmitError::RootfsNotFound(name.clone())),
+        None => Ok(None),
+    }
+}
+
+/// Points a run that names a stored binary at the blob in the store, which it
+/// executes in place, and pins the blob until the run is over. The blob is outside
+/// any root filesystem image, so the two cannot be combined.
+fn resolve_stored_binary(state: &AppState, req: &mut SandboxRunRequest) -> Result<Option<store::Pin>, SubmitError> {
+    let Some(sha256) = req.binary_sha256.as_ref().map(|sha256| sha256.to_lowercase()) else {
+        return Ok(None);
+    };
+    if req.rootfs.is_some() {
+        return Err(SubmitError::StoredBinaryInRootfs);
+    }
+    let pin = store::pin(&state.store_pins, &sha256).ok_or_else(|| SubmitError::BinaryNotFound(sha256.clone()))?;
+
+    req.binary_path = Some(store::blob_path(&sha256).display().to_string());
+    req.binary_sha256 = Some(sha256);
+    Ok(Some(pin))
+}
+
+async fn enqueue_run(
+    state: &AppState,
+    req: SandboxRunRequest,
+    policy: Option<SyscallPolicy>,
+    replay_of: Option<Uuid>,
+    terminal: Option<pty::Terminal>,
+    pins: Vec<store::Pin>,
+    queued: impl FnOnce(Uuid) + Send,
+) -> Result<SandboxRun, SubmitError> {
+    if let Some(original) = req.rerun_of {
+        if !state.sandbox_runs.lock().unwrap().contains_key(&original) {
+            return Err(SubmitError::RunNotFound(original));
+        }
+    }
+
+    let mut run = new_run(&req);
+    run.policy_snapshot = policy.clone();
+    run.replay_of = replay_of;
+    run.interactive = terminal.is_some();
+    let run_id = run.id;
+
+    state
+        .sandbox_queue
+        .enqueue(run_id, run.project_id, run.priority.clone())
+        .map_err(|full| SubmitError::QueueFull { queued: full.queued })?;
+
+    state.active_sandboxes.lock().unwrap().insert(run_id, ActiveSandbox { pgid: None, cancelled: false });
+    state.sandbox_runs.lock().unwrap().insert(run_id, run.clone());
+    queued(run_id);
+
+    // Run on a separate task so a client that disconnects doesn't abandon its queue slot
+    let task_state = state.clone();
+    let queued = run.clone();
+    let task = tokio::spawn(async move {
+        let _pins = pins;
+        process_queued_run(&task_state, run, req, policy, terminal).await
+    });
+
+    match task.await {
+        Ok(run) => Ok(run),
+        Err(e) => Ok(abandon_run(state, queued, format!("Sandbox run task failed: {}", e))),
+    }
+}
+
+/// Records a run whose task died as failed and gives back everything it held.
+fn abandon_run(state: &AppState, run: SandboxRun, error: String) -> SandboxRun {
+    let run_id = run.id;
+    tracing::error!("Sandbox run {}: {}", run_id, error);
+
+    state.sandbox_queue.remove_waiting(run_id);
+    state.sandbox_queue.release(run_id);
+    state.active_sandboxes.lock().unwrap().remove(&run_id);
+    let _ = std::fs::remove_dir_all(get_sandbox_workspace_root().join(run_id.to_string()));
+    let _ = std::fs::remove_dir_all(get_sandbox_workspace_root().join(format!("{}.meta", run_id)));
+
+    let run = SandboxRun {
+        status: RunStatus::Failed,
+        stderr: error,
+        completed_at: Some(Utc::now()),
+        ..run
+    };
+    state.sandbox_runs.lock().unwrap().insert(run_id, run.clone());
+    run
+}
+
+async fn process_queued_run(
+    state: &AppState,
+    mut run: SandboxRun,
+    req: SandboxRunRequest,
+    policy: Option<SyscallPolicy>,
+    terminal: Option<pty::Terminal>,
+) -> SandboxRun {
+    let run_id = run.id;
+
+    let mut run = if state.sandbox_queue.wait_for_slot(run_id).await {
+        run.status = RunStatus::Running;
+        run.toolchain = toolchain::versions(&req).await;
+        run.binary_inspection = inspect_target(state, &req).await;
+        state.sandbox_runs.lock().unwrap().insert(run_id, run.clone());
+
+        let run = run_sandbox(run, req, policy.as_ref(), &state.active_sandboxes, terminal).await;
+        state.sandbox_queue.release(run_id);
+        run
     } else {
-        ("No code or binary provided".to_string(),
-         "Error: No input".to_string(),
-         Some(1),
-         vec![],
-         ResourceUsage {
-             memory_mb: 0.0,
-             cpu_percent: 0.0,
-             execution_time_ms: 0,
-             syscalls_count: 0,
-         })
+        // Cancelled while still waiting in the queue
+        state.active_sandboxes.lock().unwrap().remove(&run_id);
+        SandboxRun {
+            status: RunStatus::Cancelled,
+            completed_at: Some(Utc::now()),
+            ..run
+        }
     };
 
+    if let Some(original_id) = run.rerun_of {
+        let original = state.sandbox_runs.lock().unwrap().get(&original_id).cloned();
+        if let Some(original) = original {
+            let report = compare::reproduction(&original, &run);
+            if !report.reproduced {
+                logger::add_log(
+                    &state.logs,
+                    LogLevel::Warning,
+                    LogSource::Sandbox,
+                    format!("Sandbox run {} did not reproduce run {}", run_id, original_id),
+                    Some(json!({
+                        "run_id": run_id,
+                        "reproduction": report
+                    })),
+                );
+            }
+            run.reproduction = Some(report);
+        }
+    }
+
+    let mapping = state.attack_mapping.lock().unwrap().clone();
+    run.attack_techniques = attack::map_run(&run, &mapping);
+
+    let run_verdict = verdict::evaluate(&run, &verdict::current_rules(state));
+    if run_verdict.verdict != Verdict::Benign {
+        logger::add_tagged_log(
+            &state.logs,
+            if run_verdict.verdict == Verdict::Malicious { LogLevel::Critical } else { LogLevel::Warning },
+            LogSource::Sandbox,
+            format!(
+                "Sandbox run {} scored {} ({:?}): {}",
+                run_id,
+                run_verdict.score,
+                run_verdict.verdict,
+                verdict::summary(&run_verdict)
+            ),
+            Some(json!({
+                "run_id": run_id,
+                "verdict": run_verdict
+            })),
+            attack::ids(&run.attack_techniques),
+        );
+    }
+    run.verdict = Some(run_verdict);
+
+    // Store the run
+    {
+        let mut runs = state.sandbox_runs.lock().unwrap();
+        runs.insert(run_id, run.clone());
+    }
+
+    // Log the sandbox run
+    logger::add_tagged_log(
+        &state.logs,
+        match run.status {
+            RunStatus::Completed => LogLevel::Info,
+            RunStatus::Failed | RunStatus::Timeout => LogLevel::Error,
+            RunStatus::Cancelled => LogLevel::Warning,
+            _ => LogLevel::Info,
+        },
+        LogSource::Sandbox,
+        format!("Sandbox run {} completed with status {:?}", run_id, run.status),
+        Some(json!({
+            "run_id": run_id,
+            "status": run.status,
+            "exit_code": run.exit_code,
+            "signal": run.signal,
+            "attack_techniques": attack::ids(&run.attack_techniques)
+        })),
+        attack::ids(&run.attack_techniques),
+    );
+
+    if !run.network_activity.is_empty() {
+        let hosts: BTreeSet<&str> = run
+            .network_activity
+            .iter()
+            .filter_map(|event| event.host.as_deref())
+            .collect();
+
+        logger::add_tagged_log(
+            &state.logs,
+            LogLevel::Warning,
+            LogSource::Sandbox,
+            format!(
+                "Sandbox run {} made {} network requests on the emulated network",
+                run_id,
+                run.network_activity.len()
+            ),
+            Some(json!({
+                "run_id": run_id,
+                "hosts": hosts,
+                "truncated": run.network_activity_truncated
+            })),
+            attack::tags(&run.attack_techniques, &mapping, |matcher| {
+                matches!(matcher, BehaviourMatcher::Connection { .. } | BehaviourMatcher::DnsQuery { .. })
+            }),
+        );
+    }
+
+    for violation in &run.policy_violations {
+        logger::add_tagged_log(
+            &state.logs,
+            if violation.action == PolicyAction::Kill { LogLevel::Critical } else { LogLevel::Warning },
+            LogSource::Sandbox,
+            format!(
+                "Syscall policy {} applied {:?} to {} ({}x) in run {}",
+                violation.policy, violation.action, violation.syscall, violation.count, run_id
+            ),
+            Some(json!({
+                "run_id": run_id,
+                "violation": violation
+            })),
+            attack::tags(&run.attack_techniques, &mapping, |matcher| match matcher {
+                BehaviourMatcher::Syscall { syscalls, .. } => syscalls.contains(&violation.syscall),
+                BehaviourMatcher::PolicyViolation => true,
+                _ => false,
+            }),
+        );
+    }
+
+    run
+}
+
+pub(crate) fn new_run(req: &SandboxRunRequest) -> SandboxRun {
     SandboxRun {
-        id: run_id,
-        status: if exit_code == Some(0) { RunStatus::Completed } else { RunStatus::Failed },
-        stdout,
-        stderr,
-        exit_code,
-        syscall_log,
-        resource_usage,
-        created_at,
+        id: Uuid::new_v4(),
+        status: RunStatus::Pending,
+        project_id: req.project_id,
+        priority: req.priority.clone().unwrap_or_default(),
+        queue_position: None,
+        syscall_policy: req.syscall_policy.clone(),
+        policy_violations: vec![],
+        syscall_counts: BTreeMap::new(),
+        network_mode: network_mode(req),
+        network_activity: vec![],
+        network_activity_truncated: false,
+        backtrace: vec![],
+        deterministic: deterministic_settings(req),
+        rerun_of: req.rerun_of,
+        reproduction: None,
+        request: req.clone(),
+        policy_snapshot: None,
+        toolchain: Default::default(),
+        replay_of: None,
+        interactive: false,
+        backend: backend(req),
+        rootfs_image: req.rootfs.as_deref().and_then(rootfs::get_image),
+        binary_inspection: None,
+        verdict: None,
+        attack_techniques: vec![],
+        stdout: String::new(),
+        stderr: String::new(),
+        exit_code: None,
+        signal: None,
+        syscall_log: vec![],
+        resource_usage: empty_resource_usage(),
+        limits_hit: vec![],
+        artifacts: vec![],
+        filesystem_changes: vec![],
+        process_tree: vec![],
+        created_at: Utc::now(),
+        completed_at: None,
+    }
+}
+
+async fn run_sandbox(
+    run: SandboxRun,
+    req: SandboxRunRequest,
+    policy: Option<&SyscallPolicy>,
+    active: &ActiveSandboxes,
+    terminal: Option<pty::Terminal>,
+) -> SandboxRun {
+    let run_id = run.id;
+    let outcome = execute(run_id, &req, policy, active, terminal).await;
+
+    let cancelled = active
+        .lock()
+        .unwrap()
+        .remove(&run_id)
+        .map(|sandbox| sandbox.cancelled)
+        .unwrap_or(false);
+
+    let limits_hit = detect_limits_hit(&req, &outcome);
+
+    SandboxRun {
+        status: if cancelled {
+            RunStatus::Cancelled
+        } else if outcome.timed_out {
+            RunStatus::Timeout
+        } else if outcome.exit_code == Some(0) {
+            RunStatus::Completed
+        } else {
+            RunStatus::Failed
+        },
+        stdout: outcome.stdout,
+        stderr: outcome.stderr,
+        exit_code: outcome.exit_code,
+        syscall_log: outcome.syscall_log,
+        resource_usage: outcome.resource_usage,
+        limits_hit,
+        artifacts: outcome.artifacts,
+        filesystem_changes: outcome.filesystem_changes,
+        process_tree: outcome.process_tree,
+        policy_violations: outcome.policy_violations,
+        syscall_counts: outcome.syscall_counts,
+        network_activity: outcome.network_activity,
+        network_activity_truncated: outcome.network_activity_truncated,
+        signal: outcome.signal,
+        backtrace: outcome.backtrace,
         completed_at: Some(Utc::now()),
+        ..run
     }
 }
 
-async fn run_code_in_sandbox(code: &str, req: &SandboxRunRequest) -> (String, String, Option<i32>, Vec<SyscallEntry>, ResourceUsage) {
-    let aegiscc_path = get_aegis_binary_path("aegiscc");
-    let aegis_sandbox_path = get_aegis_binary_path("aegis-sandbox");
+/// Drops a queued run or kills the whole process group of a running one.
+/// Returns false if the run is neither queued nor running.
+pub fn cancel_run(state: &AppState, run_id: Uuid) -> bool {
+    let mut active = state.active_sandboxes.lock().unwrap();
+    let Some(sandbox) = active.get_mut(&run_id) else {
+        return false;
+    };
 
-    let start = std::time::Instant::now();
+    // A run cancelled before its process is spawned is killed as soon as it registers
+    sandbox.cancelled = true;
+    if let Some(pgid) = sandbox.pgid {
+        kill_process_group(pgid);
+    }
+    drop(active);
 
-    // Build sandbox command
-    let mut cmd = Command::new(&aegis_sandbox_path);
+    state.sandbox_queue.remove_waiting(run_id);
 
-    // Add sandbox options
-    if let Some(mem_limit) = &req.memory_limit {
-        cmd.arg("--memory").arg(mem_limit);
+    true
+}
+
+fn kill_process_group(pgid: u32) {
+    // The sandbox is spawned as a process group leader, so this reaches every descendant
+    unsafe {
+        libc::kill(-(pgid as i32), libc::SIGKILL);
     }
+}
 
-    if let Some(timeout) = &req.timeout {
-        cmd.arg("--timeout").arg(timeout);
+async fn execute(
+    run_id: Uuid,
+    req: &SandboxRunRequest,
+    policy: Option<&SyscallPolicy>,
+    active: &ActiveSandboxes,
+    terminal: Option<pty::Terminal>,
+) -> ExecutionOutcome {
+    let mut ctx = match prepare_context(run_id, req, policy, active, terminal) {
+        Ok(ctx) => ctx,
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(get_sandbox_workspace_root().join(run_id.to_string()));
+            let _ = std::fs::remove_dir_all(get_sandbox_workspace_root().join(format!("{}.meta", run_id)));
+            return failed_outcome(e);
+        }
+    };
+
+    if network_mode(req) == Some(NetworkMode::Emulated) {
+        match netemu::FakeNetwork::start().await {
+            Ok(network) => ctx.network = Some(network),
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&ctx.workspace);
+                let _ = std::fs::remove_dir_all(&ctx.meta_dir);
+                return failed_outcome(format!("Failed to start emulated network: {}", e));
+            }
+        }
+    }
+
+    let before = fsdiff::snapshot(&ctx.workspace);
+
+    // If code is provided, compile and run it
+    let mut outcome = if backend(req) == SandboxBackend::Wasm {
+        run_wasm_in_sandbox(req, policy, &ctx).await
+    } else if let Some(code) = &req.code {
+        run_code_in_sandbox(code, req, &ctx).await
+    } else if let Some(binary_path) = &req.binary_path {
+        run_binary_in_sandbox(binary_path, req, &ctx).await
+    } else {
+        ExecutionOutcome {
+            stdout: "No code or binary provided".to_string(),
+            stderr: "Error: No input".to_string(),
+            exit_code: Some(1),
+            signal: None,
+            core_dumped: false,
+            timed_out: false,
+            limits_hit: vec![],
+            syscall_log: vec![],
+            resource_usage: empty_resource_usage(),
+            artifacts: vec![],
+            filesystem_changes: vec![],
+            process_tree: vec![],
+            policy_violations: vec![],
+            syscall_counts: BTreeMap::new(),
+            network_activity: vec![],
+            network_activity_truncated: false,
+            backtrace: vec![],
+            recording: None,
+        }
+    };
+
+    if let Ok(text) = std::fs::read_to_string(ctx.meta_dir.join("trace.log")) {
+        let trace = trace::parse_trace(&text);
+        if trace.syscalls_count > 0 {
+            outcome.resource_usage.syscalls_count = trace.syscalls_count as i32;
+            outcome.syscall_log = trace.syscalls;
+            outcome.process_tree = trace.processes;
+            outcome.syscall_counts = trace.syscall_counts;
+        }
+    }
+
+    if outcome.syscall_counts.is_empty() {
+        for entry in &outcome.syscall_log {
+            *outcome.syscall_counts.entry(entry.syscall.clone()).or_default() += 1;
+        }
+    }
+
+    // The sandbox enforces the timeout itself, so only its status says it did
+    if let Some(status) = read_status(&ctx.meta_dir) {
+        outcome.timed_out |= status.timed_out;
+        let usage = &mut outcome.resource_usage;
+        if let Some(bytes) = status.peak_memory_bytes {
+            usage.memory_mb = Some(bytes as f64 / (1024.0 * 1024.0));
+        }
+        if let Some(cpu_time) = status.cpu_time_ms {
+            usage.cpu_percent = Some(cpu_time as f64 * 100.0 / usage.execution_time_ms.max(1) as f64);
+        }
+        for limit in status.limits_hit {
+            if !outcome.limits_hit.contains(&limit) {
+                outcome.limits_hit.push(limit);
+            }
+        }
+    }
+
+    if let Some(network) = ctx.network.take() {
+        let capture = network.finish().await;
+        outcome.network_activity = capture.events;
+        outcome.network_activity_truncated = capture.truncated;
+    }
+
+    if let Some(policy) = policy {
+        outcome.policy_violations = policy::apply(policy, &mut outcome.syscall_log, &outcome.process_tree);
+    }
+
+    let mut changes = fsdiff::diff(&before, &fsdiff::snapshot(&ctx.workspace));
+    // /tmp starts out empty, so everything in it is the target's
+    changes.extend(fsdiff::diff(&fsdiff::Snapshot::new(), &fsdiff::snapshot_mounted(&ctx.tmp_dir, "/tmp")));
+    if let (Some(name), Some(upper)) = (&req.rootfs, &ctx.rootfs_upper) {
+        changes.extend(fsdiff::overlay_changes(&rootfs::rootfs_path(name), upper));
+    }
+    outcome.filesystem_changes = changes;
+    outcome.artifacts = artifacts::collect_outputs(run_id, &ctx.workdir, &ctx.output_paths);
+
+    // Before the workspace goes, since the crashed executable may live in it
+    let cores = coredump::collect(run_id, &ctx.core_dir, &outcome.process_tree, req.binary_path.as_deref(), |path| {
+        host_path_after_run(req, &ctx, path)
+    })
+    .await;
+    outcome.artifacts.extend(cores.artifacts);
+    outcome.backtrace = cores.backtrace;
+
+    if let Some(capture) = ctx.pcap.as_ref().filter(|path| path.metadata().is_ok_and(|m| m.len() > 0)) {
+        match artifacts::store_run_artifact(run_id, artifacts::PCAP_ARTIFACT, capture, ArtifactKind::Pcap) {
+            Ok(artifact) => outcome.artifacts.push(artifact),
+            Err(e) => tracing::warn!("Failed to store packet capture for run {}: {}", run_id, e),
+        }
+    }
+
+    if let Some(recording) = outcome.recording.take() {
+        let path = ctx.meta_dir.join("session.cast");
+        let cast = recording.lock().unwrap().to_asciicast();
+        let stored = std::fs::write(&path, cast)
+            .and_then(|_| artifacts::store_run_artifact(run_id, artifacts::RECORDING_ARTIFACT, &path, ArtifactKind::Recording));
+        match stored {
+            Ok(artifact) => outcome.artifacts.push(artifact),
+            Err(e) => tracing::warn!("Failed to store terminal recording for run {}: {}", run_id, e),
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(&ctx.workspace);
+    let _ = std::fs::remove_dir_all(&ctx.meta_dir);
+
+    outcome
+}
+
+/// Creates the per-run workspace, decodes the request's inputs and writes the input files.
+fn prepare_context(
+    run_id: Uuid,
+    req: &SandboxRunRequest,
+    policy: Option<&SyscallPolicy>,
+    active: &ActiveSandboxes,
+    terminal: Option<pty::Terminal>,
+) -> Result<RunContext, String> {
+    let stdin = match &req.stdin {
+        Some(encoded) => Some(BASE64.decode(encoded).map_err(|e| format!("Invalid stdin: {}", e))?),
+        None => None,
+    };
+
+    let workspace = get_sandbox_workspace_root().join(run_id.to_string());
+    let meta_dir = get_sandbox_workspace_root().join(format!("{}.meta", run_id));
+    let workdir = match &req.working_dir {
+        Some(dir) => workspace.join(workspace_relative_path(dir)?),
+        None => workspace.clone(),
+    };
+
+    let output_paths = req
+        .output_paths
+        .iter()
+        .flatten()
+        .map(|path| workspace_relative_path(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    std::fs::create_dir_all(&workdir).map_err(|e| format!("Failed to create workspace: {}", e))?;
+    std::fs::create_dir_all(&meta_dir).map_err(|e| format!("Failed to create workspace: {}", e))?;
+
+    for file in req.input_files.iter().flatten() {
+        let content = BASE64
+            .decode(&file.content)
+            .map_err(|e| format!("Invalid content for input file {}: {}", file.path, e))?;
+        let path = workdir.join(workspace_relative_path(&file.path)?);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(&path, content).map_err(|e| format!("Failed to write input file {}: {}", file.path, e))?;
     }
 
-    if let Some(false) = req.network_enabled {
-        cmd.arg("--network").arg("none");
+    let seccomp_profile = match policy {
+        Some(policy) => {
+            let path = meta_dir.join("seccomp.json");
+            let profile = serde_json::to_vec_pretty(&policy::to_oci_seccomp(policy)).map_err(|e| e.to_string())?;
+            std::fs::write(&path, profile).map_err(|e| format!("Failed to write seccomp profile: {}", e))?;
+            Some(path)
+        }
+        None => None,
+    };
+
+    let core_dir = meta_dir.join("cores");
+    std::fs::create_dir_all(&core_dir).map_err(|e| format!("Failed to create core dump directory: {}", e))?;
+
+    let tmp_dir = meta_dir.join("tmp");
+    std::fs::create_dir_all(&tmp_dir).map_err(|e| format!("Failed to create temporary directory: {}", e))?;
+
+    let rootfs_upper = match &req.rootfs {
+        Some(_) => {
+            let upper = meta_dir.join("upper");
+            std::fs::create_dir_all(&upper).map_err(|e| format!("Failed to create image overlay: {}", e))?;
+            Some(upper)
+        }
+        None => None,
+    };
+
+    // Nothing to capture without an interface
+    let pcap = (network_mode(req) != Some(NetworkMode::None)).then(|| meta_dir.join("capture.pcap"));
+
+    Ok(RunContext {
+        run_id,
+        active: active.clone(),
+        workspace,
+        meta_dir,
+        workdir,
+        stdin,
+        output_paths,
+        seccomp_profile,
+        network: None,
+        pcap,
+        core_dir,
+        tmp_dir,
+        rootfs_upper,
+        deterministic: deterministic_settings(req),
+        terminal: Mutex::new(terminal),
+    })
+}
+
+/// Accepts only relative paths that cannot climb out of the run's workspace.
+pub fn workspace_relative_path(path: &str) -> Result<PathBuf, String> {
+    let path = Path::new(path);
+    if path.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+        Ok(path.to_path_buf())
+    } else {
+        Err(format!("Path must stay inside the run workspace: {}", path.display()))
     }
+}
+
+async fn run_code_in_sandbox(code: &str, req: &SandboxRunRequest, ctx: &RunContext) -> ExecutionOutcome {
+    let aegiscc_path = get_aegis_binary_path("aegiscc");
+
+    let start = std::time::Instant::now();
+
+    // Build sandbox command
+    let mut cmd = sandbox_command(req, ctx);
 
     // Add aegiscc command
     cmd.arg(&aegiscc_path);
@@ -67,71 +891,212 @@ async fn run_code_in_sandbox(code: &str, req: &SandboxRunRequest) -> (String, St
     cmd.arg(code);
     cmd.arg("--run");
 
+    if let Some(args) = req.args.as_ref().filter(|args| !args.is_empty()) {
+        cmd.arg("--").args(args);
+    }
+
     // Execute
-    let output = cmd.output();
+    let output = collect_output(&mut cmd, req.timeout.as_deref().and_then(parse_duration), ctx).await;
 
     let execution_time = start.elapsed().as_millis() as i64;
 
     match output {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            let exit_code = output.status.code();
-
-            // Create mock syscall log
-            let syscall_log = vec![
-                SyscallEntry {
-                    syscall: "execve".to_string(),
-                    args: format!("{:?}", cmd),
-                    result: "0".to_string(),
-                    timestamp: Utc::now(),
-                    allowed: true,
-                },
-                SyscallEntry {
-                    syscall: "write".to_string(),
-                    args: "fd=1, size=42".to_string(),
-                    result: "42".to_string(),
-                    timestamp: Utc::now(),
-                    allowed: true,
-                },
-                SyscallEntry {
-                    syscall: "exit_group".to_string(),
-                    args: format!("exit_code={:?}", exit_code),
-                    result: format!("{:?}", exit_code),
-                    timestamp: Utc::now(),
-                    allowed: true,
-                },
-            ];
-
-            let resource_usage = ResourceUsage {
-                memory_mb: 2.5,
-                cpu_percent: 15.0,
-                execution_time_ms: execution_time,
-                syscalls_count: syscall_log.len() as i32,
-            };
+        // The syscall log comes from the trace, if the sandbox wrote one
+        Ok(output) => outcome_from_output(
+            output,
+            vec![],
+            ResourceUsage { execution_time_ms: execution_time, ..empty_resource_usage() },
+        ),
+        Err(e) => failed_outcome(format!("Failed to run sandbox: {}", e)),
+    }
+}
 
-            (stdout, stderr, exit_code, syscall_log, resource_usage)
-        }
-        Err(e) => (
-            "".to_string(),
-            format!("Failed to run sandbox: {}", e),
-            Some(1),
+async fn run_binary_in_sandbox(binary_path: &str, req: &SandboxRunRequest, ctx: &RunContext) -> ExecutionOutcome {
+    let start = std::time::Instant::now();
+
+    let mut cmd = sandbox_command(req, ctx);
+    cmd.arg(binary_path);
+    cmd.args(req.args.iter().flatten());
+
+    let output = collect_output(&mut cmd, req.timeout.as_deref().and_then(parse_duration), ctx).await;
+    let execution_time = start.elapsed().as_millis() as i64;
+
+    match output {
+        Ok(output) => outcome_from_output(
+            output,
             vec![],
-            ResourceUsage {
-                memory_mb: 0.0,
-                cpu_percent: 0.0,
-                execution_time_ms: 0,
-                syscalls_count: 0,
-            },
-        )
+            ResourceUsage { execution_time_ms: execution_time, ..empty_resource_usage() },
+        ),
+        Err(e) => failed_outcome(format!("Failed to run binary: {}", e)),
     }
 }
 
-async fn run_binary_in_sandbox(binary_path: &str, req: &SandboxRunRequest) -> (String, String, Option<i32>, Vec<SyscallEntry>, ResourceUsage) {
-    let aegis_sandbox_path = get_aegis_binary_path("aegis-sandbox");
+/// Runs a WebAssembly module in the embedded runtime instead of `aegis-sandbox`. The
+/// module sees the run's working directory as "/" and reaches nothing else of the host;
+/// its WASI calls take the place of the syscall trace. The deadline and cancellation are
+/// checked at each host call, so a module stuck in pure computation is stopped by its
+/// fuel instead; the run holds its queue slot until the runtime's thread returns.
+async fn run_wasm_in_sandbox(req: &SandboxRunRequest, policy: Option<&SyscallPolicy>, ctx: &RunContext) -> ExecutionOutcome {
+    let Some(binary_path) = req.binary_path.as_deref().filter(|_| req.code.is_none()) else {
+        return failed_outcome("The Wasm backend runs binary_path modules only".to_string());
+    };
+    if policy.is_some() {
+        return failed_outcome("Syscall policies apply to native runs only".to_string());
+    }
+    if req.rootfs.is_some() {
+        return failed_outcome("Root filesystem images apply to native runs only".to_string());
+    }
+    if ctx.terminal.lock().unwrap().is_some() {
+        return failed_outcome("Interactive sessions need the native backend".to_string());
+    }
+    let native_limits: Vec<&str> = [
+        ("pids_limit", req.pids_limit.is_some()),
+        ("cpu_quota", req.cpu_quota.is_some()),
+        ("cpu_shares", req.cpu_shares.is_some()),
+        ("disk_quota", req.disk_quota.is_some()),
+        ("core_size_limit", req.core_size_limit.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect();
+    if !native_limits.is_empty() {
+        return failed_outcome(format!("{} apply to native runs only", native_limits.join(", ")));
+    }
+
+    let module = match tokio::fs::read(ctx.workdir.join(binary_path)).await {
+        Ok(module) => module,
+        Err(e) => return failed_outcome(format!("Failed to read Wasm module: {}", e)),
+    };
+
+    let program = Path::new(binary_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| binary_path.to_string());
+    let env = match &ctx.deterministic {
+        Some(settings) => settings.environment.clone(),
+        None => req.env.clone().unwrap_or_default(),
+    };
+    let timeout = req.timeout.as_deref().and_then(parse_duration);
+    let (active, run_id) = (ctx.active.clone(), ctx.run_id);
+    let recording = recording::Recording::piped();
+
+    let config = wasm::WasmConfig {
+        args: std::iter::once(program).chain(req.args.iter().flatten().cloned()).collect(),
+        env: env.into_iter().collect(),
+        stdin: ctx.stdin.clone().unwrap_or_default(),
+        root: ctx.workdir.clone(),
+        fuel: wasm::fuel_budget(req.fuel, timeout),
+        memory_limit: req.memory_limit.as_deref().and_then(parse_size),
+        file_size_limit: req.file_size_limit.as_deref().and_then(parse_size),
+        open_files_limit: req.open_files_limit,
+        deadline: timeout.map(|limit| std::time::Instant::now() + limit),
+        clock: ctx.deterministic.as_ref().map(|settings| settings.clock),
+        seed: match &ctx.deterministic {
+            Some(settings) => settings.seed,
+            None => Uuid::new_v4().as_u64_pair().0,
+        },
+        cancelled: Box::new(move || active.lock().unwrap().get(&run_id).is_some_and(|sandbox| sandbox.cancelled)),
+        recording: recording.clone(),
+    };
 
     let start = std::time::Instant::now();
+    let result = tokio::task::spawn_blocking(move || wasm::run(&module, config)).await;
+    let execution_time = start.elapsed().as_millis() as i64;
+
+    let output = match result {
+        Ok(output) => output,
+        Err(e) => return failed_outcome(format!("Wasm runtime failed: {}", e)),
+    };
+
+    let mut stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if let Some(error) = &output.error {
+        if !stderr.is_empty() && !stderr.ends_with('\n') {
+            stderr.push('\n');
+        }
+        stderr.push_str(error);
+        stderr.push('\n');
+    }
+
+    ExecutionOutcome {
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr,
+        exit_code: output.exit_code,
+        timed_out: output.timed_out,
+        limits_hit: output.limits_hit,
+        resource_usage: ResourceUsage {
+            memory_mb: Some(output.peak_memory as f64 / (1024.0 * 1024.0)),
+            cpu_percent: None,
+            execution_time_ms: execution_time,
+            syscalls_count: output.call_counts.values().sum::<u64>() as i32,
+            fuel_consumed: Some(output.fuel_consumed),
+        },
+        syscall_log: output.calls,
+        syscall_counts: output.call_counts,
+        recording: Some(recording),
+        ..failed_outcome(String::new())
+    }
+}
+
+/// The backend a run executes on: the requested one, or Wasm when the binary is a
+/// WebAssembly module (by extension or by its magic number).
+pub fn backend(req: &SandboxRunRequest) -> SandboxBackend {
+    if let Some(backend) = req.backend {
+        return backend;
+    }
+    let Some(binary_path) = req.binary_path.as_deref().filter(|_| req.code.is_none()) else {
+        return SandboxBackend::Native;
+    };
+
+    let mut magic = [0u8; 4];
+    let is_module = binary_path.ends_with(".wasm")
+        || host_binary_path(req)
+            .and_then(|path| std::fs::File::open(path).ok())
+            .is_some_and(|mut file| std::io::Read::read_exact(&mut file, &mut magic).is_ok() && &magic == b"\0asm");
+    if is_module {
+        SandboxBackend::Wasm
+    } else {
+        SandboxBackend::Native
+    }
+}
+
+/// `network_mode` wins; otherwise the legacy `network_enabled: false` means no network
+/// and anything else leaves the sandbox on the host network.
+fn network_mode(req: &SandboxRunRequest) -> Option<NetworkMode> {
+    req.network_mode.clone().or(match req.network_enabled {
+        Some(false) => Some(NetworkMode::None),
+        _ => None,
+    })
+}
+
+/// Fills in the defaults of a deterministic run. The defaults are fixed values, so
+/// two runs of the same request get identical settings.
+fn deterministic_settings(req: &SandboxRunRequest) -> Option<DeterministicSettings> {
+    let config = req.deterministic.as_ref()?;
+    let timezone = config.timezone.clone().unwrap_or_else(|| "UTC".to_string());
+
+    let mut environment = BTreeMap::from([
+        ("LANG".to_string(), "C".to_string()),
+        ("LC_ALL".to_string(), "C".to_string()),
+    ]);
+    environment.extend(req.env.clone().unwrap_or_default());
+    environment.insert("TZ".to_string(), timezone.clone());
+
+    Some(DeterministicSettings {
+        clock: config
+            .clock
+            .unwrap_or_else(|| Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap()),
+        timezone,
+        seed: config.seed.unwrap_or(0),
+        cpu: config.cpu.unwrap_or(0),
+        hostname: config.hostname.clone().unwrap_or_else(|| "aegis-sandbox".to_string()),
+        environment,
+    })
+}
 
+/// Builds the `aegis-sandbox` invocation with every limit from the request.
+/// Enforcement is the sandbox's job; the backend only detects limits that were hit.
+fn sandbox_command(req: &SandboxRunRequest, ctx: &RunContext) -> Command {
+    let aegis_sandbox_path = get_aegis_binary_path("aegis-sandbox");
     let mut cmd = Command::new(&aegis_sandbox_path);
 
     if let Some(mem_limit) = &req.memory_limit {
@@ -142,45 +1107,469 @@ async fn run_binary_in_sandbox(binary_path: &str, req: &SandboxRunRequest) -> (S
         cmd.arg("--timeout").arg(timeout);
     }
 
-    if let Some(false) = req.network_enabled {
-        cmd.arg("--network").arg("none");
+    match network_mode(req) {
+        Some(NetworkMode::None) => {
+            cmd.arg("--network").arg("none");
+        }
+        Some(NetworkMode::Loopback) => {
+            cmd.arg("--network").arg("loopback");
+        }
+        Some(NetworkMode::Emulated) => {
+            cmd.arg("--network").arg("emulated");
+            if let Some(network) = &ctx.network {
+                cmd.arg("--fake-dns").arg(network.dns_addr.to_string());
+                cmd.arg("--fake-sink").arg(network.sink_addr.to_string());
+                cmd.arg("--sinkhole").arg(netemu::SINKHOLE_ADDR.to_string());
+            }
+        }
+        None => {}
     }
 
-    cmd.arg(binary_path);
+    if let Some(pids) = req.pids_limit {
+        cmd.arg("--pids").arg(pids.to_string());
+    }
 
-    let output = cmd.output();
-    let execution_time = start.elapsed().as_millis() as i64;
+    if let Some(nofile) = req.open_files_limit {
+        cmd.arg("--nofile").arg(nofile.to_string());
+    }
 
-    match output {
-        Ok(output) => {
-            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            let exit_code = output.status.code();
-
-            (
-                stdout,
-                stderr,
-                exit_code,
-                vec![],
-                ResourceUsage {
-                    memory_mb: 1.0,
-                    cpu_percent: 5.0,
-                    execution_time_ms: execution_time,
-                    syscalls_count: 0,
-                },
-            )
-        }
-        Err(e) => (
-            "".to_string(),
-            format!("Failed to run binary: {}", e),
-            Some(1),
-            vec![],
-            ResourceUsage {
-                memory_mb: 0.0,
-                cpu_percent: 0.0,
-                execution_time_ms: 0,
-                syscalls_count: 0,
-            },
-        )
+    if let Some(fsize) = &req.file_size_limit {
+        cmd.arg("--fsize").arg(fsize);
+    }
+
+    if let Some(quota) = req.cpu_quota {
+        cmd.arg("--cpu-quota").arg(quota.to_string());
+    }
+
+    if let Some(shares) = req.cpu_shares {
+        cmd.arg("--cpu-shares").arg(shares.to_string());
+    }
+
+    if let Some(disk) = &req.disk_quota {
+        cmd.arg("--disk").arg(disk);
+    }
+
+    if let Some(core) = &req.core_size_limit {
+        cmd.arg("--core").arg(core);
+    }
+
+    // The sandbox pivots into the image and bind-mounts the workspace at --workdir inside it.
+    // The image is the read-only lower layer of an overlay, so it is the same for every run.
+    if let Some(name) = &req.rootfs {
+        cmd.arg("--rootfs").arg(rootfs::rootfs_path(name));
+        if let Some(upper) = &ctx.rootfs_upper {
+            cmd.arg("--rootfs-upper").arg(upper);
+        }
+    }
+
+    cmd.arg("--workdir").arg(&ctx.workdir);
+    cmd.arg("--tmpdir").arg(&ctx.tmp_dir);
+
+    // Which limits stopped the target, as JSON once the sandbox exits
+    cmd.arg("--status").arg(ctx.meta_dir.join("status.json"));
+
+    // Every fork, exec and syscall in the sandbox is traced to strace's `-f -ttt` format
+    cmd.arg("--trace").arg(ctx.meta_dir.join("trace.log"));
+
+    if let Some(profile) = &ctx.seccomp_profile {
+        cmd.arg("--seccomp").arg(profile);
+    }
+
+    if let Some(capture) = &ctx.pcap {
+        cmd.arg("--pcap").arg(capture);
+    }
+
+    // Cores land here as core.<pid>, subject to --core
+    cmd.arg("--core-dir").arg(&ctx.core_dir);
+
+    if let Some(settings) = &ctx.deterministic {
+        cmd.arg("--fake-time").arg(settings.clock.to_rfc3339());
+        cmd.arg("--seed").arg(settings.seed.to_string());
+        cmd.arg("--cpu-affinity").arg(settings.cpu.to_string());
+        cmd.arg("--hostname").arg(&settings.hostname);
+        cmd.arg("--no-aslr");
+
+        // Never inherited, so the environment is part of the recorded settings
+        cmd.arg("--clear-env");
+        for (key, value) in &settings.environment {
+            cmd.arg("--env").arg(format!("{}={}", key, value));
+        }
+    } else {
+        // The target gets exactly the requested environment unless inheritance is asked for
+        if !req.inherit_env.unwrap_or(false) {
+            cmd.arg("--clear-env");
+        }
+
+        for (key, value) in req.env.iter().flatten() {
+            cmd.arg("--env").arg(format!("{}={}", key, value));
+        }
+    }
+
+    cmd.stdin(if ctx.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    // An interactive target leads its own session instead, which also makes it a group leader
+    if ctx.terminal.lock().unwrap().is_none() {
+        cmd.process_group(0);
     }
+
+    cmd
+}
+
+/// Runs the command to completion, killing its process group if it outlives `timeout`
+/// plus a grace period or is cancelled. Output produced before the kill is kept.
+async fn collect_output(cmd: &mut Command, timeout: Option<Duration>, ctx: &RunContext) -> std::io::Result<CollectedOutput> {
+    let terminal = ctx.terminal.lock().unwrap().take();
+    if let Some(terminal) = terminal {
+        return collect_terminal_output(cmd, timeout, ctx, terminal).await;
+    }
+
+    let mut child = cmd.spawn()?;
+    let pgid = child.id();
+    register_process_group(ctx, pgid);
+    let recording = recording::Recording::piped();
+
+    if let (Some(mut pipe), Some(input)) = (child.stdin.take(), ctx.stdin.clone()) {
+        // The pipe is dropped once written so the target sees EOF
+        tokio::spawn(async move {
+            let _ = pipe.write_all(&input).await;
+        });
+    }
+
+    let (stdout, stdout_task) = spawn_reader(child.stdout.take(), recording.clone(), recording::Stream::Stdout);
+    let (stderr, stderr_task) = spawn_reader(child.stderr.take(), recording.clone(), recording::Stream::Stderr);
+
+    let (status, timed_out) = wait_for_exit(&mut child, timeout, pgid).await?;
+
+    for mut task in [stdout_task, stderr_task] {
+        if tokio::time::timeout(PIPE_DRAIN_TIMEOUT, &mut task).await.is_err() {
+            task.abort();
+        }
+    }
+
+    let stdout = std::mem::take(&mut *stdout.lock().unwrap());
+    let stderr = std::mem::take(&mut *stderr.lock().unwrap());
+
+    Ok(CollectedOutput {
+        stdout,
+        stderr,
+        status,
+        timed_out,
+        recording,
+    })
+}
+
+/// Runs the command on a fresh pseudo-terminal instead of pipes, relaying the
+/// client's keystrokes and resizes in and the terminal output out as it arrives.
+/// The target sees one stream, so everything lands in stdout.
+async fn collect_terminal_output(
+    cmd: &mut Command,
+    timeout: Option<Duration>,
+    ctx: &RunContext,
+    terminal: pty::Terminal,
+) -> std::io::Result<CollectedOutput> {
+    let pty::Terminal { mut input, events, cols, rows } = terminal;
+    let pty = pty::open(cols, rows)?;
+
+    cmd.stdin(Stdio::from(pty.slave.try_clone()?))
+        .stdout(Stdio::from(pty.slave.try_clone()?))
+        .stderr(Stdio::from(pty.slave.try_clone()?));
+    unsafe {
+        cmd.pre_exec(pty::make_controlling_terminal);
+    }
+
+    let mut child = cmd.spawn()?;
+    let pgid = child.id();
+    register_process_group(ctx, pgid);
+    let recording = recording::Recording::terminal(cols, rows);
+
+    // Otherwise the master never sees the slave close
+    drop(pty.slave);
+    let master = Arc::new(pty.master);
+    let _ = events.send(pty::TerminalEvent::Started { run_id: ctx.run_id }).await;
+
+    let writer = master.clone();
+    let initial = ctx.stdin.clone();
+    let keystrokes = recording.clone();
+    let input_task = tokio::spawn(async move {
+        if let Some(initial) = initial {
+            keystrokes.lock().unwrap().input(&initial);
+            let _ = pty::write_all(&writer, &initial).await;
+        }
+        while let Some(message) = input.recv().await {
+            let _ = match message {
+                pty::TerminalInput::Data(data) => {
+                    keystrokes.lock().unwrap().input(&data);
+                    pty::write_all(&writer, &data).await
+                }
+                pty::TerminalInput::Resize { cols, rows } => {
+                    keystrokes.lock().unwrap().resize(cols, rows);
+                    pty::resize(&writer, cols, rows)
+                }
+            };
+        }
+        // The client went away; nobody is left to drive the session
+        if let Some(pgid) = pgid {
+            kill_process_group(pgid);
+        }
+    });
+
+    let transcript = Arc::new(Mutex::new(Vec::new()));
+    let sink = transcript.clone();
+    let screen = recording.clone();
+    let mut output_task = tokio::spawn(async move {
+        let mut chunk = [0u8; 8192];
+        while let Ok(n) = pty::read(&master, &mut chunk).await {
+            if n == 0 {
+                break;
+            }
+            sink.lock().unwrap().extend_from_slice(&chunk[..n]);
+            screen.lock().unwrap().output(recording::Stream::Stdout, &chunk[..n]);
+            let _ = events.send(pty::TerminalEvent::Output(chunk[..n].to_vec())).await;
+        }
+    });
+
+    let (status, timed_out) = wait_for_exit(&mut child, timeout, pgid).await?;
+
+    if tokio::time::timeout(PIPE_DRAIN_TIMEOUT, &mut output_task).await.is_err() {
+        output_task.abort();
+    }
+    input_task.abort();
+
+    let stdout = std::mem::take(&mut *transcript.lock().unwrap());
+
+    Ok(CollectedOutput {
+        stdout,
+        stderr: vec![],
+        status,
+        timed_out,
+        recording,
+    })
+}
+
+/// Records the sandbox's process group so the run can be cancelled, and kills it
+/// straight away if the run was cancelled before it started.
+fn register_process_group(ctx: &RunContext, pgid: Option<u32>) {
+    let Some(pgid) = pgid else {
+        return;
+    };
+
+    let mut active = ctx.active.lock().unwrap();
+    if let Some(sandbox) = active.get_mut(&ctx.run_id) {
+        sandbox.pgid = Some(pgid);
+        if sandbox.cancelled {
+            kill_process_group(pgid);
+        }
+    }
+}
+
+/// Waits for the child, killing its process group once it outlives `timeout` plus the grace period.
+async fn wait_for_exit(
+    child: &mut tokio::process::Child,
+    timeout: Option<Duration>,
+    pgid: Option<u32>,
+) -> std::io::Result<(Option<ExitStatus>, bool)> {
+    match timeout {
+        Some(limit) => match tokio::time::timeout(limit + TIMEOUT_GRACE, child.wait()).await {
+            Ok(status) => Ok((Some(status?), false)),
+            Err(_) => {
+                if let Some(pgid) = pgid {
+                    kill_process_group(pgid);
+                }
+                let _ = child.kill().await;
+                Ok((None, true))
+            }
+        },
+        None => Ok((Some(child.wait().await?), false)),
+    }
+}
+
+/// Reads a pipe into a shared buffer so whatever arrived is available even if the read
+/// never finishes, and into the run's recording as it arrives.
+fn spawn_reader<R: AsyncRead + Unpin + Send + 'static>(
+    pipe: Option<R>,
+    recording: recording::SharedRecording,
+    stream: recording::Stream,
+) -> (Arc<Mutex<Vec<u8>>>, JoinHandle<()>) {
+    let buffer = Arc::new(Mutex::new(Vec::new()));
+    let sink = buffer.clone();
+
+    let task = tokio::spawn(async move {
+        let Some(mut pipe) = pipe else {
+            return;
+        };
+        let mut chunk = [0u8; 8192];
+        while let Ok(n) = pipe.read(&mut chunk).await {
+            if n == 0 {
+                break;
+            }
+            sink.lock().unwrap().extend_from_slice(&chunk[..n]);
+            recording.lock().unwrap().output(stream, &chunk[..n]);
+        }
+    });
+
+    (buffer, task)
+}
+
+fn outcome_from_output(output: CollectedOutput, syscall_log: Vec<SyscallEntry>, resource_usage: ResourceUsage) -> ExecutionOutcome {
+    let exit_code = output.status.and_then(|status| status.code());
+    let signal = output.status.and_then(termination_signal);
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    ExecutionOutcome {
+        timed_out: output.timed_out,
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr,
+        exit_code,
+        signal,
+        core_dumped: output.status.map(|status| status.core_dumped()).unwrap_or(false),
+        limits_hit: vec![],
+        syscall_log,
+        resource_usage,
+        artifacts: vec![],
+        filesystem_changes: vec![],
+        process_tree: vec![],
+        policy_violations: vec![],
+        syscall_counts: BTreeMap::new(),
+        network_activity: vec![],
+        network_activity_truncated: false,
+        backtrace: vec![],
+        recording: Some(output.recording),
+    }
+}
+
+fn failed_outcome(stderr: String) -> ExecutionOutcome {
+    ExecutionOutcome {
+        stdout: "".to_string(),
+        stderr,
+        exit_code: Some(1),
+        signal: None,
+        core_dumped: false,
+        timed_out: false,
+        limits_hit: vec![],
+        syscall_log: vec![],
+        resource_usage: empty_resource_usage(),
+        artifacts: vec![],
+        filesystem_changes: vec![],
+        process_tree: vec![],
+        policy_violations: vec![],
+        syscall_counts: BTreeMap::new(),
+        network_activity: vec![],
+        network_activity_truncated: false,
+        backtrace: vec![],
+        recording: None,
+    }
+}
+
+fn empty_resource_usage() -> ResourceUsage {
+    ResourceUsage {
+        memory_mb: None,
+        cpu_percent: None,
+        execution_time_ms: 0,
+        syscalls_count: 0,
+        fuel_consumed: None,
+    }
+}
+
+/// Signal that terminated the target. The sandbox forwards a child's death by
+/// signal as the shell-style exit code 128 + signo, so both forms are accepted.
+fn termination_signal(status: ExitStatus) -> Option<i32> {
+    status.signal().or_else(|| match status.code() {
+        Some(code) if code > 128 && code < 128 + 65 => Some(code - 128),
+        _ => None,
+    })
+}
+
+/// None when the sandbox wrote no status, e.g. because it was killed.
+fn read_status(meta_dir: &Path) -> Option<SandboxStatus> {
+    let text = std::fs::read_to_string(meta_dir.join("status.json")).ok()?;
+    match serde_json::from_str(&text) {
+        Ok(status) => Some(status),
+        Err(e) => {
+            tracing::warn!("Ignoring unreadable sandbox status: {}", e);
+            None
+        }
+    }
+}
+
+/// The limits the sandbox reported plus those the exit signal proves: the target's
+/// output is not consulted, since a sample can print anything.
+fn detect_limits_hit(req: &SandboxRunRequest, outcome: &ExecutionOutcome) -> Vec<ResourceLimit> {
+    let mut hit = outcome.limits_hit.clone();
+    let mut push = |limit: ResourceLimit| {
+        if !hit.contains(&limit) {
+            hit.push(limit);
+        }
+    };
+
+    if outcome.timed_out {
+        push(ResourceLimit::Timeout);
+    }
+
+    // The kernel sends these when RLIMIT_FSIZE and RLIMIT_CPU run out
+    if outcome.signal == Some(libc::SIGXFSZ) {
+        push(ResourceLimit::FileSize);
+    }
+
+    if outcome.signal == Some(libc::SIGXCPU) {
+        push(ResourceLimit::Cpu);
+    }
+
+    // A core-dumping signal that produced no core means the core limit suppressed it
+    if req.core_size_limit.is_some() && !outcome.core_dumped && outcome.signal.map(dumps_core).unwrap_or(false) {
+        push(ResourceLimit::CoreSize);
+    }
+
+    hit
+}
+
+fn dumps_core(signal: i32) -> bool {
+    matches!(
+        signal,
+        libc::SIGQUIT | libc::SIGILL | libc::SIGTRAP | libc::SIGABRT | libc::SIGBUS
+            | libc::SIGFPE | libc::SIGSEGV | libc::SIGXCPU | libc::SIGXFSZ | libc::SIGSYS
+    )
+}
+
+/// Parses durations in the `aegis-sandbox` flag format: `500ms`, `5s`, `2m`, `1h` or bare seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+
+    // Digit strings long enough to overflow a Duration are rejected, not a panic
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parses sizes in the `aegis-sandbox` flag format: `512k`, `64m`, `1g` or bare bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim().to_lowercase();
+    let value = value.strip_suffix('b').unwrap_or(&value);
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    let multiplier = match unit.trim() {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        _ => return None,
+    };
+
+    number.checked_mul(multiplier)
 }
@@ -0,0 +1,535 @@
+use crate::models::{
+    LogLevel, LogSource, NetworkMode, ProbeOutcome, ProbeResult, ResourceLimit, RunStatus, SandboxRun, SandboxRunRequest,
+    SelftestReport, SelftestRequest,
+};
+use crate::services::artifacts::sha256_file;
+use crate::services::{get_data_dir, logger, sandbox, store, AppState};
+use chrono::Utc;
+use serde_json::json;
+use std::ffi::CString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Makes the backend binary run a probe instead of the server; the self-test runs
+/// itself in the sandbox this way, so the probes need no toolchain.
+pub const PROBE_FLAG: &str = "--aegis-probe";
+
+const PROBE_TIMEOUT: &str = "10s";
+
+// Limits the rlimit probes try to exceed, well below what they ask for
+const PROBE_PIDS_LIMIT: u32 = 16;
+const PROBE_PROCESSES: usize = 64;
+const PROBE_MEMORY_LIMIT: &str = "64m";
+const PROBE_MEMORY_MB: usize = 256;
+const PROBE_FILE_SIZE_LIMIT: &str = "1m";
+const PROBE_FILE_MB: usize = 8;
+
+const CANARY_FILE: &str = "canary";
+
+struct Probe {
+    name: &'static str,
+    description: &'static str,
+    configure: fn(&mut SandboxRunRequest),
+    // Ways the sandbox may stop the probe before it reports that count as blocking it
+    expected_stop: &'static [ExpectedStop],
+}
+
+enum ExpectedStop {
+    Limit(ResourceLimit),
+    Signal(i32),
+}
+
+// A seccomp policy with a kill action stops these probes at the call they try
+const SECCOMP_KILL: &[ExpectedStop] = &[ExpectedStop::Signal(libc::SIGSYS)];
+
+const PROBES: &[Probe] = &[
+    Probe {
+        name: "host-file-read",
+        description: "Read a file the backend created outside the sandbox",
+        configure: |_| {},
+        expected_stop: &[],
+    },
+    Probe {
+        name: "host-file-write",
+        description: "Create a file next to it",
+        configure: |_| {},
+        expected_stop: &[],
+    },
+    Probe {
+        name: "raw-socket",
+        description: "Open an AF_PACKET or raw ICMP socket",
+        configure: |_| {},
+        expected_stop: SECCOMP_KILL,
+    },
+    Probe {
+        name: "mount",
+        description: "Mount a tmpfs",
+        configure: |_| {},
+        expected_stop: SECCOMP_KILL,
+    },
+    Probe {
+        name: "ptrace-host",
+        description: "Attach with ptrace to the backend process",
+        configure: |_| {},
+        expected_stop: SECCOMP_KILL,
+    },
+    Probe {
+        name: "signal-host",
+        description: "See and signal the backend process",
+        configure: |_| {},
+        expected_stop: SECCOMP_KILL,
+    },
+    Probe {
+        name: "proc-sys-write",
+        description: "Open /proc/sys/kernel/hostname for writing",
+        configure: |_| {},
+        expected_stop: &[],
+    },
+    Probe {
+        name: "network-egress",
+        description: "Connect to 1.1.1.1:53 over TCP",
+        configure: |_| {},
+        expected_stop: SECCOMP_KILL,
+    },
+    Probe {
+        name: "process-limit",
+        description: "Start more processes than the pids limit allows",
+        configure: |req| req.pids_limit = Some(PROBE_PIDS_LIMIT),
+        expected_stop: &[ExpectedStop::Limit(ResourceLimit::Processes)],
+    },
+    Probe {
+        name: "memory-limit",
+        description: "Allocate more memory than the memory limit allows",
+        configure: |req| req.memory_limit = Some(PROBE_MEMORY_LIMIT.to_string()),
+        expected_stop: &[ExpectedStop::Limit(ResourceLimit::Memory)],
+    },
+    Probe {
+        name: "file-size-limit",
+        description: "Write a file larger than the file size limit allows",
+        configure: |req| req.file_size_limit = Some(PROBE_FILE_SIZE_LIMIT.to_string()),
+        expected_stop: &[ExpectedStop::Limit(ResourceLimit::FileSize)],
+    },
+];
+
+pub enum SelftestError {
+    PolicyNotFound(String),
+    Setup(String),
+}
+
+/// Runs every probe through the sandbox queue, one after another, and records the
+/// report. A probe passes when it reports being blocked, or when the sandbox stopped
+/// it the way that probe is meant to be stopped.
+pub async fn run_selftest(state: &AppState, req: SelftestRequest) -> Result<SelftestReport, SelftestError> {
+    if let Some(name) = &req.syscall_policy {
+        if !state.syscall_policies.lock().unwrap().contains_key(name) {
+            return Err(SelftestError::PolicyNotFound(name.clone()));
+        }
+    }
+
+    let id = Uuid::new_v4();
+    let started_at = Utc::now();
+    let network_mode = req.network_mode.clone().unwrap_or(NetworkMode::None);
+    let pins = state.store_pins.clone();
+    let (probe_sha256, _pin) = match tokio::task::spawn_blocking(move || store_probe_binary(&pins)).await {
+        Ok(stored) => stored.map_err(SelftestError::Setup)?,
+        Err(e) => return Err(SelftestError::Setup(format!("Failed to store the backend binary: {}", e))),
+    };
+    let canary_dir = create_canary(id).map_err(|e| SelftestError::Setup(format!("Failed to create the canary: {}", e)))?;
+
+    let mut probes = Vec::new();
+    for probe in PROBES {
+        let mut run_req = SandboxRunRequest {
+            binary_sha256: Some(probe_sha256.clone()),
+            args: Some(vec![
+                PROBE_FLAG.to_string(),
+                probe.name.to_string(),
+                canary_dir.to_string_lossy().to_string(),
+                std::process::id().to_string(),
+            ]),
+            timeout: Some(PROBE_TIMEOUT.to_string()),
+            network_mode: Some(network_mode.clone()),
+            syscall_policy: req.syscall_policy.clone(),
+            ..Default::default()
+        };
+        (probe.configure)(&mut run_req);
+
+        let (outcome, detail, run_id) = match sandbox::submit_run(state, run_req).await {
+            Ok(run) => {
+                let (outcome, detail) = classify(probe, &run);
+                (outcome, detail, Some(run.id))
+            }
+            Err(e) => (ProbeOutcome::Error, e.to_string(), None),
+        };
+        probes.push(ProbeResult {
+            probe: probe.name.to_string(),
+            description: probe.description.to_string(),
+            outcome,
+            detail,
+            run_id,
+        });
+    }
+
+    let _ = std::fs::remove_dir_all(&canary_dir);
+
+    let sandbox_version = probes
+        .iter()
+        .filter_map(|probe| probe.run_id)
+        .find_map(|run_id| state.sandbox_runs.lock().unwrap().get(&run_id)?.toolchain.aegis_sandbox.clone());
+    let report = SelftestReport {
+        id,
+        passed: probes.iter().all(|probe| probe.outcome == ProbeOutcome::Blocked),
+        syscall_policy: req.syscall_policy,
+        network_mode,
+        sandbox_version,
+        probes,
+        started_at,
+        completed_at: Utc::now(),
+    };
+    state.selftest_reports.lock().unwrap().push(report.clone());
+
+    let failed: Vec<&str> = report
+        .probes
+        .iter()
+        .filter(|probe| probe.outcome != ProbeOutcome::Blocked)
+        .map(|probe| probe.probe.as_str())
+        .collect();
+    logger::add_log(
+        &state.logs,
+        if report.passed { LogLevel::Info } else { LogLevel::Critical },
+        LogSource::Sandbox,
+        if report.passed {
+            format!("Sandbox self-test {} passed: all {} probes were blocked", id, report.probes.len())
+        } else {
+            format!("Sandbox self-test {} failed: {}", id, failed.join(", "))
+        },
+        Some(json!({
+            "selftest_id": id,
+            "failed": failed
+        })),
+    );
+
+    Ok(report)
+}
+
+/// The probes run from the store like any other sample. The backend binary is added
+/// on the first self-test after it changes, and stays pinned for the whole self-test.
+fn store_probe_binary(pins: &store::Pins) -> Result<(String, store::Pin), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to locate the backend binary: {}", e))?;
+    let sha256 = sha256_file(&exe).map_err(|e| format!("Failed to hash the backend binary: {}", e))?;
+    if let Some(pin) = store::pin(pins, &sha256) {
+        return Ok((sha256, pin));
+    }
+
+    let upload = store::store_dir().join(format!(".upload-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(store::store_dir()).map_err(|e| format!("Failed to create the store: {}", e))?;
+    std::fs::copy(&exe, &upload).map_err(|e| format!("Failed to copy the backend binary: {}", e))?;
+    let name = exe.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let stored = store::add_binary(&upload, &name, None, vec!["selftest".to_string()]);
+    // Already moved into the store unless something failed
+    let _ = std::fs::remove_file(&upload);
+
+    let sha256 = stored?.sha256;
+    let pin = store::pin(pins, &sha256).ok_or("The stored backend binary was removed")?;
+    Ok((sha256, pin))
+}
+
+/// A directory on the host, outside every sandbox workspace, holding a file the
+/// probes try to read. Its path has to be absolute; probes run in their workspace.
+fn create_canary(id: Uuid) -> std::io::Result<PathBuf> {
+    let dir = get_data_dir().join("selftest").join(id.to_string());
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(CANARY_FILE), id.to_string())?;
+    dir.canonicalize()
+}
+
+fn classify(probe: &Probe, run: &SandboxRun) -> (ProbeOutcome, String) {
+    let reported = run.stdout.lines().rev().find_map(|line| {
+        if let Some(detail) = line.strip_prefix("escaped: ") {
+            Some((ProbeOutcome::Escaped, detail.to_string()))
+        } else {
+            line.strip_prefix("blocked: ").map(|detail| (ProbeOutcome::Blocked, detail.to_string()))
+        }
+    });
+
+    match reported {
+        // What reached the emulated network never left the host
+        Some((ProbeOutcome::Escaped, _)) if probe.name == "network-egress" && !run.network_activity.is_empty() => {
+            (ProbeOutcome::Blocked, "Connection was answered by the emulated network".to_string())
+        }
+        Some(reported) => reported,
+        None => unreported(probe, run),
+    }
+}
+
+/// A probe that never got to report was blocked only if it was stopped the way it is
+/// meant to be; a crash, a timeout or some other limit says nothing about isolation.
+fn unreported(probe: &Probe, run: &SandboxRun) -> (ProbeOutcome, String) {
+    let expected = probe.expected_stop.iter().find_map(|stop| match stop {
+        ExpectedStop::Limit(limit) => run.limits_hit.contains(limit).then(|| format!("Stopped by the {:?} limit", limit)),
+        ExpectedStop::Signal(signal) => (run.signal == Some(*signal)).then(|| format!("Killed by signal {}", signal)),
+    });
+    if let Some(detail) = expected {
+        return (ProbeOutcome::Blocked, detail);
+    }
+
+    let stopped = if let Some(signal) = run.signal {
+        format!("was killed by signal {}", signal)
+    } else if run.status == RunStatus::Timeout {
+        "timed out".to_string()
+    } else if !run.limits_hit.is_empty() {
+        format!("hit the {:?} limit", run.limits_hit)
+    } else {
+        format!("exited with status {:?}", run.status)
+    };
+    let stderr = run.stderr.lines().last().unwrap_or_default();
+    (ProbeOutcome::Error, format!("Probe reported nothing and {} {}", stopped, stderr).trim().to_string())
+}
+
+// ============================================================================
+// Probes
+// ============================================================================
+
+/// Entry point of `aegis-studio-backend --aegis-probe <name> <canary dir> <backend pid>`.
+/// Prints "escaped: ..." if the action worked and "blocked: ..." if it did not.
+/// Must run before the async runtime starts its threads; some probes fork.
+pub fn run_probe(args: &[String]) -> i32 {
+    let (Some(name), Some(canary_dir), Some(pid)) = (args.first(), args.get(1), args.get(2).and_then(|pid| pid.parse().ok()))
+    else {
+        eprintln!("usage: {} <probe> <canary dir> <backend pid>", PROBE_FLAG);
+        return 2;
+    };
+    let canary_dir = Path::new(canary_dir);
+
+    let result = match name.as_str() {
+        "host-file-read" => probe_host_file_read(canary_dir),
+        "host-file-write" => probe_host_file_write(canary_dir),
+        "raw-socket" => probe_raw_socket(),
+        "mount" => probe_mount(),
+        "ptrace-host" => probe_ptrace(pid),
+        "signal-host" => probe_signal(pid),
+        "proc-sys-write" => probe_proc_sys(),
+        "network-egress" => probe_network(),
+        "process-limit" => probe_processes(),
+        "memory-limit" => probe_memory(),
+        "file-size-limit" => probe_file_size(),
+        _ => {
+            eprintln!("unknown probe '{}'", name);
+            return 2;
+        }
+    };
+
+    match result {
+        Ok(detail) => println!("escaped: {}", detail),
+        Err(detail) => println!("blocked: {}", detail),
+    }
+    0
+}
+
+// Each probe returns Ok with what it managed to do, or Err with why it could not.
+type Attempt = Result<String, String>;
+
+fn probe_host_file_read(canary_dir: &Path) -> Attempt {
+    let path = canary_dir.join(CANARY_FILE);
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => Ok(format!("Read {} ({} bytes)", path.display(), contents.len())),
+        Err(e) => Err(format!("Could not read {}: {}", path.display(), e)),
+    }
+}
+
+fn probe_host_file_write(canary_dir: &Path) -> Attempt {
+    let path = canary_dir.join("written-by-probe");
+    match std::fs::write(&path, "escaped") {
+        Ok(()) => Ok(format!("Created {}", path.display())),
+        Err(e) => Err(format!("Could not create {}: {}", path.display(), e)),
+    }
+}
+
+fn probe_raw_socket() -> Attempt {
+    let attempts = [
+        ("AF_PACKET", libc::AF_PACKET, libc::SOCK_RAW, (libc::ETH_P_ALL as u16).to_be() as libc::c_int),
+        ("raw ICMP", libc::AF_INET, libc::SOCK_RAW, libc::IPPROTO_ICMP),
+    ];
+
+    let mut errors = Vec::new();
+    for (label, domain, kind, protocol) in attempts {
+        let fd = unsafe { libc::socket(domain, kind, protocol) };
+        if fd >= 0 {
+            unsafe { libc::close(fd) };
+            return Ok(format!("Opened an {} socket", label));
+        }
+        errors.push(format!("{}: {}", label, std::io::Error::last_os_error()));
+    }
+    Err(errors.join("; "))
+}
+
+fn probe_mount() -> Attempt {
+    let target = "probe-mount";
+    std::fs::create_dir_all(target).map_err(|e| format!("Could not create the mount point: {}", e))?;
+    let (source, target_c, fstype) = (CString::new("tmpfs").unwrap(), CString::new(target).unwrap(), CString::new("tmpfs").unwrap());
+
+    if unsafe { libc::mount(source.as_ptr(), target_c.as_ptr(), fstype.as_ptr(), 0, std::ptr::null()) } == 0 {
+        unsafe { libc::umount2(target_c.as_ptr(), libc::MNT_DETACH) };
+        Ok("Mounted a tmpfs".to_string())
+    } else {
+        Err(format!("mount: {}", std::io::Error::last_os_error()))
+    }
+}
+
+fn probe_ptrace(pid: libc::pid_t) -> Attempt {
+    // PTRACE_SEIZE, unlike PTRACE_ATTACH, does not stop the backend if it works
+    let null = std::ptr::null_mut::<libc::c_void>();
+    if unsafe { libc::ptrace(libc::PTRACE_SEIZE, pid, null, null) } == 0 {
+        unsafe { libc::ptrace(libc::PTRACE_DETACH, pid, null, null) };
+        Ok(format!("Attached to pid {}", pid))
+    } else {
+        Err(format!("ptrace(PTRACE_SEIZE, {}): {}", pid, std::io::Error::last_os_error()))
+    }
+}
+
+fn probe_signal(pid: libc::pid_t) -> Attempt {
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        Ok(format!("Can signal pid {}", pid))
+    } else {
+        Err(format!("kill({}, 0): {}", pid, std::io::Error::last_os_error()))
+    }
+}
+
+fn probe_proc_sys() -> Attempt {
+    // Opening is enough to tell; nothing is written
+    let path = "/proc/sys/kernel/hostname";
+    match std::fs::OpenOptions::new().write(true).open(path) {
+        Ok(_) => Ok(format!("Opened {} for writing", path)),
+        Err(e) => Err(format!("Could not open {} for writing: {}", path, e)),
+    }
+}
+
+fn probe_network() -> Attempt {
+    let address = ([1, 1, 1, 1], 53).into();
+    match std::net::TcpStream::connect_timeout(&address, Duration::from_secs(3)) {
+        Ok(_) => Ok(format!("Connected to {}", address)),
+        Err(e) => Err(format!("Could not connect to {}: {}", address, e)),
+    }
+}
+
+fn probe_processes() -> Attempt {
+    let mut pipe = [0; 2];
+    if unsafe { libc::pipe(pipe.as_mut_ptr()) } != 0 {
+        return Err(format!("pipe: {}", std::io::Error::last_os_error()));
+    }
+
+    // Children wait on the pipe, so they all exist at once, and exit when it closes
+    let mut children = Vec::new();
+    let mut error = None;
+    for _ in 0..PROBE_PROCESSES {
+        match unsafe { libc::fork() } {
+            0 => unsafe {
+                libc::close(pipe[1]);
+                let mut byte = 0u8;
+                libc::read(pipe[0], (&mut byte as *mut u8).cast(), 1);
+                libc::_exit(0);
+            },
+            -1 => {
+                error = Some(std::io::Error::last_os_error());
+                break;
+            }
+            child => children.push(child),
+        }
+    }
+
+    unsafe { libc::close(pipe[1]) };
+    for child in &children {
+        unsafe { libc::waitpid(*child, std::ptr::null_mut(), 0) };
+    }
+
+    match error {
+        None => Ok(format!("Started {} processes", children.len())),
+        Some(e) => Err(format!("fork failed after {} processes: {}", children.len(), e)),
+    }
+}
+
+fn probe_memory() -> Attempt {
+    const CHUNK_MB: usize = 16;
+    let mut chunks = Vec::new();
+    for _ in 0..PROBE_MEMORY_MB / CHUNK_MB {
+        // Filled with a non-zero byte so every page is really committed
+        chunks.push(std::hint::black_box(vec![1u8; CHUNK_MB << 20]));
+    }
+    Ok(format!("Allocated {} MiB", chunks.len() * CHUNK_MB))
+}
+
+fn probe_file_size() -> Attempt {
+    // Without this the limit kills the probe with SIGXFSZ, which also counts as blocked
+    unsafe { libc::signal(libc::SIGXFSZ, libc::SIG_IGN) };
+
+    let mut file = std::fs::File::create("probe-file").map_err(|e| format!("Could not create a file: {}", e))?;
+    let block = vec![0u8; 64 << 10];
+    for written in 0..(PROBE_FILE_MB << 20) / block.len() {
+        if let Err(e) = file.write_all(&block) {
+            return Err(format!("Write failed after {} KiB: {}", written * 64, e));
+        }
+    }
+    Ok(format!("Wrote {} MiB", PROBE_FILE_MB))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(name: &str) -> &'static Probe {
+        PROBES.iter().find(|probe| probe.name == name).unwrap()
+    }
+
+    fn finished_run(status: RunStatus, signal: Option<i32>, limits_hit: Vec<ResourceLimit>) -> SandboxRun {
+        SandboxRun {
+            status,
+            signal,
+            limits_hit,
+            ..sandbox::new_run(&SandboxRunRequest::default())
+        }
+    }
+
+    #[test]
+    fn a_crash_is_an_error_not_a_block() {
+        let run = SandboxRun {
+            stderr: "Segmentation fault".to_string(),
+            ..finished_run(RunStatus::Failed, Some(libc::SIGSEGV), vec![])
+        };
+        let (outcome, detail) = classify(probe("mount"), &run);
+        assert_eq!(outcome, ProbeOutcome::Error);
+        assert!(detail.contains("killed by signal 11"), "{}", detail);
+
+        // Nor is a timeout, or a limit the probe is not about
+        let run = finished_run(RunStatus::Timeout, None, vec![ResourceLimit::Timeout]);
+        assert_eq!(classify(probe("host-file-read"), &run).0, ProbeOutcome::Error);
+        let run = finished_run(RunStatus::Failed, Some(libc::SIGKILL), vec![ResourceLimit::Memory]);
+        assert_eq!(classify(probe("process-limit"), &run).0, ProbeOutcome::Error);
+    }
+
+    #[test]
+    fn the_expected_stop_is_a_block() {
+        let run = finished_run(RunStatus::Failed, None, vec![ResourceLimit::Processes]);
+        assert_eq!(classify(probe("process-limit"), &run).0, ProbeOutcome::Blocked);
+
+        let run = finished_run(RunStatus::Failed, Some(libc::SIGSYS), vec![]);
+        assert_eq!(classify(probe("ptrace-host"), &run).0, ProbeOutcome::Blocked);
+        // SIGSYS only counts for the probes a seccomp policy is meant to stop
+        assert_eq!(classify(probe("memory-limit"), &run).0, ProbeOutcome::Error);
+    }
+
+    #[test]
+    fn the_probes_own_report_wins() {
+        let run = SandboxRun {
+            stdout: "blocked: ptrace(PTRACE_SEIZE, 1): Operation not permitted\n".to_string(),
+            ..finished_run(RunStatus::Completed, None, vec![])
+        };
+        assert_eq!(classify(probe("ptrace-host"), &run).0, ProbeOutcome::Blocked);
+
+        let run = SandboxRun {
+            stdout: "escaped: Mounted a tmpfs\n".to_string(),
+            ..finished_run(RunStatus::Completed, None, vec![])
+        };
+        assert_eq!(classify(probe("mount"), &run), (ProbeOutcome::Escaped, "Mounted a tmpfs".to_string()));
+    }
+}
@@ -0,0 +1,298 @@
+use crate::models::{BatchStatus, FuzzStatus, RunStatus, StoredBinary};
+use crate::services::artifacts::sha256_file;
+use crate::services::{get_data_dir, AppState};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Largest binary accepted for upload
+pub const MAX_UPLOAD_SIZE: u64 = 512 << 20;
+
+const BLOB_FILE: &str = "blob";
+const METADATA_FILE: &str = "binary.json";
+
+/// How many queued or running runs hold each stored binary, or each root filesystem
+/// image in the image pins. Deletion and garbage collection leave pinned ones alone.
+pub type Pins = Arc<Mutex<HashMap<String, usize>>>;
+
+/// Keeps a stored binary or image from being deleted until dropped.
+pub struct Pin {
+    pins: Pins,
+    key: String,
+}
+
+impl Drop for Pin {
+    fn drop(&mut self) {
+        let mut pins = self.pins.lock().unwrap();
+        if let Some(count) = pins.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                pins.remove(&self.key);
+            }
+        }
+    }
+}
+
+pub enum Deletion {
+    Deleted,
+    NotFound,
+    InUse(String),
+}
+
+pub fn store_dir() -> PathBuf {
+    get_data_dir().join("store")
+}
+
+/// A lowercase hex SHA-256, which is also the binary's directory name.
+pub fn valid_hash(sha256: &str) -> bool {
+    sha256.len() == 64 && sha256.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The stored file a run executes. Absolute, since the data directory may be given
+/// relative to the backend's working directory.
+pub fn blob_path(sha256: &str) -> PathBuf {
+    let path = store_dir().join(sha256).join(BLOB_FILE);
+    std::path::absolute(&path).unwrap_or(path)
+}
+
+pub fn get_binary(sha256: &str) -> Option<StoredBinary> {
+    if !valid_hash(sha256) {
+        return None;
+    }
+    let text = fs::read_to_string(store_dir().join(sha256).join(METADATA_FILE)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+pub fn list_binaries() -> Vec<StoredBinary> {
+    let Ok(entries) = fs::read_dir(store_dir()) else {
+        return vec![];
+    };
+
+    let mut binaries: Vec<StoredBinary> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| get_binary(&entry.file_name().to_string_lossy()))
+        .collect();
+    binaries.sort_by_key(|binary| std::cmp::Reverse(binary.uploaded_at));
+    binaries
+}
+
+/// Moves an upload into the store under its hash. Uploading content that is already
+/// stored keeps the original record and adds any new tags to it.
+pub fn add_binary(upload: &Path, name: &str, uploader: Option<String>, tags: Vec<String>) -> Result<StoredBinary, String> {
+    let sha256 = sha256_file(upload).map_err(|e| format!("Failed to hash upload: {}", e))?;
+    let dir = store_dir().join(&sha256);
+
+    if let Some(mut existing) = get_binary(&sha256) {
+        let merged: BTreeSet<String> = existing.tags.drain(..).chain(tags).collect();
+        existing.tags = merged.into_iter().collect();
+        write_metadata(&dir, &existing)?;
+        return Ok(existing);
+    }
+
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let blob = dir.join(BLOB_FILE);
+    fs::rename(upload, &blob).map_err(|e| format!("Failed to store binary: {}", e))?;
+    make_read_only_executable(&blob).map_err(|e| format!("Failed to set permissions: {}", e))?;
+
+    let binary = StoredBinary {
+        sha256,
+        name: name.to_string(),
+        size: fs::metadata(&blob).map(|meta| meta.len()).unwrap_or(0),
+        uploader,
+        tags: tags.into_iter().collect::<BTreeSet<_>>().into_iter().collect(),
+        uploaded_at: Utc::now(),
+    };
+    write_metadata(&dir, &binary)?;
+    Ok(binary)
+}
+
+/// Pins a stored binary, or returns None if there is no such binary.
+pub fn pin(pins: &Pins, sha256: &str) -> Option<Pin> {
+    hold(pins, sha256, || get_binary(sha256).is_some())
+}
+
+/// Pins `key` if `exists` finds it. The check and the pin are one step, so a deletion
+/// that holds the pins cannot come in between.
+pub fn hold(pins: &Pins, key: &str, exists: impl FnOnce() -> bool) -> Option<Pin> {
+    let mut held = pins.lock().unwrap();
+    if !exists() {
+        return None;
+    }
+    *held.entry(key.to_string()).or_default() += 1;
+    Some(Pin { pins: pins.clone(), key: key.to_string() })
+}
+
+/// Refuses while a queued or running run or a running campaign still needs the binary.
+pub fn delete_binary(state: &AppState, sha256: &str) -> std::io::Result<Deletion> {
+    let pins = state.store_pins.lock().unwrap();
+    if let Some(user) = binary_in_use(state, sha256) {
+        return Ok(Deletion::InUse(user));
+    }
+    if pins.contains_key(sha256) {
+        return Ok(Deletion::InUse("a queued run".to_string()));
+    }
+    if remove_binary(sha256)? {
+        Ok(Deletion::Deleted)
+    } else {
+        Ok(Deletion::NotFound)
+    }
+}
+
+// Returns false if there is no such binary
+fn remove_binary(sha256: &str) -> std::io::Result<bool> {
+    if get_binary(sha256).is_none() {
+        return Ok(false);
+    }
+    fs::remove_dir_all(store_dir().join(sha256))?;
+    Ok(true)
+}
+
+/// Every stored binary that a run, an unfinished batch or a fuzzing campaign refers
+/// to. Finished runs count too, since replaying them needs the same binary.
+pub fn referenced_binaries(state: &AppState) -> HashSet<String> {
+    let mut referenced: HashSet<String> = state
+        .sandbox_runs
+        .lock()
+        .unwrap()
+        .values()
+        .filter_map(|run| run.request.binary_sha256.clone())
+        .collect();
+
+    // Cells not yet submitted have no run to refer to the binary for them
+    for batch in state.sandbox_batches.lock().unwrap().values() {
+        if batch.status == BatchStatus::Running {
+            referenced.extend(
+                batch
+                    .cells
+                    .iter()
+                    .filter_map(|cell| cell.target.strip_prefix("sha256:"))
+                    .map(str::to_string),
+            );
+        }
+    }
+
+    referenced.extend(state.fuzz_campaigns.lock().unwrap().values().filter_map(|campaign| campaign.target_sha256.clone()));
+    referenced
+}
+
+/// The run or campaign that is executing the binary right now, if any.
+fn binary_in_use(state: &AppState, sha256: &str) -> Option<String> {
+    let runs = state.sandbox_runs.lock().unwrap();
+    if let Some(run) = runs.values().find(|run| {
+        matches!(run.status, RunStatus::Pending | RunStatus::Running) && run.request.binary_sha256.as_deref() == Some(sha256)
+    }) {
+        return Some(format!("run {}", run.id));
+    }
+    drop(runs);
+
+    let campaigns = state.fuzz_campaigns.lock().unwrap();
+    campaigns
+        .values()
+        .find(|campaign| matches!(campaign.status, FuzzStatus::Running) && campaign.target_sha256.as_deref() == Some(sha256))
+        .map(|campaign| format!("fuzzing campaign {}", campaign.id))
+}
+
+/// Removes every stored binary that nothing in `referenced` points at, that no run
+/// has pinned and that was uploaded before `cutoff`, so a binary uploaded for a run
+/// that hasn't been submitted yet survives. Returns what was (or, on a dry run, would
+/// be) removed.
+pub fn collect_garbage(pins: &Pins, referenced: &HashSet<String>, cutoff: DateTime<Utc>, dry_run: bool) -> Vec<StoredBinary> {
+    // Held throughout, so nothing pins a binary that is about to go
+    let pins = pins.lock().unwrap();
+    let mut removed = Vec::new();
+    for binary in list_binaries() {
+        if referenced.contains(&binary.sha256) || pins.contains_key(&binary.sha256) || binary.uploaded_at > cutoff {
+            continue;
+        }
+        if dry_run {
+            removed.push(binary);
+            continue;
+        }
+        match remove_binary(&binary.sha256) {
+            Ok(_) => removed.push(binary),
+            Err(e) => tracing::warn!("Failed to remove stored binary {}: {}", binary.sha256, e),
+        }
+    }
+    removed
+}
+
+fn write_metadata(dir: &Path, binary: &StoredBinary) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(binary).map_err(|e| e.to_string())?;
+    fs::write(dir.join(METADATA_FILE), text).map_err(|e| format!("Failed to write metadata: {}", e))
+}
+
+// Runs execute the blob in place, so it must stay runnable but never change under its hash
+fn make_read_only_executable(path: &Path) -> std::io::Result<()> {
+    fs::set_permissions(path, fs::Permissions::from_mode(0o555))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    // The store lives under the data directory, which only the environment sets
+    fn use_temp_data_dir() {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("aegis-store-{}", Uuid::new_v4()));
+            std::env::set_var("AEGIS_DATA_DIR", dir);
+        });
+    }
+
+    fn upload(name: &str) -> StoredBinary {
+        fs::create_dir_all(store_dir()).unwrap();
+        let path = store_dir().join(format!(".upload-{}", Uuid::new_v4()));
+        fs::write(&path, format!("{} {}", name, Uuid::new_v4())).unwrap();
+        add_binary(&path, name, None, vec![]).unwrap()
+    }
+
+    fn hashes(binaries: &[StoredBinary]) -> Vec<&str> {
+        binaries.iter().map(|binary| binary.sha256.as_str()).collect()
+    }
+
+    #[test]
+    fn collects_only_unreferenced_unpinned_old_binaries() {
+        use_temp_data_dir();
+        let pins = Pins::default();
+        let (referenced, pinned, garbage) = (upload("referenced"), upload("pinned"), upload("garbage"));
+        let cutoff = Utc::now();
+        let fresh = upload("fresh");
+        let references = HashSet::from([referenced.sha256.clone()]);
+        let pin = pin(&pins, &pinned.sha256).unwrap();
+
+        let removed = collect_garbage(&pins, &references, cutoff, true);
+        assert_eq!(hashes(&removed), [garbage.sha256.as_str()]);
+        assert!(get_binary(&garbage.sha256).is_some());
+
+        collect_garbage(&pins, &references, cutoff, false);
+        assert!(get_binary(&garbage.sha256).is_none());
+        for kept in [&referenced, &pinned, &fresh] {
+            assert!(get_binary(&kept.sha256).is_some(), "{}", kept.name);
+        }
+
+        drop(pin);
+        let removed = collect_garbage(&pins, &references, cutoff, false);
+        assert_eq!(hashes(&removed), [pinned.sha256.as_str()]);
+        fs::remove_dir_all(get_data_dir()).unwrap();
+    }
+
+    #[test]
+    fn pins_are_counted_and_released_on_drop() {
+        let pins = Pins::default();
+        assert!(hold(&pins, "image", || false).is_none());
+        assert!(pins.lock().unwrap().is_empty());
+
+        let first = hold(&pins, "image", || true).unwrap();
+        let second = hold(&pins, "image", || true).unwrap();
+        assert_eq!(pins.lock().unwrap().get("image"), Some(&2));
+
+        drop(first);
+        assert_eq!(pins.lock().unwrap().get("image"), Some(&1));
+        drop(second);
+        assert!(pins.lock().unwrap().is_empty());
+    }
+}
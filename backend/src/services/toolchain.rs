@@ -0,0 +1,43 @@
+use crate::models::{SandboxBackend, SandboxRunRequest, ToolchainVersions};
+use crate::services::{get_aegis_binary_path, sandbox, wasm};
+use std::time::Duration;
+use tokio::process::Command;
+
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Versions of the AEGIS tools a run is about to use.
+pub async fn versions(req: &SandboxRunRequest) -> ToolchainVersions {
+    if sandbox::backend(req) == SandboxBackend::Wasm {
+        return ToolchainVersions {
+            wasm_runtime: Some(wasm::RUNTIME.to_string()),
+            ..Default::default()
+        };
+    }
+
+    ToolchainVersions {
+        aegis_sandbox: binary_version("aegis-sandbox").await,
+        aegiscc: match req.code {
+            Some(_) => binary_version("aegiscc").await,
+            None => None,
+        },
+        wasm_runtime: None,
+    }
+}
+
+/// First line of `<binary> --version`, or `None` if the binary is missing or fails.
+pub async fn binary_version(binary_name: &str) -> Option<String> {
+    let output = Command::new(get_aegis_binary_path(binary_name))
+        .arg("--version")
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(VERSION_TIMEOUT, output).await.ok()?.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
@@ -0,0 +1,387 @@
+use crate::models::{ProcessNode, ProcessResourceUsage, SyscallEntry};
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+// The syscall log kept on a run is capped; the counts still cover the whole trace
+pub const MAX_SYSCALL_LOG: usize = 10_000;
+
+/// Result of parsing a `--trace` file written by `aegis-sandbox`.
+/// The format is the one produced by `strace -f -ttt`: `PID EPOCH syscall(args) = result`.
+pub struct Trace {
+    pub syscalls: Vec<SyscallEntry>,
+    pub syscalls_count: usize,
+    pub syscall_counts: BTreeMap<String, u64>,
+    pub processes: Vec<ProcessNode>,
+}
+
+pub fn parse_trace(text: &str) -> Trace {
+    let mut syscalls = Vec::new();
+    let mut syscalls_count = 0;
+    let mut syscall_counts: BTreeMap<String, u64> = BTreeMap::new();
+    let mut processes: BTreeMap<u32, ProcessNode> = BTreeMap::new();
+    let mut unfinished: HashMap<u32, (String, DateTime<Utc>)> = HashMap::new();
+
+    for line in text.lines() {
+        let Some((pid, timestamp, body)) = split_prefix(line) else {
+            continue;
+        };
+
+        // A child's first line can precede the clone() result that names its parent
+        processes.entry(pid).or_insert_with(|| new_process(pid, None, Vec::new(), timestamp));
+
+        if let Some(exit) = body.strip_prefix("+++ ").and_then(|b| b.strip_suffix(" +++")) {
+            record_exit(processes.get_mut(&pid), exit, timestamp);
+            continue;
+        }
+
+        if body.starts_with("---") {
+            continue;
+        }
+
+        // Calls interrupted by another process are split over two lines
+        let (call, started_at) = if let Some(partial) = body.strip_suffix(" <unfinished ...>") {
+            unfinished.insert(pid, (partial.to_string(), timestamp));
+            continue;
+        } else if let Some(rest) = body.strip_prefix("<... ") {
+            let Some((_, rest)) = rest.split_once(" resumed>") else {
+                continue;
+            };
+            match unfinished.remove(&pid) {
+                Some((partial, started_at)) => (join_halves(&partial, rest), started_at),
+                None => continue,
+            }
+        } else {
+            (body.to_string(), timestamp)
+        };
+
+        let Some(entry) = parse_call(&call, pid, started_at) else {
+            continue;
+        };
+
+        syscalls_count += 1;
+        *syscall_counts.entry(entry.syscall.clone()).or_default() += 1;
+        if let Some(process) = processes.get_mut(&pid) {
+            process.resource_usage.syscalls_count += 1;
+        }
+
+        apply_process_event(&mut processes, &entry, pid, started_at);
+
+        if syscalls.len() < MAX_SYSCALL_LOG {
+            syscalls.push(entry);
+        }
+    }
+
+    Trace {
+        syscalls,
+        syscalls_count,
+        syscall_counts,
+        processes: processes.into_values().collect(),
+    }
+}
+
+/// Rejoins an interrupted call. The line was trimmed, so the space between the
+/// arguments on either side of the split is put back.
+fn join_halves(partial: &str, rest: &str) -> String {
+    let (partial, rest) = (partial.trim_end(), rest.trim_start());
+    if partial.ends_with('(') || rest.starts_with(')') {
+        format!("{}{}", partial, rest)
+    } else {
+        format!("{} {}", partial, rest)
+    }
+}
+
+fn split_prefix(line: &str) -> Option<(u32, DateTime<Utc>, &str)> {
+    let line = line.trim_start();
+    let line = line.strip_prefix("[pid").map(|l| l.trim_start()).unwrap_or(line);
+
+    let (pid, rest) = line.split_once([' ', ']'])?;
+    let pid: u32 = pid.parse().ok()?;
+    let rest = rest.trim_start_matches(']').trim_start();
+
+    let (timestamp, body) = match rest.split_once(' ') {
+        Some((first, body)) if first.parse::<f64>().is_ok() => (parse_epoch(first), body),
+        _ => (Utc::now(), rest),
+    };
+
+    Some((pid, timestamp, body.trim()))
+}
+
+fn parse_epoch(value: &str) -> DateTime<Utc> {
+    let (secs, frac) = value.split_once('.').unwrap_or((value, "0"));
+    let secs: i64 = secs.parse().unwrap_or(0);
+    let micros: u32 = format!("{:0<6}", &frac[..frac.len().min(6)]).parse().unwrap_or(0);
+    Utc.timestamp_opt(secs, micros * 1000).single().unwrap_or_else(Utc::now)
+}
+
+fn parse_call(call: &str, pid: u32, timestamp: DateTime<Utc>) -> Option<SyscallEntry> {
+    let open = call.find('(')?;
+    let name = &call[..open];
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let (args, result) = match call.rfind(") = ") {
+        Some(close) => (&call[open + 1..close], call[close + 4..].trim()),
+        None => (call[open + 1..].trim_end_matches(')'), "?"),
+    };
+
+    // Drop the `-T` duration suffix if the tracer added one
+    let result = match result.rfind(" <") {
+        Some(pos) if result.ends_with('>') => &result[..pos],
+        _ => result,
+    };
+
+    Some(SyscallEntry {
+        syscall: name.to_string(),
+        args: args.to_string(),
+        result: result.to_string(),
+        timestamp,
+        allowed: true,
+        matched_rule: None,
+        pid: Some(pid),
+    })
+}
+
+fn apply_process_event(processes: &mut BTreeMap<u32, ProcessNode>, entry: &SyscallEntry, pid: u32, timestamp: DateTime<Utc>) {
+    let succeeded = !entry.result.starts_with('-') && entry.result != "?";
+
+    match entry.syscall.as_str() {
+        "clone" | "clone3" | "fork" | "vfork" if succeeded => {
+            let Ok(child) = entry.result.split_whitespace().next().unwrap_or("").parse::<u32>() else {
+                return;
+            };
+            // Threads share the parent's process node
+            if entry.args.contains("CLONE_THREAD") {
+                return;
+            }
+            let argv = processes.get(&pid).map(|p| p.argv.clone()).unwrap_or_default();
+            let node = processes.entry(child).or_insert_with(|| new_process(child, Some(pid), Vec::new(), timestamp));
+            node.parent_pid = Some(pid);
+            if node.argv.is_empty() && node.executable.is_none() {
+                node.argv = argv;
+            }
+        }
+        "execve" | "execveat" if succeeded => {
+            if let Some(process) = processes.get_mut(&pid) {
+                let mut strings = quoted_strings(&entry.args);
+                if !strings.is_empty() {
+                    process.executable = Some(strings.remove(0));
+                    process.argv = strings;
+                }
+            }
+        }
+        "wait4" if succeeded => {
+            let Ok(child) = entry.result.parse::<u32>() else {
+                return;
+            };
+            if let Some(process) = processes.get_mut(&child) {
+                apply_rusage(&mut process.resource_usage, &entry.args);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn record_exit(process: Option<&mut ProcessNode>, exit: &str, timestamp: DateTime<Utc>) {
+    let Some(process) = process else {
+        return;
+    };
+
+    process.ended_at = Some(timestamp);
+    if let Some(code) = exit.strip_prefix("exited with ") {
+        process.exit_code = code.trim().parse().ok();
+    } else if let Some(killed) = exit.strip_prefix("killed by ") {
+        let name = killed.split_whitespace().next().unwrap_or("");
+        process.signal = signal_number(name);
+        process.core_dumped = killed.contains("(core dumped)");
+    }
+}
+
+/// Reads `ru_utime`, `ru_stime` and `ru_maxrss` from a decoded `struct rusage` argument.
+fn apply_rusage(usage: &mut ProcessResourceUsage, args: &str) {
+    let timeval = |field: &str| -> Option<f64> {
+        let start = args.find(&format!("{}={{", field))?;
+        let rest = &args[start..];
+        let secs: f64 = field_value(rest, "tv_sec=")?.parse().ok()?;
+        let usecs: f64 = field_value(rest, "tv_usec=")?.parse().ok()?;
+        Some(secs * 1000.0 + usecs / 1000.0)
+    };
+
+    if let Some(utime) = timeval("ru_utime") {
+        usage.user_time_ms = Some(utime);
+    }
+    if let Some(stime) = timeval("ru_stime") {
+        usage.system_time_ms = Some(stime);
+    }
+    if let Some(maxrss) = field_value(args, "ru_maxrss=").and_then(|v| v.parse().ok()) {
+        usage.max_rss_kb = Some(maxrss);
+    }
+}
+
+fn field_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    let start = text.find(key)? + key.len();
+    let rest = &text[start..];
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+/// Collects the double-quoted strings of a traced argument list, with strace's escapes
+/// undone. Stops at the first `]` outside a string, so for `execve` that is the path
+/// and then argv, without the environment.
+pub(crate) fn quoted_strings(args: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut chars = args.chars();
+
+    while let Some(c) = chars.next() {
+        if c == ']' {
+            break;
+        }
+        if c != '"' {
+            continue;
+        }
+        let mut value = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some(escaped) = chars.next() {
+                        value.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => other,
+                        });
+                    }
+                }
+                '"' => break,
+                other => value.push(other),
+            }
+        }
+        strings.push(value);
+    }
+
+    strings
+}
+
+fn new_process(pid: u32, parent_pid: Option<u32>, argv: Vec<String>, started_at: DateTime<Utc>) -> ProcessNode {
+    ProcessNode {
+        pid,
+        parent_pid,
+        argv,
+        executable: None,
+        started_at,
+        ended_at: None,
+        exit_code: None,
+        signal: None,
+        core_dumped: false,
+        resource_usage: ProcessResourceUsage {
+            syscalls_count: 0,
+            user_time_ms: None,
+            system_time_ms: None,
+            max_rss_kb: None,
+        },
+    }
+}
+
+pub fn signal_number(name: &str) -> Option<i32> {
+    let number = match name {
+        "SIGHUP" => libc::SIGHUP,
+        "SIGINT" => libc::SIGINT,
+        "SIGQUIT" => libc::SIGQUIT,
+        "SIGILL" => libc::SIGILL,
+        "SIGTRAP" => libc::SIGTRAP,
+        "SIGABRT" => libc::SIGABRT,
+        "SIGBUS" => libc::SIGBUS,
+        "SIGFPE" => libc::SIGFPE,
+        "SIGKILL" => libc::SIGKILL,
+        "SIGUSR1" => libc::SIGUSR1,
+        "SIGSEGV" => libc::SIGSEGV,
+        "SIGUSR2" => libc::SIGUSR2,
+        "SIGPIPE" => libc::SIGPIPE,
+        "SIGALRM" => libc::SIGALRM,
+        "SIGTERM" => libc::SIGTERM,
+        "SIGXCPU" => libc::SIGXCPU,
+        "SIGXFSZ" => libc::SIGXFSZ,
+        "SIGSYS" => libc::SIGSYS,
+        _ => return None,
+    };
+    Some(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_unfinished_and_resumed_halves() {
+        let trace = parse_trace(concat!(
+            "100 1700000000.000100 read(3, <unfinished ...>\n",
+            "[pid   101] 1700000000.000200 getpid() = 101\n",
+            "100 1700000000.000300 <... read resumed>\"abc\", 16) = 3\n",
+            "101 1700000000.000400 wait4(-1, <unfinished ...>\n",
+            "101 1700000000.000500 <... wait4 resumed> NULL, 0, NULL) = 102\n",
+            "101 1700000000.000600 pause( <unfinished ...>\n",
+            "101 1700000000.000700 <... pause resumed>) = ? ERESTARTNOHAND\n",
+        ));
+
+        assert_eq!(trace.syscalls_count, 4);
+        let read = trace.syscalls.iter().find(|entry| entry.syscall == "read").unwrap();
+        assert_eq!(read.args, "3, \"abc\", 16");
+        assert_eq!(read.result, "3");
+        assert_eq!(read.pid, Some(100));
+        // Timed from when the call started, not when it returned
+        assert_eq!(read.timestamp, Utc.timestamp_opt(1700000000, 100_000).unwrap());
+
+        assert_eq!(trace.syscalls[2].args, "-1, NULL, 0, NULL");
+        assert_eq!(trace.syscalls[2].result, "102");
+        assert_eq!(trace.syscalls[3].args, "");
+        assert_eq!(trace.syscalls[3].result, "? ERESTARTNOHAND");
+    }
+
+    #[test]
+    fn drops_a_resumed_half_without_its_start() {
+        let trace = parse_trace("100 1700000000.0 <... read resumed>\"abc\", 16) = 3\n");
+        assert_eq!(trace.syscalls_count, 0);
+    }
+
+    #[test]
+    fn skips_signal_deliveries_and_records_how_processes_ended() {
+        let trace = parse_trace(concat!(
+            "100 1700000000.0 clone(child_stack=NULL, flags=SIGCHLD) = 101\n",
+            "101 1700000000.1 --- SIGSEGV {si_signo=SIGSEGV, si_code=SEGV_MAPERR, si_addr=NULL} ---\n",
+            "101 1700000000.2 +++ killed by SIGSEGV (core dumped) +++\n",
+            "100 1700000000.3 +++ exited with 3 +++\n",
+        ));
+
+        assert_eq!(trace.syscalls_count, 1);
+        let child = trace.processes.iter().find(|process| process.pid == 101).unwrap();
+        assert_eq!(child.parent_pid, Some(100));
+        assert_eq!(child.signal, Some(libc::SIGSEGV));
+        assert!(child.core_dumped);
+        assert_eq!(child.exit_code, None);
+
+        let parent = trace.processes.iter().find(|process| process.pid == 100).unwrap();
+        assert_eq!(parent.exit_code, Some(3));
+        assert_eq!(parent.ended_at, Some(Utc.timestamp_opt(1700000000, 300_000_000).unwrap()));
+    }
+
+    #[test]
+    fn keeps_commas_and_brackets_inside_quoted_strings() {
+        let trace = parse_trace(concat!(
+            "100 1700000000.0 execve(\"/bin/sh\", [\"sh\", \"-c\", \"echo a, b], c\"], 0x7ffd /* 3 vars */) = 0\n",
+            "100 1700000000.1 write(1, \"a, b) = 9\\n\", 10) = 10\n",
+        ));
+
+        let process = &trace.processes[0];
+        assert_eq!(process.executable.as_deref(), Some("/bin/sh"));
+        assert_eq!(process.argv, ["sh", "-c", "echo a, b], c"]);
+
+        let write = &trace.syscalls[1];
+        assert_eq!(write.args, "1, \"a, b) = 9\\n\", 10");
+        assert_eq!(write.result, "10");
+    }
+
+    #[test]
+    fn ignores_the_environment_after_argv() {
+        let strings = quoted_strings("\"/bin/true\", [\"true\"], [\"PATH=/bin\", \"HOME=/\"]");
+        assert_eq!(strings, ["/bin/true", "true"]);
+    }
+}
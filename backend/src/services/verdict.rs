@@ -0,0 +1,438 @@
+use crate::models::{
+    BehaviourMatcher, NetworkProtocol, RunVerdict, SandboxRun, SyscallEntry, TriggeredBehaviour, Verdict, VerdictRule,
+    VerdictRuleRequest,
+};
+use crate::services::trace::quoted_strings;
+use crate::services::{get_sandbox_workspace_root, AppState};
+use chrono::Utc;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+
+// Scores at or above these turn a run suspicious or malicious
+pub const SUSPICIOUS_SCORE: u32 = 25;
+pub const MALICIOUS_SCORE: u32 = 60;
+
+pub const MAX_EVIDENCE: usize = 5;
+
+const OPEN_SYSCALLS: &[&str] = &["open", "openat", "openat2"];
+const WRITE_FLAGS: &[&str] = &["O_WRONLY", "O_RDWR", "O_CREAT", "O_TRUNC"];
+
+// Syscalls that change the filesystem at every path they are given
+const WRITE_SYSCALLS: &[&str] = &[
+    "creat", "mkdir", "mkdirat", "rmdir", "unlink", "unlinkat", "rename", "renameat", "renameat2", "link", "linkat",
+    "symlink", "symlinkat", "truncate", "chmod", "fchmodat", "chown", "lchown", "fchownat", "mknod", "mknodat",
+];
+
+const CONNECT_SYSCALLS: &[&str] = &["connect", "sendto", "sendmsg"];
+
+pub fn builtin_rules() -> Vec<VerdictRule> {
+    let rule = |id: &str, description: &str, score: u32, matcher: BehaviourMatcher| VerdictRule {
+        id: id.to_string(),
+        version: 1,
+        description: description.to_string(),
+        score,
+        matcher,
+        enabled: true,
+        builtin: true,
+        updated_at: Utc::now(),
+    };
+    let syscalls = |names: &[&str], args_contain: &[&str]| BehaviourMatcher::Syscall {
+        syscalls: names.iter().map(|s| s.to_string()).collect(),
+        args_contain: args_contain.iter().map(|s| s.to_string()).collect(),
+        min_count: None,
+    };
+
+    vec![
+        rule("ptrace", "Attempted to trace or write into another process", 40, syscalls(&["ptrace", "process_vm_writev"], &[])),
+        rule(
+            "write-outside-workdir",
+            "Wrote, created or removed files outside its working directory",
+            30,
+            BehaviourMatcher::FileWrite {
+                outside_workdir: true,
+                paths: vec![],
+                // Each run gets a private /tmp, so temporary files are not outside anything
+                ignore: ["/dev/null", "/dev/tty", "/dev/pts/", "/proc/self/fd/", "/tmp/"].iter().map(|s| s.to_string()).collect(),
+            },
+        ),
+        rule(
+            "outbound-connection",
+            "Connected or sent packets to a non-loopback address",
+            25,
+            BehaviourMatcher::Connection { include_loopback: false, ports: vec![] },
+        ),
+        rule("dns-lookup", "Resolved a host name", 10, BehaviourMatcher::DnsQuery { hosts: vec![] }),
+        rule("raw-socket", "Opened a raw or packet socket", 30, syscalls(&["socket"], &["SOCK_RAW", "AF_PACKET"])),
+        rule(
+            "privilege-change",
+            "Tried to change credentials, namespaces or mounts",
+            30,
+            syscalls(
+                &["setuid", "setreuid", "setresuid", "setgid", "capset", "setns", "unshare", "mount", "pivot_root", "chroot"],
+                &[],
+            ),
+        ),
+        rule("kernel-module", "Tried to load or unload a kernel module", 60, syscalls(&["init_module", "finit_module", "delete_module"], &[])),
+        rule("fileless-exec", "Created an anonymous in-memory file", 25, syscalls(&["memfd_create"], &[])),
+        rule(
+            "shell-spawn",
+            "Started a shell",
+            20,
+            BehaviourMatcher::Process {
+                executables: ["sh", "bash", "dash", "zsh", "ksh", "busybox"].iter().map(|s| s.to_string()).collect(),
+                min_count: None,
+            },
+        ),
+        rule(
+            "process-burst",
+            "Started 50 or more processes",
+            20,
+            BehaviourMatcher::Process { executables: vec![], min_count: Some(50) },
+        ),
+        rule("policy-violation", "Made syscalls its syscall policy denied or logged", 20, BehaviourMatcher::PolicyViolation),
+    ]
+}
+
+/// The latest version of every rule, by id.
+pub fn current_rules(state: &AppState) -> Vec<VerdictRule> {
+    let rules = state.verdict_rules.lock().unwrap();
+    let mut current: Vec<VerdictRule> = rules.values().filter_map(|versions| versions.last().cloned()).collect();
+    current.sort_by(|a, b| a.id.cmp(&b.id));
+    current
+}
+
+/// Stores the request as a new rule, or as the next version of an existing one.
+/// Built-in rules can be tuned this way too; earlier versions are kept.
+pub fn save_rule(state: &AppState, req: VerdictRuleRequest) -> VerdictRule {
+    let mut rules = state.verdict_rules.lock().unwrap();
+    let versions = rules.entry(req.id.clone()).or_default();
+    let previous = versions.last();
+
+    let rule = VerdictRule {
+        id: req.id,
+        version: previous.map(|rule| rule.version + 1).unwrap_or(1),
+        description: req.description,
+        score: req.score,
+        matcher: req.matcher,
+        enabled: req.enabled.unwrap_or(true),
+        builtin: previous.is_some_and(|rule| rule.builtin),
+        updated_at: Utc::now(),
+    };
+    versions.push(rule.clone());
+    rule
+}
+
+/// Scores a finished run against the enabled rules. Each triggered rule adds its
+/// score once, however often the behaviour occurred.
+pub fn evaluate(run: &SandboxRun, rules: &[VerdictRule]) -> RunVerdict {
+    let mut behaviours = Vec::new();
+    for rule in rules.iter().filter(|rule| rule.enabled) {
+        let (count, evidence) = match_behaviour(run, &rule.matcher);
+        if count > 0 {
+            behaviours.push(TriggeredBehaviour {
+                rule: rule.id.clone(),
+                rule_version: rule.version,
+                description: rule.description.clone(),
+                score: rule.score,
+                count,
+                evidence,
+            });
+        }
+    }
+
+    let score = behaviours.iter().map(|behaviour| behaviour.score).sum();
+    RunVerdict {
+        verdict: if score >= MALICIOUS_SCORE {
+            Verdict::Malicious
+        } else if score >= SUSPICIOUS_SCORE {
+            Verdict::Suspicious
+        } else {
+            Verdict::Benign
+        },
+        score,
+        behaviours,
+        rules: rules.iter().filter(|rule| rule.enabled).map(|rule| (rule.id.clone(), rule.version)).collect(),
+        scored_at: Utc::now(),
+    }
+}
+
+/// How often the run showed the behaviour (0 if below the matcher's minimum), with evidence.
+pub fn match_behaviour(run: &SandboxRun, matcher: &BehaviourMatcher) -> (u64, Vec<String>) {
+    let workspace = get_sandbox_workspace_root().join(run.id.to_string());
+    let workdir = match &run.request.working_dir {
+        Some(dir) => workspace.join(dir),
+        None => workspace.clone(),
+    };
+    let workdir = workdir.as_path();
+    let mut evidence = Evidence::default();
+
+    let minimum = match matcher {
+        BehaviourMatcher::Syscall { syscalls, args_contain, min_count } => {
+            for entry in run.syscall_log.iter().filter(|entry| syscalls.contains(&entry.syscall)) {
+                if args_contain.is_empty() || args_contain.iter().any(|needle| entry.args.contains(needle.as_str())) {
+                    evidence.add(describe(entry));
+                }
+            }
+            // The log may be capped; the counts are not
+            if args_contain.is_empty() {
+                evidence.count = syscalls.iter().map(|name| run.syscall_counts.get(name).copied().unwrap_or(0)).sum::<u64>().max(evidence.count);
+            }
+            min_count.unwrap_or(1)
+        }
+        BehaviourMatcher::FileWrite { outside_workdir, paths, ignore } => {
+            let selected = |path: &Path| {
+                let text = path.to_string_lossy();
+                (!outside_workdir || !path.starts_with(workdir))
+                    && (paths.is_empty() || paths.iter().any(|prefix| text.starts_with(prefix.as_str())))
+                    && !ignore.iter().any(|prefix| text.starts_with(prefix.as_str()))
+            };
+
+            for entry in &run.syscall_log {
+                let touched = if OPEN_SYSCALLS.contains(&entry.syscall.as_str()) {
+                    if !WRITE_FLAGS.iter().any(|flag| entry.args.contains(flag)) {
+                        continue;
+                    }
+                    quoted_strings(&entry.args).into_iter().take(1).collect()
+                } else if WRITE_SYSCALLS.contains(&entry.syscall.as_str()) {
+                    quoted_strings(&entry.args)
+                } else {
+                    continue;
+                };
+                if touched.iter().any(|path| selected(&resolve(workdir, path))) {
+                    evidence.add(describe(entry));
+                }
+            }
+
+            for change in &run.filesystem_changes {
+                if selected(&resolve(&workspace, &change.path)) {
+                    evidence.add(format!("{:?} {}", change.kind, change.path));
+                }
+            }
+            1
+        }
+        BehaviourMatcher::Process { executables, min_count } => {
+            // The root of the tree is the target itself
+            for process in run.process_tree.iter().filter(|process| process.parent_pid.is_some()) {
+                let name = process
+                    .executable
+                    .as_deref()
+                    .or(process.argv.first().map(String::as_str))
+                    .unwrap_or_default();
+                let base = name.rsplit('/').next().unwrap_or(name);
+                if executables.is_empty() || executables.iter().any(|executable| executable == base) {
+                    evidence.add(format!("{} {}", process.pid, process.argv.join(" ")));
+                }
+            }
+            min_count.unwrap_or(1)
+        }
+        BehaviourMatcher::Connection { include_loopback, ports } => {
+            let selected = |address: &SocketAddr| {
+                (*include_loopback || !address.ip().is_loopback()) && (ports.is_empty() || ports.contains(&address.port()))
+            };
+
+            for entry in run.syscall_log.iter().filter(|entry| CONNECT_SYSCALLS.contains(&entry.syscall.as_str())) {
+                if socket_address(&entry.args).is_some_and(|address| selected(&address)) {
+                    evidence.add(describe(entry));
+                }
+            }
+            for event in run.network_activity.iter().filter(|event| event.protocol != NetworkProtocol::Dns) {
+                let address = event.destination.as_deref().and_then(|destination| destination.parse().ok());
+                // The emulated network is never loopback from the sample's point of view
+                if address.map(|address| selected(&address)).unwrap_or(ports.is_empty()) {
+                    evidence.add(format!(
+                        "{:?} to {}",
+                        event.protocol,
+                        event.host.as_deref().or(event.destination.as_deref()).unwrap_or("unknown")
+                    ));
+                }
+            }
+            1
+        }
+        BehaviourMatcher::DnsQuery { hosts } => {
+            let selected = |host: &str| {
+                let host = host.trim_end_matches('.');
+                hosts.is_empty() || hosts.iter().any(|suffix| host == suffix || host.ends_with(&format!(".{}", suffix)))
+            };
+
+            for event in run.network_activity.iter().filter(|event| event.protocol == NetworkProtocol::Dns) {
+                if event.host.as_deref().is_some_and(selected) {
+                    evidence.add(format!("{} {}", event.request.as_deref().unwrap_or("query"), event.host.as_deref().unwrap_or_default()));
+                }
+            }
+            // Without the emulated resolver only the server is known, so these match only "any host"
+            if hosts.is_empty() {
+                for entry in run.syscall_log.iter().filter(|entry| CONNECT_SYSCALLS.contains(&entry.syscall.as_str())) {
+                    if socket_address(&entry.args).is_some_and(|address| address.port() == 53) {
+                        evidence.add(describe(entry));
+                    }
+                }
+            }
+            1
+        }
+        BehaviourMatcher::PolicyViolation => {
+            for violation in &run.policy_violations {
+                evidence.add(format!("{} {} ({}x, rule {})", violation.syscall, violation.policy, violation.count, violation.rule));
+                evidence.count += violation.count.saturating_sub(1);
+            }
+            1
+        }
+    };
+
+    if evidence.count >= minimum.max(1) {
+        (evidence.count, evidence.items)
+    } else {
+        (0, vec![])
+    }
+}
+
+#[derive(Default)]
+struct Evidence {
+    count: u64,
+    items: Vec<String>,
+}
+
+impl Evidence {
+    fn add(&mut self, item: String) {
+        self.count += 1;
+        if self.items.len() < MAX_EVIDENCE {
+            self.items.push(item);
+        }
+    }
+}
+
+fn describe(entry: &SyscallEntry) -> String {
+    format!("{}({}) = {}", entry.syscall, entry.args, entry.result)
+}
+
+/// Resolves a path against the directory the target ran in, without touching the filesystem.
+fn resolve(base: &Path, path: &str) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in base.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::CurDir => {}
+            other => resolved.push(other),
+        }
+    }
+    resolved
+}
+
+/// The inet address in a traced sockaddr, e.g.
+/// `{sa_family=AF_INET, sin_port=htons(443), sin_addr=inet_addr("93.184.216.34")}`.
+fn socket_address(args: &str) -> Option<SocketAddr> {
+    let between = |start: &str, end: &str| -> Option<&str> {
+        let from = args.find(start)? + start.len();
+        let to = args[from..].find(end)? + from;
+        Some(&args[from..to])
+    };
+
+    let (port, ip): (u16, IpAddr) = if args.contains("sa_family=AF_INET6") {
+        (
+            between("sin6_port=htons(", ")")?.parse().ok()?,
+            between("inet_pton(AF_INET6, \"", "\"")?.parse().ok()?,
+        )
+    } else if args.contains("sa_family=AF_INET") {
+        (between("sin_port=htons(", ")")?.parse().ok()?, between("inet_addr(\"", "\"")?.parse().ok()?)
+    } else {
+        return None;
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Human-readable summary for logs, e.g. "ptrace, raw-socket".
+pub fn summary(verdict: &RunVerdict) -> String {
+    verdict.behaviours.iter().map(|behaviour| behaviour.rule.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NetworkEvent, SandboxRunRequest};
+    use crate::services::sandbox;
+
+    fn call(syscall: &str, args: &str, result: &str) -> SyscallEntry {
+        SyscallEntry {
+            syscall: syscall.to_string(),
+            args: args.to_string(),
+            result: result.to_string(),
+            timestamp: Utc::now(),
+            allowed: true,
+            matched_rule: None,
+            pid: None,
+        }
+    }
+
+    fn run_with(syscall_log: Vec<SyscallEntry>) -> SandboxRun {
+        SandboxRun { syscall_log, ..sandbox::new_run(&SandboxRunRequest::default()) }
+    }
+
+    fn triggered(run: &SandboxRun) -> Vec<String> {
+        evaluate(run, &builtin_rules()).behaviours.into_iter().map(|behaviour| behaviour.rule).collect()
+    }
+
+    #[test]
+    fn temporary_and_workspace_files_are_not_outside_the_workdir() {
+        let run = run_with(vec![
+            call("openat", "AT_FDCWD, \"/tmp/tmpXXa1b2\", O_RDWR|O_CREAT|O_EXCL, 0600", "3"),
+            call("openat", "AT_FDCWD, \"out/result\", O_WRONLY|O_CREAT|O_TRUNC, 0644", "4"),
+            call("unlink", "\"/tmp/tmpXXa1b2\"", "0"),
+            call("openat", "AT_FDCWD, \"/etc/passwd\", O_RDONLY", "5"),
+        ]);
+        let verdict = evaluate(&run, &builtin_rules());
+        assert_eq!(verdict.verdict, Verdict::Benign);
+        assert!(verdict.behaviours.is_empty());
+
+        let run = run_with(vec![call("openat", "AT_FDCWD, \"/etc/cron.d/job\", O_WRONLY|O_CREAT, 0644", "3")]);
+        assert_eq!(triggered(&run), ["write-outside-workdir"]);
+    }
+
+    #[test]
+    fn scores_add_up_to_the_verdict() {
+        let ptrace = call("ptrace", "PTRACE_ATTACH, 1", "-1 EPERM (Operation not permitted)");
+        let raw = call("socket", "AF_INET, SOCK_RAW, IPPROTO_ICMP", "3");
+        let datagram = call("socket", "AF_INET, SOCK_DGRAM, IPPROTO_IP", "4");
+
+        let verdict = evaluate(&run_with(vec![ptrace.clone(), datagram.clone()]), &builtin_rules());
+        assert_eq!((verdict.verdict, verdict.score), (Verdict::Suspicious, 40));
+
+        let verdict = evaluate(&run_with(vec![ptrace.clone(), raw, datagram]), &builtin_rules());
+        assert_eq!((verdict.verdict, verdict.score), (Verdict::Malicious, 70));
+
+        // Disabled rules neither score nor count as applied
+        let mut rules = builtin_rules();
+        rules.iter_mut().find(|rule| rule.id == "ptrace").unwrap().enabled = false;
+        let verdict = evaluate(&run_with(vec![ptrace]), &rules);
+        assert_eq!(verdict.verdict, Verdict::Benign);
+        assert!(!verdict.rules.iter().any(|(id, _)| id == "ptrace"));
+    }
+
+    #[test]
+    fn matches_connections_and_dns_by_address_and_name() {
+        let mut run = run_with(vec![
+            call("connect", "3, {sa_family=AF_INET, sin_port=htons(443), sin_addr=inet_addr(\"93.184.216.34\")}, 16", "0"),
+            call("connect", "4, {sa_family=AF_INET6, sin6_port=htons(80), inet_pton(AF_INET6, \"::1\", &sin6_addr)}, 28", "0"),
+        ]);
+        run.network_activity.push(NetworkEvent {
+            protocol: NetworkProtocol::Dns,
+            destination: None,
+            host: Some("c2.evil.example.".to_string()),
+            request: Some("A".to_string()),
+            payload: String::new(),
+            payload_size: 0,
+            timestamp: Utc::now(),
+        });
+
+        let (count, evidence) = match_behaviour(&run, &BehaviourMatcher::Connection { include_loopback: false, ports: vec![] });
+        assert_eq!(count, 1);
+        assert!(evidence[0].contains("93.184.216.34"));
+        let (count, _) = match_behaviour(&run, &BehaviourMatcher::Connection { include_loopback: true, ports: vec![80] });
+        assert_eq!(count, 1);
+
+        let dns = |hosts: &[&str]| BehaviourMatcher::DnsQuery { hosts: hosts.iter().map(|s| s.to_string()).collect() };
+        assert_eq!(match_behaviour(&run, &dns(&["evil.example"])).0, 1);
+        assert_eq!(match_behaviour(&run, &dns(&["il.example"])).0, 0);
+    }
+}
@@ -0,0 +1,953 @@
+use crate::models::{ResourceLimit, SyscallEntry};
+use crate::services::recording::{SharedRecording, Stream};
+use crate::services::trace::MAX_SYSCALL_LOG;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use wasmi::{Caller, Config, Engine, Error, Extern, ExternType, Linker, Memory, Module, ResourceLimiter, Store, Val};
+
+pub const RUNTIME: &str = "wasmi 0.32";
+
+// Instructions a module may execute when the request sets neither `fuel` nor `timeout`
+pub const DEFAULT_FUEL: u64 = 10_000_000_000;
+// wasmi cannot interrupt pure computation, so this is what bounds how long a run's
+// thread can keep going after its deadline or cancellation
+pub const MAX_FUEL: u64 = 100_000_000_000;
+// Roughly what wasmi executes per second, used to turn a timeout into fuel
+const FUEL_PER_SECOND: u64 = 500_000_000;
+
+const WASI: &str = "wasi_snapshot_preview1";
+
+// The run's working directory, seen by the module as "/"
+const PREOPEN_FD: u32 = 3;
+const PREOPEN_NAME: &str = "/";
+
+// Host calls that would reach outside the preopened directory or onto the network
+const DENIED_CALLS: &[&str] = &["sock_accept", "sock_recv", "sock_send", "sock_shutdown", "path_symlink", "path_link"];
+
+// WASI errno values
+const SUCCESS: i32 = 0;
+const EACCES: i32 = 2;
+const EBADF: i32 = 8;
+const EEXIST: i32 = 20;
+const EFAULT: i32 = 21;
+const EFBIG: i32 = 22;
+const EINVAL: i32 = 28;
+const EIO: i32 = 29;
+const EISDIR: i32 = 31;
+const EMFILE: i32 = 33;
+const ENOENT: i32 = 44;
+const ENOSPC: i32 = 51;
+const ENOSYS: i32 = 52;
+const ENOTDIR: i32 = 54;
+const ENOTEMPTY: i32 = 55;
+const EPERM: i32 = 63;
+const ESPIPE: i32 = 70;
+const ENOTCAPABLE: i32 = 76;
+
+// WASI file types
+const FILETYPE_UNKNOWN: u8 = 0;
+const FILETYPE_CHARACTER_DEVICE: u8 = 2;
+const FILETYPE_DIRECTORY: u8 = 3;
+const FILETYPE_REGULAR_FILE: u8 = 4;
+
+// path_open flags and rights
+const OFLAGS_CREAT: i32 = 1;
+const OFLAGS_DIRECTORY: i32 = 2;
+const OFLAGS_EXCL: i32 = 4;
+const OFLAGS_TRUNC: i32 = 8;
+const FDFLAGS_APPEND: i32 = 1;
+const RIGHTS_FD_READ: i64 = 1 << 1;
+const RIGHTS_FD_WRITE: i64 = 1 << 6;
+
+/// What a module gets to see and how far it may go.
+pub struct WasmConfig {
+    pub args: Vec<String>,  // Including argv[0]
+    pub env: Vec<(String, String)>,
+    pub stdin: Vec<u8>,
+    pub root: PathBuf,
+    pub fuel: u64,
+    pub memory_limit: Option<u64>,
+    pub file_size_limit: Option<u64>,
+    pub open_files_limit: Option<u32>,
+    pub deadline: Option<Instant>,
+    pub clock: Option<DateTime<Utc>>,  // Fixed start time of a deterministic run
+    pub seed: u64,
+    pub cancelled: Box<dyn Fn() -> bool + Send + Sync>,
+    pub recording: SharedRecording,
+}
+
+pub struct WasmOutcome {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: Option<i32>,
+    pub error: Option<String>,  // Why the module did not run to completion
+    pub timed_out: bool,
+    pub limits_hit: Vec<ResourceLimit>,
+    pub calls: Vec<SyscallEntry>,
+    pub call_counts: BTreeMap<String, u64>,
+    pub peak_memory: u64,
+    pub fuel_consumed: u64,
+}
+
+enum Descriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+    Preopen(PathBuf),
+    Dir(PathBuf),
+    File(File),
+}
+
+enum Stop {
+    Timeout,
+    Cancelled,
+}
+
+struct Wasi {
+    args: Vec<String>,
+    env: Vec<String>,
+    stdin: Vec<u8>,
+    stdin_pos: usize,
+    root: PathBuf,
+    fds: BTreeMap<u32, Descriptor>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    recording: SharedRecording,
+    calls: Vec<SyscallEntry>,
+    call_counts: BTreeMap<String, u64>,
+    memory_limit: Option<u64>,
+    file_size_limit: Option<u64>,
+    open_files_limit: Option<u32>,
+    peak_memory: u64,
+    limits_hit: Vec<ResourceLimit>,
+    deadline: Option<Instant>,
+    cancelled: Box<dyn Fn() -> bool + Send + Sync>,
+    stop: Option<Stop>,
+    started: Instant,
+    clock: Option<DateTime<Utc>>,
+    rng: u64,
+}
+
+impl Wasi {
+    fn limit_hit(&mut self, limit: ResourceLimit) {
+        if !self.limits_hit.contains(&limit) {
+            self.limits_hit.push(limit);
+        }
+    }
+
+    fn log(&mut self, name: &str, args: String, errno: i32) {
+        *self.call_counts.entry(name.to_string()).or_default() += 1;
+        if self.calls.len() < MAX_SYSCALL_LOG {
+            self.calls.push(SyscallEntry {
+                syscall: name.to_string(),
+                args,
+                result: if errno == SUCCESS { "0".to_string() } else { errno_name(errno).to_string() },
+                timestamp: Utc::now(),
+                allowed: errno != ENOTCAPABLE,
+                matched_rule: (errno == ENOTCAPABLE).then(|| "wasi-capabilities".to_string()),
+                pid: None,
+            });
+        }
+    }
+
+    /// Resolves a guest path against a directory descriptor. Paths are taken apart
+    /// lexically and may not climb above the preopened directory; the guest cannot
+    /// create symlinks, and the workspace starts without any, so none lead out.
+    fn resolve(&self, dirfd: i32, path: &str) -> Result<PathBuf, i32> {
+        let base = match self.fds.get(&(dirfd as u32)) {
+            Some(Descriptor::Preopen(dir) | Descriptor::Dir(dir)) => dir,
+            Some(_) => return Err(ENOTDIR),
+            None => return Err(EBADF),
+        };
+
+        let mut relative = base.strip_prefix(&self.root).map_err(|_| ENOTCAPABLE)?.to_path_buf();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if !relative.pop() {
+                        return Err(ENOTCAPABLE);
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(ENOTCAPABLE),
+            }
+        }
+
+        let resolved = self.root.join(relative);
+        if std::fs::symlink_metadata(&resolved).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            return Err(ENOTCAPABLE);
+        }
+        Ok(resolved)
+    }
+
+    fn insert_fd(&mut self, descriptor: Descriptor) -> Result<u32, i32> {
+        if self.open_files_limit.is_some_and(|limit| self.fds.len() >= limit as usize) {
+            self.limit_hit(ResourceLimit::OpenFiles);
+            return Err(EMFILE);
+        }
+        let fd = (0..).find(|fd| !self.fds.contains_key(fd)).unwrap_or_default();
+        self.fds.insert(fd, descriptor);
+        Ok(fd)
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // splitmix64: reproducible from the seed, which is all a sandboxed guest needs
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl ResourceLimiter for Wasi {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool, wasmi::errors::MemoryError> {
+        if self.memory_limit.is_some_and(|limit| desired as u64 > limit) {
+            self.limit_hit(ResourceLimit::Memory);
+            return Ok(false);
+        }
+        self.peak_memory = self.peak_memory.max(desired as u64);
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: u32, _desired: u32, _maximum: Option<u32>) -> Result<bool, wasmi::errors::TableError> {
+        Ok(true)
+    }
+}
+
+/// The instruction budget of a run: the requested fuel, or about as much as fits in the
+/// timeout, never more than `MAX_FUEL`.
+pub fn fuel_budget(requested: Option<u64>, timeout: Option<Duration>) -> u64 {
+    requested
+        .or_else(|| timeout.map(|limit| (limit.as_secs_f64() * FUEL_PER_SECOND as f64) as u64))
+        .unwrap_or(DEFAULT_FUEL)
+        .min(MAX_FUEL)
+}
+
+/// Runs a WASI command module to completion on the calling thread; it blocks,
+/// so callers move it off the async runtime. Every host call is logged.
+pub fn run(module: &[u8], config: WasmConfig) -> WasmOutcome {
+    let mut fds = BTreeMap::new();
+    fds.insert(0, Descriptor::Stdin);
+    fds.insert(1, Descriptor::Stdout);
+    fds.insert(2, Descriptor::Stderr);
+    fds.insert(PREOPEN_FD, Descriptor::Preopen(config.root.clone()));
+
+    let wasi = Wasi {
+        args: config.args,
+        env: config.env.into_iter().map(|(key, value)| format!("{}={}", key, value)).collect(),
+        stdin: config.stdin,
+        stdin_pos: 0,
+        root: config.root,
+        fds,
+        stdout: Vec::new(),
+        stderr: Vec::new(),
+        recording: config.recording,
+        calls: Vec::new(),
+        call_counts: BTreeMap::new(),
+        memory_limit: config.memory_limit,
+        file_size_limit: config.file_size_limit,
+        open_files_limit: config.open_files_limit,
+        peak_memory: 0,
+        limits_hit: Vec::new(),
+        deadline: config.deadline,
+        cancelled: config.cancelled,
+        stop: None,
+        started: Instant::now(),
+        clock: config.clock,
+        rng: config.seed,
+    };
+
+    let mut engine_config = Config::default();
+    engine_config.consume_fuel(true);
+    let engine = Engine::new(&engine_config);
+    let mut store = Store::new(&engine, wasi);
+    store.limiter(|wasi| wasi as &mut dyn ResourceLimiter);
+    let _ = store.set_fuel(config.fuel);
+
+    let result = instantiate_and_start(&engine, &mut store, module);
+    let fuel_consumed = config.fuel.saturating_sub(store.get_fuel().unwrap_or(0));
+    let mut wasi = store.into_data();
+
+    // Computation past the deadline only ends when the fuel does, which is a timeout
+    let out_of_fuel = result.as_ref().err().and_then(|e| e.as_trap_code()) == Some(wasmi::core::TrapCode::OutOfFuel);
+    if out_of_fuel && wasi.stop.is_none() && wasi.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        wasi.stop = Some(Stop::Timeout);
+    }
+
+    let (exit_code, error) = match result {
+        Ok(()) => (Some(0), None),
+        Err(e) => match e.i32_exit_status() {
+            Some(status) => (Some(status), None),
+            None => {
+                let message = match (&wasi.stop, e.as_trap_code()) {
+                    (Some(Stop::Timeout), _) => "wasm: timed out".to_string(),
+                    (Some(Stop::Cancelled), _) => "wasm: cancelled".to_string(),
+                    (None, Some(wasmi::core::TrapCode::OutOfFuel)) => {
+                        wasi.limit_hit(ResourceLimit::Fuel);
+                        format!("wasm: out of fuel after {} instructions", fuel_consumed)
+                    }
+                    _ => format!("wasm: {}", e),
+                };
+                (None, Some(message))
+            }
+        },
+    };
+
+    WasmOutcome {
+        stdout: wasi.stdout,
+        stderr: wasi.stderr,
+        exit_code,
+        error,
+        timed_out: matches!(wasi.stop, Some(Stop::Timeout)),
+        limits_hit: wasi.limits_hit,
+        calls: wasi.calls,
+        call_counts: wasi.call_counts,
+        peak_memory: wasi.peak_memory,
+        fuel_consumed,
+    }
+}
+
+fn instantiate_and_start(engine: &Engine, store: &mut Store<Wasi>, bytes: &[u8]) -> Result<(), Error> {
+    let module = Module::new(engine, bytes)?;
+    let mut linker = Linker::<Wasi>::new(engine);
+    define_wasi(&mut linker)?;
+
+    // Calls this runtime does not provide fail with ENOSYS, or ENOTCAPABLE if they are never granted
+    for import in module.imports() {
+        let ExternType::Func(ty) = import.ty() else {
+            return Err(Error::new(format!("Unsupported import {}::{}", import.module(), import.name())));
+        };
+        if import.module() != WASI {
+            return Err(Error::new(format!("Unsupported import {}::{}", import.module(), import.name())));
+        }
+
+        let name = import.name().to_string();
+        let errno = if DENIED_CALLS.contains(&name.as_str()) { ENOTCAPABLE } else { ENOSYS };
+        // Fails for the calls defined above, which keep their implementation
+        let _ = linker.func_new(WASI, import.name(), ty.clone(), move |mut caller, _params, results| {
+            caller.data_mut().log(&name, String::new(), errno);
+            if let Some(result) = results.first_mut() {
+                *result = Val::I32(errno);
+            }
+            Ok(())
+        });
+    }
+
+    let instance = linker.instantiate(&mut *store, &module)?.start(&mut *store)?;
+    let start = instance.get_typed_func::<(), ()>(&*store, "_start")?;
+    start.call(store, ())
+}
+
+/// Checks the deadline and cancellation, then runs the call and logs it.
+fn host_call(
+    caller: &mut Caller<'_, Wasi>,
+    name: &str,
+    args: String,
+    call: impl FnOnce(&mut [u8], &mut Wasi) -> Result<(), i32>,
+) -> Result<i32, Error> {
+    {
+        let wasi = caller.data_mut();
+        if wasi.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            wasi.stop = Some(Stop::Timeout);
+        } else if (wasi.cancelled)() {
+            wasi.stop = Some(Stop::Cancelled);
+        }
+        if wasi.stop.is_some() {
+            wasi.log(name, args, EINVAL);
+            return Err(Error::new("run stopped"));
+        }
+    }
+
+    let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+        caller.data_mut().log(name, args, EFAULT);
+        return Ok(EFAULT);
+    };
+    let errno = with_memory(memory, caller, call);
+    caller.data_mut().log(name, args, errno);
+    Ok(errno)
+}
+
+fn with_memory(memory: Memory, caller: &mut Caller<'_, Wasi>, call: impl FnOnce(&mut [u8], &mut Wasi) -> Result<(), i32>) -> i32 {
+    let (memory, wasi) = memory.data_and_store_mut(caller);
+    match call(memory, wasi) {
+        Ok(()) => SUCCESS,
+        Err(errno) => errno,
+    }
+}
+
+fn define_wasi(linker: &mut Linker<Wasi>) -> Result<(), Error> {
+    linker.func_wrap(WASI, "args_sizes_get", |mut caller: Caller<'_, Wasi>, argc: i32, size: i32| {
+        host_call(&mut caller, "args_sizes_get", String::new(), |memory, wasi| {
+            write_u32(memory, argc, wasi.args.len() as u32)?;
+            write_u32(memory, size, wasi.args.iter().map(|arg| arg.len() as u32 + 1).sum())
+        })
+    })?;
+    linker.func_wrap(WASI, "args_get", |mut caller: Caller<'_, Wasi>, argv: i32, buf: i32| {
+        host_call(&mut caller, "args_get", String::new(), |memory, wasi| write_strings(memory, &wasi.args, argv, buf))
+    })?;
+    linker.func_wrap(WASI, "environ_sizes_get", |mut caller: Caller<'_, Wasi>, count: i32, size: i32| {
+        host_call(&mut caller, "environ_sizes_get", String::new(), |memory, wasi| {
+            write_u32(memory, count, wasi.env.len() as u32)?;
+            write_u32(memory, size, wasi.env.iter().map(|var| var.len() as u32 + 1).sum())
+        })
+    })?;
+    linker.func_wrap(WASI, "environ_get", |mut caller: Caller<'_, Wasi>, environ: i32, buf: i32| {
+        host_call(&mut caller, "environ_get", String::new(), |memory, wasi| write_strings(memory, &wasi.env, environ, buf))
+    })?;
+
+    linker.func_wrap(WASI, "clock_res_get", |mut caller: Caller<'_, Wasi>, id: i32, resolution: i32| {
+        host_call(&mut caller, "clock_res_get", format!("{}", id), |memory, _| write_u64(memory, resolution, 1))
+    })?;
+    linker.func_wrap(WASI, "clock_time_get", |mut caller: Caller<'_, Wasi>, id: i32, _precision: i64, time: i32| {
+        host_call(&mut caller, "clock_time_get", format!("{}", id), |memory, wasi| {
+            let elapsed = wasi.started.elapsed().as_nanos() as u64;
+            let now = match id {
+                0 => {
+                    let start = wasi.clock.unwrap_or_else(Utc::now);
+                    let base = start.timestamp_nanos_opt().unwrap_or_default().max(0) as u64;
+                    if wasi.clock.is_some() { base + elapsed } else { base }
+                }
+                1..=3 => elapsed,
+                _ => return Err(EINVAL),
+            };
+            write_u64(memory, time, now)
+        })
+    })?;
+    linker.func_wrap(WASI, "random_get", |mut caller: Caller<'_, Wasi>, buf: i32, len: i32| {
+        host_call(&mut caller, "random_get", format!("{}", len), |memory, wasi| {
+            let out = slice_mut(memory, buf, len)?;
+            for chunk in out.chunks_mut(8) {
+                let bytes = wasi.next_random().to_le_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+            Ok(())
+        })
+    })?;
+    linker.func_wrap(WASI, "sched_yield", |mut caller: Caller<'_, Wasi>| {
+        host_call(&mut caller, "sched_yield", String::new(), |_, _| Ok(()))
+    })?;
+    linker.func_wrap(WASI, "proc_exit", |mut caller: Caller<'_, Wasi>, code: i32| -> Result<(), Error> {
+        caller.data_mut().log("proc_exit", format!("{}", code), SUCCESS);
+        Err(Error::i32_exit(code))
+    })?;
+
+    linker.func_wrap(WASI, "fd_write", |mut caller: Caller<'_, Wasi>, fd: i32, iovs: i32, iovs_len: i32, written: i32| {
+        host_call(&mut caller, "fd_write", format!("{}, {} iovs", fd, iovs_len), |memory, wasi| {
+            let mut data = Vec::new();
+            for (ptr, len) in iovecs(memory, iovs, iovs_len)? {
+                data.extend_from_slice(slice(memory, ptr, len)?);
+            }
+
+            let file_size_limit = wasi.file_size_limit;
+            match wasi.fds.get_mut(&(fd as u32)) {
+                Some(Descriptor::Stdout) => {
+                    wasi.recording.lock().unwrap().output(Stream::Stdout, &data);
+                    wasi.stdout.extend_from_slice(&data);
+                }
+                Some(Descriptor::Stderr) => {
+                    wasi.recording.lock().unwrap().output(Stream::Stderr, &data);
+                    wasi.stderr.extend_from_slice(&data);
+                }
+                Some(Descriptor::File(file)) => {
+                    let end = file.stream_position().map_err(io_errno)? + data.len() as u64;
+                    if file_size_limit.is_some_and(|limit| end > limit) {
+                        wasi.limit_hit(ResourceLimit::FileSize);
+                        return Err(EFBIG);
+                    }
+                    file.write_all(&data).map_err(io_errno)?;
+                }
+                Some(_) => return Err(EBADF),
+                None => return Err(EBADF),
+            }
+            write_u32(memory, written, data.len() as u32)
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_read", |mut caller: Caller<'_, Wasi>, fd: i32, iovs: i32, iovs_len: i32, read: i32| {
+        host_call(&mut caller, "fd_read", format!("{}, {} iovs", fd, iovs_len), |memory, wasi| {
+            let mut total = 0;
+            for (ptr, len) in iovecs(memory, iovs, iovs_len)? {
+                let out = slice_mut(memory, ptr, len)?;
+                let count = match wasi.fds.get_mut(&(fd as u32)) {
+                    Some(Descriptor::Stdin) => {
+                        let rest = &wasi.stdin[wasi.stdin_pos..];
+                        let count = rest.len().min(out.len());
+                        out[..count].copy_from_slice(&rest[..count]);
+                        wasi.stdin_pos += count;
+                        count
+                    }
+                    Some(Descriptor::File(file)) => file.read(out).map_err(io_errno)?,
+                    Some(Descriptor::Preopen(_) | Descriptor::Dir(_)) => return Err(EISDIR),
+                    _ => return Err(EBADF),
+                };
+                total += count;
+                if count < len as usize {
+                    break;
+                }
+            }
+            write_u32(memory, read, total as u32)
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_close", |mut caller: Caller<'_, Wasi>, fd: i32| {
+        host_call(&mut caller, "fd_close", format!("{}", fd), |_, wasi| {
+            wasi.fds.remove(&(fd as u32)).map(|_| ()).ok_or(EBADF)
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_seek", |mut caller: Caller<'_, Wasi>, fd: i32, offset: i64, whence: i32, new_offset: i32| {
+        host_call(&mut caller, "fd_seek", format!("{}, {}, {}", fd, offset, whence), |memory, wasi| {
+            let Some(Descriptor::File(file)) = wasi.fds.get_mut(&(fd as u32)) else {
+                return Err(if wasi.fds.contains_key(&(fd as u32)) { ESPIPE } else { EBADF });
+            };
+            let from = match whence {
+                0 => SeekFrom::Start(u64::try_from(offset).map_err(|_| EINVAL)?),
+                1 => SeekFrom::Current(offset),
+                2 => SeekFrom::End(offset),
+                _ => return Err(EINVAL),
+            };
+            let position = file.seek(from).map_err(io_errno)?;
+            write_u64(memory, new_offset, position)
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_tell", |mut caller: Caller<'_, Wasi>, fd: i32, offset: i32| {
+        host_call(&mut caller, "fd_tell", format!("{}", fd), |memory, wasi| {
+            let Some(Descriptor::File(file)) = wasi.fds.get_mut(&(fd as u32)) else {
+                return Err(if wasi.fds.contains_key(&(fd as u32)) { ESPIPE } else { EBADF });
+            };
+            let position = file.stream_position().map_err(io_errno)?;
+            write_u64(memory, offset, position)
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_fdstat_get", |mut caller: Caller<'_, Wasi>, fd: i32, stat: i32| {
+        host_call(&mut caller, "fd_fdstat_get", format!("{}", fd), |memory, wasi| {
+            let filetype = match wasi.fds.get(&(fd as u32)).ok_or(EBADF)? {
+                Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => FILETYPE_CHARACTER_DEVICE,
+                Descriptor::Preopen(_) | Descriptor::Dir(_) => FILETYPE_DIRECTORY,
+                Descriptor::File(_) => FILETYPE_REGULAR_FILE,
+            };
+            let out = slice_mut(memory, stat, 24)?;
+            out.fill(0);
+            out[0] = filetype;
+            // Rights are not tracked per descriptor; the path checks are the capability boundary
+            out[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+            out[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+            Ok(())
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_filestat_get", |mut caller: Caller<'_, Wasi>, fd: i32, stat: i32| {
+        host_call(&mut caller, "fd_filestat_get", format!("{}", fd), |memory, wasi| {
+            let metadata = match wasi.fds.get(&(fd as u32)).ok_or(EBADF)? {
+                Descriptor::Stdin | Descriptor::Stdout | Descriptor::Stderr => None,
+                Descriptor::Preopen(dir) | Descriptor::Dir(dir) => Some(std::fs::metadata(dir).map_err(io_errno)?),
+                Descriptor::File(file) => Some(file.metadata().map_err(io_errno)?),
+            };
+            write_filestat(memory, stat, metadata.as_ref())
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_prestat_get", |mut caller: Caller<'_, Wasi>, fd: i32, prestat: i32| {
+        host_call(&mut caller, "fd_prestat_get", format!("{}", fd), |memory, wasi| {
+            let Some(Descriptor::Preopen(_)) = wasi.fds.get(&(fd as u32)) else {
+                return Err(EBADF);
+            };
+            let out = slice_mut(memory, prestat, 8)?;
+            out.fill(0);
+            out[4..8].copy_from_slice(&(PREOPEN_NAME.len() as u32).to_le_bytes());
+            Ok(())
+        })
+    })?;
+    linker.func_wrap(WASI, "fd_prestat_dir_name", |mut caller: Caller<'_, Wasi>, fd: i32, path: i32, len: i32| {
+        host_call(&mut caller, "fd_prestat_dir_name", format!("{}", fd), |memory, wasi| {
+            let Some(Descriptor::Preopen(_)) = wasi.fds.get(&(fd as u32)) else {
+                return Err(EBADF);
+            };
+            let out = slice_mut(memory, path, len)?;
+            let name = PREOPEN_NAME.as_bytes();
+            let count = name.len().min(out.len());
+            out[..count].copy_from_slice(&name[..count]);
+            Ok(())
+        })
+    })?;
+    linker.func_wrap(
+        WASI,
+        "fd_readdir",
+        |mut caller: Caller<'_, Wasi>, fd: i32, buf: i32, buf_len: i32, cookie: i64, used: i32| {
+            host_call(&mut caller, "fd_readdir", format!("{}, {}", fd, cookie), |memory, wasi| {
+                let dir = match wasi.fds.get(&(fd as u32)).ok_or(EBADF)? {
+                    Descriptor::Preopen(dir) | Descriptor::Dir(dir) => dir,
+                    _ => return Err(ENOTDIR),
+                };
+
+                let mut names: Vec<(String, u8)> = std::fs::read_dir(dir)
+                    .map_err(io_errno)?
+                    .filter_map(Result::ok)
+                    .map(|entry| {
+                        let filetype = entry.file_type().map(|kind| filetype_of(&kind)).unwrap_or(FILETYPE_UNKNOWN);
+                        (entry.file_name().to_string_lossy().to_string(), filetype)
+                    })
+                    .collect();
+                names.sort();
+
+                // Entries past the buffer are cut off; the guest calls again with a bigger one
+                let mut entries = Vec::new();
+                for (index, (name, filetype)) in names.iter().enumerate().skip(cookie.max(0) as usize) {
+                    entries.extend_from_slice(&(index as u64 + 1).to_le_bytes());
+                    entries.extend_from_slice(&0u64.to_le_bytes());
+                    entries.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                    entries.extend_from_slice(&[*filetype, 0, 0, 0]);
+                    entries.extend_from_slice(name.as_bytes());
+                }
+                let out = slice_mut(memory, buf, buf_len)?;
+                let count = entries.len().min(out.len());
+                out[..count].copy_from_slice(&entries[..count]);
+                write_u32(memory, used, count as u32)
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        WASI,
+        "path_open",
+        |mut caller: Caller<'_, Wasi>,
+         dirfd: i32,
+         _dirflags: i32,
+         path: i32,
+         path_len: i32,
+         oflags: i32,
+         rights: i64,
+         _inheriting: i64,
+         fdflags: i32,
+         fd: i32| {
+            let name = read_path(&caller, path, path_len);
+            host_call(&mut caller, "path_open", format!("{}, \"{}\", {:#x}", dirfd, name, oflags), |memory, wasi| {
+                let resolved = wasi.resolve(dirfd, &name)?;
+                let descriptor = if oflags & OFLAGS_DIRECTORY != 0 || resolved.is_dir() {
+                    if !resolved.is_dir() {
+                        return Err(if resolved.exists() { ENOTDIR } else { ENOENT });
+                    }
+                    Descriptor::Dir(resolved)
+                } else {
+                    let write = rights & RIGHTS_FD_WRITE != 0 || oflags & (OFLAGS_CREAT | OFLAGS_TRUNC) != 0;
+                    let file = OpenOptions::new()
+                        .read(rights & RIGHTS_FD_READ != 0 || !write)
+                        .write(write && fdflags & FDFLAGS_APPEND == 0)
+                        .append(fdflags & FDFLAGS_APPEND != 0)
+                        .create(oflags & OFLAGS_CREAT != 0)
+                        .create_new(oflags & OFLAGS_CREAT != 0 && oflags & OFLAGS_EXCL != 0)
+                        .truncate(oflags & OFLAGS_TRUNC != 0)
+                        .open(&resolved)
+                        .map_err(io_errno)?;
+                    Descriptor::File(file)
+                };
+                let opened = wasi.insert_fd(descriptor)?;
+                write_u32(memory, fd, opened)
+            })
+        },
+    )?;
+    linker.func_wrap(
+        WASI,
+        "path_filestat_get",
+        |mut caller: Caller<'_, Wasi>, dirfd: i32, _flags: i32, path: i32, path_len: i32, stat: i32| {
+            let name = read_path(&caller, path, path_len);
+            host_call(&mut caller, "path_filestat_get", format!("{}, \"{}\"", dirfd, name), |memory, wasi| {
+                let metadata = std::fs::metadata(wasi.resolve(dirfd, &name)?).map_err(io_errno)?;
+                write_filestat(memory, stat, Some(&metadata))
+            })
+        },
+    )?;
+    linker.func_wrap(WASI, "path_create_directory", |mut caller: Caller<'_, Wasi>, dirfd: i32, path: i32, path_len: i32| {
+        let name = read_path(&caller, path, path_len);
+        host_call(&mut caller, "path_create_directory", format!("{}, \"{}\"", dirfd, name), |_, wasi| {
+            std::fs::create_dir(wasi.resolve(dirfd, &name)?).map_err(io_errno)
+        })
+    })?;
+    linker.func_wrap(WASI, "path_remove_directory", |mut caller: Caller<'_, Wasi>, dirfd: i32, path: i32, path_len: i32| {
+        let name = read_path(&caller, path, path_len);
+        host_call(&mut caller, "path_remove_directory", format!("{}, \"{}\"", dirfd, name), |_, wasi| {
+            std::fs::remove_dir(wasi.resolve(dirfd, &name)?).map_err(io_errno)
+        })
+    })?;
+    linker.func_wrap(WASI, "path_unlink_file", |mut caller: Caller<'_, Wasi>, dirfd: i32, path: i32, path_len: i32| {
+        let name = read_path(&caller, path, path_len);
+        host_call(&mut caller, "path_unlink_file", format!("{}, \"{}\"", dirfd, name), |_, wasi| {
+            std::fs::remove_file(wasi.resolve(dirfd, &name)?).map_err(io_errno)
+        })
+    })?;
+    linker.func_wrap(
+        WASI,
+        "path_rename",
+        |mut caller: Caller<'_, Wasi>, old_fd: i32, old: i32, old_len: i32, new_fd: i32, new: i32, new_len: i32| {
+            let (from, to) = (read_path(&caller, old, old_len), read_path(&caller, new, new_len));
+            host_call(&mut caller, "path_rename", format!("\"{}\", \"{}\"", from, to), |_, wasi| {
+                std::fs::rename(wasi.resolve(old_fd, &from)?, wasi.resolve(new_fd, &to)?).map_err(io_errno)
+            })
+        },
+    )?;
+
+    Ok(())
+}
+
+// ============================================================================
+// Guest Memory
+// ============================================================================
+
+fn slice(memory: &[u8], ptr: i32, len: i32) -> Result<&[u8], i32> {
+    let start = ptr as u32 as usize;
+    memory.get(start..start + len as u32 as usize).ok_or(EFAULT)
+}
+
+fn slice_mut(memory: &mut [u8], ptr: i32, len: i32) -> Result<&mut [u8], i32> {
+    let start = ptr as u32 as usize;
+    memory.get_mut(start..start + len as u32 as usize).ok_or(EFAULT)
+}
+
+fn write_u32(memory: &mut [u8], ptr: i32, value: u32) -> Result<(), i32> {
+    slice_mut(memory, ptr, 4)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn write_u64(memory: &mut [u8], ptr: i32, value: u64) -> Result<(), i32> {
+    slice_mut(memory, ptr, 8)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+fn read_u32(memory: &[u8], ptr: i32) -> Result<u32, i32> {
+    Ok(u32::from_le_bytes(slice(memory, ptr, 4)?.try_into().unwrap()))
+}
+
+/// The (pointer, length) pairs of a ciovec or iovec array.
+fn iovecs(memory: &[u8], iovs: i32, count: i32) -> Result<Vec<(i32, i32)>, i32> {
+    (0..count)
+        .map(|i| {
+            let base = iovs.wrapping_add(i * 8);
+            Ok((read_u32(memory, base)? as i32, read_u32(memory, base.wrapping_add(4))? as i32))
+        })
+        .collect()
+}
+
+/// Writes NUL-terminated strings into `buf` and a pointer to each into `pointers`.
+fn write_strings(memory: &mut [u8], strings: &[String], pointers: i32, buf: i32) -> Result<(), i32> {
+    let mut offset = buf;
+    for (i, string) in strings.iter().enumerate() {
+        write_u32(memory, pointers.wrapping_add(i as i32 * 4), offset as u32)?;
+        let out = slice_mut(memory, offset, string.len() as i32 + 1)?;
+        out[..string.len()].copy_from_slice(string.as_bytes());
+        out[string.len()] = 0;
+        offset = offset.wrapping_add(string.len() as i32 + 1);
+    }
+    Ok(())
+}
+
+/// A path argument, read before the call so it can be logged even if the call fails.
+fn read_path(caller: &Caller<'_, Wasi>, ptr: i32, len: i32) -> String {
+    let Some(memory) = caller.get_export("memory").and_then(Extern::into_memory) else {
+        return String::new();
+    };
+    slice(memory.data(caller), ptr, len)
+        .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+        .unwrap_or_default()
+}
+
+fn write_filestat(memory: &mut [u8], ptr: i32, metadata: Option<&std::fs::Metadata>) -> Result<(), i32> {
+    use std::os::unix::fs::MetadataExt;
+
+    let out = slice_mut(memory, ptr, 64)?;
+    out.fill(0);
+    let Some(metadata) = metadata else {
+        out[16] = FILETYPE_CHARACTER_DEVICE;
+        return Ok(());
+    };
+
+    let nanos = |seconds: i64, nanoseconds: i64| (seconds.max(0) as u64) * 1_000_000_000 + nanoseconds.max(0) as u64;
+    out[0..8].copy_from_slice(&metadata.dev().to_le_bytes());
+    out[8..16].copy_from_slice(&metadata.ino().to_le_bytes());
+    out[16] = filetype_of(&metadata.file_type());
+    out[24..32].copy_from_slice(&metadata.nlink().to_le_bytes());
+    out[32..40].copy_from_slice(&metadata.len().to_le_bytes());
+    out[40..48].copy_from_slice(&nanos(metadata.atime(), metadata.atime_nsec()).to_le_bytes());
+    out[48..56].copy_from_slice(&nanos(metadata.mtime(), metadata.mtime_nsec()).to_le_bytes());
+    out[56..64].copy_from_slice(&nanos(metadata.ctime(), metadata.ctime_nsec()).to_le_bytes());
+    Ok(())
+}
+
+fn filetype_of(kind: &std::fs::FileType) -> u8 {
+    if kind.is_dir() {
+        FILETYPE_DIRECTORY
+    } else if kind.is_file() {
+        FILETYPE_REGULAR_FILE
+    } else {
+        FILETYPE_UNKNOWN
+    }
+}
+
+fn io_errno(error: std::io::Error) -> i32 {
+    match error.raw_os_error() {
+        Some(libc::EACCES) => EACCES,
+        Some(libc::EPERM) => EPERM,
+        Some(libc::ENOENT) => ENOENT,
+        Some(libc::EEXIST) => EEXIST,
+        Some(libc::EISDIR) => EISDIR,
+        Some(libc::ENOTDIR) => ENOTDIR,
+        Some(libc::ENOTEMPTY) => ENOTEMPTY,
+        Some(libc::ENOSPC) | Some(libc::EDQUOT) => ENOSPC,
+        Some(libc::EFBIG) => EFBIG,
+        Some(libc::EMFILE) => EMFILE,
+        Some(libc::EINVAL) => EINVAL,
+        _ => EIO,
+    }
+}
+
+fn errno_name(errno: i32) -> &'static str {
+    match errno {
+        EACCES => "EACCES",
+        EBADF => "EBADF",
+        EEXIST => "EEXIST",
+        EFAULT => "EFAULT",
+        EFBIG => "EFBIG",
+        EINVAL => "EINVAL",
+        EIO => "EIO",
+        EISDIR => "EISDIR",
+        EMFILE => "EMFILE",
+        ENOENT => "ENOENT",
+        ENOSPC => "ENOSPC",
+        ENOSYS => "ENOSYS",
+        ENOTDIR => "ENOTDIR",
+        ENOTEMPTY => "ENOTEMPTY",
+        EPERM => "EPERM",
+        ESPIPE => "ESPIPE",
+        ENOTCAPABLE => "ENOTCAPABLE",
+        _ => "EUNKNOWN",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::recording::Recording;
+
+    fn run_wat(source: &str, root: &Path, fuel: u64) -> WasmOutcome {
+        let config = WasmConfig {
+            args: vec!["module.wasm".to_string()],
+            env: vec![],
+            stdin: vec![],
+            root: root.to_path_buf(),
+            fuel,
+            memory_limit: None,
+            file_size_limit: None,
+            open_files_limit: None,
+            deadline: None,
+            clock: None,
+            seed: 1,
+            cancelled: Box::new(|| false),
+            recording: Recording::piped(),
+        };
+        run(&wat::parse_str(source).unwrap(), config)
+    }
+
+    // Opens `path` relative to the preopen and exits with the errno
+    fn open_module(path: &str, oflags: i32) -> String {
+        format!(
+            r#"(module
+                (import "wasi_snapshot_preview1" "path_open"
+                    (func $path_open (param i32 i32 i32 i32 i32 i64 i64 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "{path}")
+                (func (export "_start")
+                    (call $proc_exit
+                        (call $path_open (i32.const 3) (i32.const 0) (i32.const 16) (i32.const {len})
+                            (i32.const {oflags}) (i64.const 64) (i64.const 0) (i32.const 0) (i32.const 8)))))"#,
+            len = path.len(),
+        )
+    }
+
+    fn temp_root() -> PathBuf {
+        let root = std::env::temp_dir().join(format!("aegis-wasm-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    #[test]
+    fn writes_to_stdout_and_exits_with_the_modules_status() {
+        let outcome = run_wat(
+            r#"(module
+                (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\10\00\00\00\03\00\00\00")
+                (data (i32.const 16) "hi\n")
+                (func (export "_start")
+                    (drop (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8)))
+                    (call $proc_exit (i32.const 3))))"#,
+            &std::env::temp_dir(),
+            DEFAULT_FUEL,
+        );
+
+        assert_eq!(outcome.stdout, b"hi\n");
+        assert_eq!((outcome.exit_code, outcome.error), (Some(3), None));
+        assert_eq!(outcome.call_counts.get("fd_write"), Some(&1));
+        assert_eq!(outcome.calls[0].args, "1, 1 iovs");
+    }
+
+    #[test]
+    fn paths_stay_inside_the_preopened_directory() {
+        let root = temp_root();
+        let outside = run_wat(&open_module("../etc/passwd", 0), &root, DEFAULT_FUEL);
+        let absolute = run_wat(&open_module("/etc/passwd", 0), &root, DEFAULT_FUEL);
+        let inside = run_wat(&open_module("out/../result.txt", OFLAGS_CREAT), &root, DEFAULT_FUEL);
+        let created = root.join("result.txt").is_file();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(outside.exit_code, Some(ENOTCAPABLE));
+        assert_eq!(absolute.exit_code, Some(ENOTCAPABLE));
+        let denied = &outside.calls[0];
+        assert!(!denied.allowed);
+        assert_eq!(denied.matched_rule.as_deref(), Some("wasi-capabilities"));
+
+        assert_eq!(inside.exit_code, Some(SUCCESS));
+        assert!(created);
+    }
+
+    #[test]
+    fn network_and_link_calls_are_not_capable_and_unknown_ones_not_implemented() {
+        let outcome = run_wat(
+            r#"(module
+                (import "wasi_snapshot_preview1" "sock_send" (func $sock_send (param i32 i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "poll_oneoff" (func $poll_oneoff (param i32 i32 i32 i32) (result i32)))
+                (import "wasi_snapshot_preview1" "proc_exit" (func $proc_exit (param i32)))
+                (memory (export "memory") 1)
+                (func (export "_start")
+                    (call $proc_exit
+                        (i32.add
+                            (i32.mul (call $sock_send (i32.const 3) (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0)) (i32.const 100))
+                            (call $poll_oneoff (i32.const 0) (i32.const 0) (i32.const 0) (i32.const 0))))))"#,
+            &std::env::temp_dir(),
+            DEFAULT_FUEL,
+        );
+
+        assert_eq!(outcome.exit_code, Some(ENOTCAPABLE * 100 + ENOSYS));
+        assert_eq!(outcome.calls[0].syscall, "sock_send");
+        assert!(!outcome.calls[0].allowed);
+    }
+
+    #[test]
+    fn running_out_of_fuel_is_a_limit_hit() {
+        let outcome = run_wat(r#"(module (memory (export "memory") 1) (func (export "_start") (loop (br 0))))"#, &std::env::temp_dir(), 10_000);
+
+        assert_eq!(outcome.exit_code, None);
+        assert!(outcome.error.is_some_and(|error| error.starts_with("wasm: out of fuel")));
+        assert_eq!(outcome.limits_hit, [ResourceLimit::Fuel]);
+        assert!(!outcome.timed_out);
+    }
+
+    #[test]
+    fn fuel_follows_the_request_then_the_timeout() {
+        assert_eq!(fuel_budget(Some(1_000), Some(Duration::from_secs(60))), 1_000);
+        assert_eq!(fuel_budget(None, Some(Duration::from_secs(2))), 2 * FUEL_PER_SECOND);
+        assert_eq!(fuel_budget(None, None), DEFAULT_FUEL);
+        assert_eq!(fuel_budget(Some(u64::MAX), None), MAX_FUEL);
+    }
+}
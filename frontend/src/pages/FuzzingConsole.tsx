@@ -29,10 +29,13 @@ ChartJS.register(
 export default function FuzzingConsole() {
   const [activeCampaign, setActiveCampaign] = useState<types.FuzzCampaign | null>(null);
   const [crashes, setCrashes] = useState<types.CrashInfo[]>([]);
+  const [targets, setTargets] = useState<types.StoredBinary[]>([]);
+  const [targetSha256, setTargetSha256] = useState('');
   const [loading, setLoading] = useState(false);
 
   useEffect(() => {
     loadCampaigns();
+    loadTargets();
     // Auto-refresh every 2 seconds
     const interval = setInterval(loadCampaigns, 2000);
     return () => clearInterval(interval);
@@ -51,6 +54,18 @@ export default function FuzzingConsole() {
     }
   };
 
+  const loadTargets = async () => {
+    try {
+      const data = await api.listStoredBinaries();
+      setTargets(data);
+      if (data.length > 0) {
+        setTargetSha256((current) => current || data[0].sha256);
+      }
+    } catch (error) {
+      console.error('Failed to load stored binaries:', error);
+    }
+  };
+
   const loadCrashes = async (campaignId: string) => {
     try {
       const data = await api.getFuzzCrashes(campaignId);
@@ -61,10 +76,11 @@ export default function FuzzingConsole() {
   };
 
   const handleStartFuzzing = async () => {
+    if (!targetSha256) return;
     setLoading(true);
     try {
       const campaign = await api.startFuzzing({
-        target_binary: 'test_target',
+        target_sha256: targetSha256,
         corpus_dir: '/tmp/corpus',
         crash_dir: '/tmp/crashes',
       });
@@ -152,14 +168,28 @@ export default function FuzzingConsole() {
                 {loading ? 'Stopping...' : 'Stop Campaign'}
               </button>
             ) : (
-              <button
-                onClick={handleStartFuzzing}
-                disabled={loading}
-                className="flex items-center gap-2 bg-green-600 hover:bg-green-700 disabled:bg-slate-700 text-white px-4 py-2 rounded-lg font-medium transition-colors"
-              >
-                <Play size={18} />
-                {loading ? 'Starting...' : 'Start Campaign'}
-              </button>
+              <>
+                <select
+                  value={targetSha256}
+                  onChange={(e) => setTargetSha256(e.target.value)}
+                  className="aegis-input"
+                >
+                  {targets.length === 0 && <option value="">No stored binaries</option>}
+                  {targets.map((target) => (
+                    <option key={target.sha256} value={target.sha256}>
+                      {target.name} ({target.sha256.slice(0, 12)})
+                    </option>
+                  ))}
+                </select>
+                <button
+                  onClick={handleStartFuzzing}
+                  disabled={loading || !targetSha256}
+                  className="flex items-center gap-2 bg-green-600 hover:bg-green-700 disabled:bg-slate-700 text-white px-4 py-2 rounded-lg font-medium transition-colors"
+                >
+                  <Play size={18} />
+                  {loading ? 'Starting...' : 'Start Campaign'}
+                </button>
+              </>
             )}
           </div>
         </div>
@@ -139,12 +139,14 @@ export default function SandboxRunner() {
               <div>
                 <div className="flex justify-between text-sm mb-1">
                   <span className="text-slate-400">Memory</span>
-                  <span className="text-white">{runResult.resource_usage.memory_mb} MB</span>
+                  <span className="text-white">
+                    {runResult.resource_usage.memory_mb != null ? `${runResult.resource_usage.memory_mb.toFixed(1)} MB` : 'n/a'}
+                  </span>
                 </div>
                 <div className="w-full bg-slate-700 rounded-full h-2">
                   <div
                     className="bg-blue-600 h-2 rounded-full"
-                    style={{ width: `${(runResult.resource_usage.memory_mb / 1000) * 100}%` }}
+                    style={{ width: `${((runResult.resource_usage.memory_mb ?? 0) / 1000) * 100}%` }}
                   />
                 </div>
               </div>
@@ -152,12 +154,14 @@ export default function SandboxRunner() {
               <div>
                 <div className="flex justify-between text-sm mb-1">
                   <span className="text-slate-400">CPU</span>
-                  <span className="text-white">{runResult.resource_usage.cpu_percent}%</span>
+                  <span className="text-white">
+                    {runResult.resource_usage.cpu_percent != null ? `${runResult.resource_usage.cpu_percent.toFixed(0)}%` : 'n/a'}
+                  </span>
                 </div>
                 <div className="w-full bg-slate-700 rounded-full h-2">
                   <div
                     className="bg-green-600 h-2 rounded-full"
-                    style={{ width: `${runResult.resource_usage.cpu_percent}%` }}
+                    style={{ width: `${Math.min(runResult.resource_usage.cpu_percent ?? 0, 100)}%` }}
                   />
                 </div>
               </div>
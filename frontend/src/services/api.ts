@@ -59,6 +59,38 @@ export async function runSandbox(req: types.SandboxRunRequest): Promise<types.Sa
   });
 }
 
+export async function cancelSandboxRun(id: string): Promise<{ success: boolean; run_id: string }> {
+  return request(`/api/sandbox/${id}/cancel`, {
+    method: 'POST',
+  });
+}
+
+export async function rescoreSandboxRun(id: string): Promise<{ success: boolean; data: types.RunVerdict }> {
+  return request(`/api/sandbox/${id}/verdict`, {
+    method: 'POST',
+  });
+}
+
+export async function remapSandboxRun(id: string): Promise<{ success: boolean; data: types.AttackTechnique[] }> {
+  return request(`/api/sandbox/${id}/attack`, {
+    method: 'POST',
+  });
+}
+
+export async function replaySandboxRun(
+  id: string,
+  req: types.ReplayRequest = {}
+): Promise<{ success: boolean; data: { run: types.SandboxRun; comparison: types.RunComparison } }> {
+  return request(`/api/sandbox/${id}/replay`, {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function diffSandboxRuns(left: string, right: string): Promise<{ success: boolean; data: types.RunDiff }> {
+  return request(`/api/sandbox/diff/${left}/${right}`);
+}
+
 export async function getSandboxLogs(id: string): Promise<{ stdout: string; stderr: string; syscall_log: types.SyscallEntry[] }> {
   return request(`/api/sandbox/logs/${id}`);
 }
@@ -71,6 +103,99 @@ export async function listSandboxRuns(): Promise<types.SandboxRun[]> {
   return request<types.SandboxRun[]>('/api/sandbox/runs');
 }
 
+export async function startSandboxBatch(req: types.BatchRunRequest): Promise<{ success: boolean; data: types.SandboxBatch }> {
+  return request('/api/sandbox/batch', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function getSandboxBatch(id: string): Promise<{ success: boolean; data: types.SandboxBatch }> {
+  return request(`/api/sandbox/batch/${id}`);
+}
+
+export async function listSandboxBatches(): Promise<{ success: boolean; data: types.SandboxBatch[] }> {
+  return request('/api/sandbox/batches');
+}
+
+export async function runSandboxSelftest(
+  req: types.SelftestRequest = {}
+): Promise<{ success: boolean; data: types.SelftestReport }> {
+  return request('/api/sandbox/selftest', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function listSandboxSelftests(): Promise<{ success: boolean; data: types.SelftestReport[] }> {
+  return request('/api/sandbox/selftest');
+}
+
+export async function getSandboxFilesystemChanges(id: string): Promise<{ success: boolean; data: types.FileChange[] }> {
+  return request(`/api/sandbox/filesystem/${id}`);
+}
+
+export async function getSandboxProcessTree(id: string): Promise<{ success: boolean; data: types.ProcessNode[] }> {
+  return request(`/api/sandbox/processes/${id}`);
+}
+
+export async function getSandboxNetworkActivity(
+  id: string
+): Promise<{ success: boolean; data: { mode?: types.NetworkMode; events: types.NetworkEvent[] } }> {
+  return request(`/api/sandbox/network/${id}`);
+}
+
+export async function getSandboxPcapSummary(id: string): Promise<{ success: boolean; data: types.PcapSummary }> {
+  return request(`/api/sandbox/pcap/${id}`);
+}
+
+export async function getSandboxStatus(id: string): Promise<{ success: boolean; data: types.SandboxRun }> {
+  return request(`/api/sandbox/status/${id}`);
+}
+
+export async function getSandboxQueue(): Promise<{
+  running: number;
+  queued: number;
+  max_concurrent: number;
+  max_per_project: number;
+  max_queued: number;
+}> {
+  return request('/api/sandbox/queue');
+}
+
+export async function listSandboxArtifacts(id: string): Promise<{ success: boolean; data: types.RunArtifact[] }> {
+  return request(`/api/sandbox/artifacts/${id}`);
+}
+
+export function sandboxArtifactUrl(id: string, name: string): string {
+  return `${API_BASE}/api/sandbox/artifacts/${id}/${name}`;
+}
+
+export async function getSandboxRecording(id: string): Promise<types.Asciicast> {
+  const response = await fetch(sandboxArtifactUrl(id, '.aegis/session.cast'));
+  if (!response.ok) {
+    throw new Error(`HTTP ${response.status}: ${response.statusText}`);
+  }
+
+  const [header, ...events] = (await response.text())
+    .split('\n')
+    .filter((line) => line.length > 0)
+    .map((line) => JSON.parse(line));
+  return { header, events };
+}
+
+// Terminal output arrives as binary frames, everything else as PtyServerMessage JSON
+export function openSandboxPty(req: types.PtyStartRequest): WebSocket {
+  const socket = new WebSocket(`${API_BASE.replace(/^http/, 'ws')}/api/sandbox/pty`);
+  socket.binaryType = 'arraybuffer';
+  socket.addEventListener('open', () => socket.send(JSON.stringify(req)));
+  return socket;
+}
+
+export function sendPtyMessage(socket: WebSocket, message: types.PtyClientMessage): void {
+  socket.send(JSON.stringify(message));
+}
+
 // ============================================================================
 // Fuzzing API
 // ============================================================================
@@ -127,6 +252,173 @@ export async function createProject(req: types.CreateProjectRequest): Promise<ty
   });
 }
 
+// ============================================================================
+// Policies API
+// ============================================================================
+
+export async function listPolicies(): Promise<types.SyscallPolicy[]> {
+  return request<types.SyscallPolicy[]>('/api/policies');
+}
+
+export async function getPolicy(name: string): Promise<types.SyscallPolicy> {
+  return request<types.SyscallPolicy>(`/api/policies/${encodeURIComponent(name)}`);
+}
+
+export async function savePolicy(req: types.CreatePolicyRequest): Promise<types.SyscallPolicy> {
+  return request<types.SyscallPolicy>('/api/policies', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function learnPolicy(req: types.LearnPolicyRequest): Promise<{ success: boolean; data: types.LearnedPolicy }> {
+  return request('/api/policies/learn', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export function policySeccompUrl(name: string): string {
+  return `${API_BASE}/api/policies/${encodeURIComponent(name)}/seccomp`;
+}
+
+export async function deletePolicy(name: string): Promise<{ success: boolean; name: string }> {
+  return request(`/api/policies/${encodeURIComponent(name)}`, {
+    method: 'DELETE',
+  });
+}
+
+// ============================================================================
+// Verdict Rules API
+// ============================================================================
+
+export async function listVerdictRules(): Promise<types.VerdictRule[]> {
+  return request<types.VerdictRule[]>('/api/verdict/rules');
+}
+
+export async function getVerdictRule(id: string): Promise<types.VerdictRule> {
+  return request<types.VerdictRule>(`/api/verdict/rules/${encodeURIComponent(id)}`);
+}
+
+export async function getVerdictRuleVersions(id: string): Promise<types.VerdictRule[]> {
+  return request<types.VerdictRule[]>(`/api/verdict/rules/${encodeURIComponent(id)}/versions`);
+}
+
+export async function saveVerdictRule(req: types.VerdictRuleRequest): Promise<types.VerdictRule> {
+  return request<types.VerdictRule>('/api/verdict/rules', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function deleteVerdictRule(id: string): Promise<{ success: boolean; id: string }> {
+  return request(`/api/verdict/rules/${encodeURIComponent(id)}`, {
+    method: 'DELETE',
+  });
+}
+
+// ============================================================================
+// ATT&CK Mapping API
+// ============================================================================
+
+export async function getAttackMapping(): Promise<types.AttackMapping> {
+  return request<types.AttackMapping>('/api/attack/mapping');
+}
+
+export async function saveAttackMapping(req: types.AttackMappingRequest): Promise<types.AttackMapping> {
+  return request<types.AttackMapping>('/api/attack/mapping', {
+    method: 'PUT',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function resetAttackMapping(): Promise<types.AttackMapping> {
+  return request<types.AttackMapping>('/api/attack/mapping', {
+    method: 'DELETE',
+  });
+}
+
+// ============================================================================
+// Binary Inspection API
+// ============================================================================
+
+export async function listBinaries(): Promise<types.BinaryInspection[]> {
+  return request<types.BinaryInspection[]>('/api/binaries');
+}
+
+export async function inspectBinary(req: types.InspectBinaryRequest): Promise<types.BinaryInspection> {
+  return request<types.BinaryInspection>('/api/binaries', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
+export async function getBinaryInspection(id: string): Promise<types.BinaryInspection> {
+  return request<types.BinaryInspection>(`/api/binaries/${id}/inspect`);
+}
+
+// ============================================================================
+// Root Filesystem API
+// ============================================================================
+
+export async function listRootfsImages(): Promise<types.RootfsImage[]> {
+  return request<types.RootfsImage[]>('/api/rootfs');
+}
+
+export async function getRootfsImage(name: string): Promise<types.RootfsImage> {
+  return request<types.RootfsImage>(`/api/rootfs/${encodeURIComponent(name)}`);
+}
+
+export async function uploadRootfsImage(name: string, archive: Blob): Promise<types.RootfsImage> {
+  return request<types.RootfsImage>(`/api/rootfs/${encodeURIComponent(name)}`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/octet-stream' },
+    body: archive,
+  });
+}
+
+export async function deleteRootfsImage(name: string): Promise<{ success: boolean; name: string }> {
+  return request(`/api/rootfs/${encodeURIComponent(name)}`, {
+    method: 'DELETE',
+  });
+}
+
+// ============================================================================
+// Binary Store API
+// ============================================================================
+
+export async function listStoredBinaries(): Promise<types.StoredBinary[]> {
+  return request<types.StoredBinary[]>('/api/store');
+}
+
+export async function getStoredBinary(sha256: string): Promise<types.StoredBinary> {
+  return request<types.StoredBinary>(`/api/store/${sha256}`);
+}
+
+export async function uploadStoredBinary(params: types.StoreUploadParams, binary: Blob): Promise<types.StoredBinary> {
+  const query = new URLSearchParams({ name: params.name });
+  if (params.uploader) query.set('uploader', params.uploader);
+  if (params.tags?.length) query.set('tags', params.tags.join(','));
+  return request<types.StoredBinary>(`/api/store?${query}`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/octet-stream' },
+    body: binary,
+  });
+}
+
+export async function deleteStoredBinary(sha256: string): Promise<{ success: boolean; sha256: string }> {
+  return request(`/api/store/${sha256}`, {
+    method: 'DELETE',
+  });
+}
+
+export async function collectStoredBinaries(req: types.StoreGcRequest = {}): Promise<types.StoreGcReport> {
+  return request<types.StoreGcReport>('/api/store/gc', {
+    method: 'POST',
+    body: JSON.stringify(req),
+  });
+}
+
 // ============================================================================
 // Health Check
 // ============================================================================
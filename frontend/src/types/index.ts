@@ -26,40 +26,651 @@ export interface PolicyValidationResult {
 // ============================================================================
 
 export interface SandboxRunRequest {
-  binary_path?: string;
+  binary_path?: string; // Relative to the working directory, or inside the rootfs image
+  binary_sha256?: string; // A StoredBinary, in place of binary_path
   code?: string;
+  backend?: SandboxBackend;
+  fuel?: number;
+  rootfs?: string;
   memory_limit?: string;
   timeout?: string;
   network_enabled?: boolean;
+  network_mode?: NetworkMode;
+  pids_limit?: number;
+  open_files_limit?: number;
+  file_size_limit?: string;
+  cpu_quota?: number;
+  cpu_shares?: number;
+  disk_quota?: string;
+  core_size_limit?: string;
+  args?: string[];
+  stdin?: string; // Base64-encoded bytes
+  working_dir?: string;
+  env?: Record<string, string>;
+  inherit_env?: boolean;
+  input_files?: SandboxInputFile[];
+  output_paths?: string[];
+  project_id?: string;
+  priority?: RunPriority;
+  syscall_policy?: string;
+  deterministic?: DeterministicConfig;
+  rerun_of?: string;
+}
+
+export interface DeterministicConfig {
+  clock?: string;
+  timezone?: string;
+  seed?: number;
+  cpu?: number;
+  hostname?: string;
+}
+
+export interface DeterministicSettings {
+  clock: string;
+  timezone: string;
+  seed: number;
+  cpu: number;
+  hostname: string;
+  environment: Record<string, string>;
+}
+
+export interface ToolchainVersions {
+  aegis_sandbox?: string;
+  aegiscc?: string;
+  wasm_runtime?: string;
+}
+
+export interface ReplayRequest {
+  use_current_policy?: boolean;
+}
+
+export interface RunComparison {
+  original_run: string;
+  replay_run: string;
+  identical: boolean;
+  setup_differences: RunDifference[];
+  outcome_differences: RunDifference[];
+}
+
+export interface RunDifference {
+  field: string;
+  original: any;
+  replay: any;
+}
+
+export interface PtyStartRequest {
+  run: SandboxRunRequest;
+  cols?: number;
+  rows?: number;
+}
+
+export type PtyClientMessage =
+  | { type: 'Input'; data: string }
+  | { type: 'Resize'; cols: number; rows: number };
+
+export type PtyServerMessage =
+  | { type: 'Started'; run_id: string }
+  | { type: 'Exit'; run: SandboxRun }
+  | { type: 'Error'; error: string };
+
+export interface BatchRunRequest {
+  name?: string;
+  base?: SandboxRunRequest;
+  matrix?: BatchMatrix;
+}
+
+export interface BatchMatrix {
+  binaries?: string[];
+  stored_binaries?: string[];
+  code?: string[];
+  memory_limits?: string[];
+  timeouts?: string[];
+  network_modes?: NetworkMode[];
+  syscall_policies?: (string | null)[];
+}
+
+export interface SandboxBatch {
+  id: string;
+  name?: string;
+  status: BatchStatus;
+  cells: BatchCell[];
+  outcomes: BatchOutcome[];
+  created_at: string;
+  completed_at?: string;
+}
+
+export type BatchStatus = 'Running' | 'Completed';
+
+export interface BatchCell {
+  target: string;
+  memory_limit?: string;
+  timeout?: string;
+  network_mode?: NetworkMode;
+  syscall_policy?: string;
+  status: RunStatus;
+  run_id?: string;
+  error?: string;
+  exit_code?: number;
+  signal?: number;
+  limits_hit: ResourceLimit[];
+  execution_time_ms?: number;
+  memory_mb?: number;
+  syscalls_count?: number;
+  policy_violations: number;
+  network_requests: number;
+  outcome?: number;
+}
+
+export interface BatchOutcome {
+  status: RunStatus;
+  exit_code?: number;
+  signal?: number;
+  limits_hit: ResourceLimit[];
+  cells: number;
+}
+
+export interface SelftestRequest {
+  syscall_policy?: string;
+  network_mode?: NetworkMode;
+}
+
+export interface SelftestReport {
+  id: string;
+  passed: boolean;
+  syscall_policy?: string;
+  network_mode: NetworkMode;
+  sandbox_version?: string;
+  probes: ProbeResult[];
+  started_at: string;
+  completed_at: string;
+}
+
+export interface ProbeResult {
+  probe: string;
+  description: string;
+  outcome: ProbeOutcome;
+  detail: string;
+  run_id?: string;
+}
+
+export type ProbeOutcome = 'Blocked' | 'Escaped' | 'Error';
+
+export interface RunDiff {
+  left_run: string;
+  right_run: string;
+  exit_status: FieldDiff[];
+  resource_usage: FieldDiff[];
+  stdout: Aligned<string>[];
+  stderr: Aligned<string>[];
+  syscalls: Aligned<SyscallEntry>[];
+  syscalls_aligned: boolean;
+  first_divergence?: number;
+  filesystem_changes: Aligned<FileChange>[];
+  network_flows: Aligned<NetworkFlow>[];
+}
+
+export interface FieldDiff {
+  field: string;
+  left: any;
+  right: any;
+  changed: boolean;
+}
+
+export interface Aligned<T> {
+  op: DiffOp;
+  left?: T;
+  right?: T;
+}
+
+export type DiffOp = 'Equal' | 'Changed' | 'Removed' | 'Added';
+
+export interface ReproductionReport {
+  original_run: string;
+  reproduced: boolean;
+  stdout_matches: boolean;
+  exit_status_matches: boolean;
+  syscalls_match: boolean;
+  settings_match: boolean;
+  first_syscall_divergence?: SyscallDivergence;
+}
+
+export interface SyscallDivergence {
+  index: number;
+  original?: string;
+  rerun?: string;
+}
+
+export type RunPriority = 'Low' | 'Normal' | 'High';
+
+export interface SandboxInputFile {
+  path: string;
+  content: string; // Base64-encoded bytes
 }
 
 export interface SandboxRun {
   id: string;
   status: RunStatus;
+  project_id?: string;
+  priority: RunPriority;
+  queue_position?: number;
   stdout: string;
   stderr: string;
   exit_code?: number;
+  signal?: number;
+  backtrace: StackFrame[];
   syscall_log: SyscallEntry[];
   resource_usage: ResourceUsage;
+  limits_hit: ResourceLimit[];
+  artifacts: RunArtifact[];
+  filesystem_changes: FileChange[];
+  process_tree: ProcessNode[];
+  syscall_policy?: string;
+  policy_violations: PolicyViolation[];
+  syscall_counts: Record<string, number>;
+  network_mode?: NetworkMode;
+  network_activity: NetworkEvent[];
+  network_activity_truncated: boolean; // Events or connections beyond the emulated network's caps were dropped
+  deterministic?: DeterministicSettings;
+  rerun_of?: string;
+  reproduction?: ReproductionReport;
+  request: SandboxRunRequest;
+  policy_snapshot?: SyscallPolicy;
+  toolchain: ToolchainVersions;
+  replay_of?: string;
+  interactive: boolean;
+  backend: SandboxBackend;
+  rootfs_image?: RootfsImage;
+  binary_inspection?: BinaryInspection;
+  verdict?: RunVerdict;
+  attack_techniques: AttackTechnique[];
   created_at: string;
   completed_at?: string;
 }
 
-export type RunStatus = 'Pending' | 'Running' | 'Completed' | 'Failed' | 'Timeout';
+export type NetworkMode = 'None' | 'Loopback' | 'Emulated';
+
+export interface NetworkEvent {
+  protocol: NetworkProtocol;
+  destination?: string;
+  host?: string;
+  request?: string;
+  payload: string; // Base64-encoded bytes
+  payload_size: number;
+  timestamp: string;
+}
+
+export type NetworkProtocol = 'Dns' | 'Tcp' | 'Http';
+
+export interface NetworkFlow {
+  protocol: string;
+  source: string;
+  source_port?: number;
+  destination: string;
+  destination_port?: number;
+  packets: number;
+  bytes_sent: number;
+  bytes_received: number;
+  first_payload?: string; // Base64-encoded bytes
+  first_seen: string;
+  last_seen: string;
+}
+
+export interface PcapSummary {
+  artifact: string;
+  link_type: number;
+  packets: number;
+  flows: NetworkFlow[];
+}
+
+export type RunStatus = 'Pending' | 'Running' | 'Completed' | 'Failed' | 'Timeout' | 'Cancelled';
+
+export type ResourceLimit =
+  | 'Memory'
+  | 'Timeout'
+  | 'Processes'
+  | 'OpenFiles'
+  | 'FileSize'
+  | 'Cpu'
+  | 'Disk'
+  | 'CoreSize'
+  | 'Fuel';
+
+export type SandboxBackend = 'Native' | 'Wasm';
+
+export interface RunArtifact {
+  name: string;
+  kind: ArtifactKind;
+  size: number;
+  sha256: string;
+}
+
+export type ArtifactKind = 'Output' | 'Pcap' | 'CoreDump' | 'Recording';
+
+export interface AsciicastHeader {
+  version: 2;
+  width: number;
+  height: number;
+  timestamp?: number;
+  env?: Record<string, string>;
+}
+
+// [seconds since start, 'o' output | 'i' input | 'r' resize, data]
+export type AsciicastEvent = [number, 'o' | 'i' | 'r', string];
+
+export interface Asciicast {
+  header: AsciicastHeader;
+  events: AsciicastEvent[];
+}
+
+export interface StackFrame {
+  index: number;
+  address?: string;
+  function?: string;
+  file?: string;
+  line?: number;
+  module?: string;
+}
+
+export interface FileChange {
+  path: string; // Relative to the workspace, or absolute under /tmp and in a rootfs image
+  kind: FileChangeKind;
+  renamed_from?: string;
+  size_before?: number;
+  size_after?: number;
+  sha256_before?: string;
+  sha256_after?: string;
+}
+
+export type FileChangeKind = 'Created' | 'Modified' | 'Deleted' | 'Renamed';
 
 export interface SyscallEntry {
   syscall: string;
   args: string;
   result: string;
   timestamp: string;
-  allowed: boolean;
+  allowed: boolean; // False when the trace shows the filter refused the call
+  matched_rule?: string; // Policy rule that decides the call, from evaluating the trace
+  pid?: number;
+}
+
+export interface ProcessNode {
+  pid: number;
+  parent_pid?: number;
+  argv: string[];
+  executable?: string;
+  started_at: string;
+  ended_at?: string;
+  exit_code?: number;
+  signal?: number;
+  core_dumped: boolean;
+  resource_usage: ProcessResourceUsage;
+}
+
+export interface ProcessResourceUsage {
+  syscalls_count: number;
+  user_time_ms?: number;
+  system_time_ms?: number;
+  max_rss_kb?: number;
 }
 
 export interface ResourceUsage {
-  memory_mb: number;
-  cpu_percent: number;
+  memory_mb?: number; // Peak, when the sandbox or runtime measured it
+  cpu_percent?: number; // CPU time over wall time, when the sandbox measured it
   execution_time_ms: number;
   syscalls_count: number;
+  fuel_consumed?: number;
+}
+
+// ============================================================================
+// Syscall Policy Types
+// ============================================================================
+
+export interface SyscallPolicy {
+  name: string;
+  description: string;
+  mode: PolicyMode;
+  default_action?: PolicyAction;
+  rules: SyscallRule[];
+  builtin: boolean;
+  updated_at: string;
+}
+
+export type PolicyMode = 'Allowlist' | 'Denylist';
+
+export interface SyscallRule {
+  name?: string;
+  syscalls: string[];
+  conditions?: ArgCondition[];
+  action: PolicyAction;
+}
+
+export interface ArgCondition {
+  index: number;
+  op: ConditionOp;
+  value: number;
+  value_two?: number;
+}
+
+export type ConditionOp =
+  | 'Equal'
+  | 'NotEqual'
+  | 'LessThan'
+  | 'LessOrEqual'
+  | 'GreaterThan'
+  | 'GreaterOrEqual'
+  | 'MaskedEqual';
+
+export type PolicyAction = 'Allow' | 'Log' | { Errno: number } | 'Kill';
+
+export interface PolicyViolation {
+  policy: string;
+  syscall: string;
+  rule: string;
+  action: PolicyAction;
+  count: number;
+  not_enforced: number; // Denied calls the trace shows went through anyway
+}
+
+export interface CreatePolicyRequest {
+  name: string;
+  description: string;
+  mode: PolicyMode;
+  default_action?: PolicyAction;
+  rules: SyscallRule[];
+}
+
+export interface LearnPolicyRequest {
+  name: string;
+  description?: string;
+  run: SandboxRunRequest;
+  iterations?: number;
+  save?: boolean;
+}
+
+export interface LearnedPolicy {
+  policy: SyscallPolicy;
+  run_ids: string[];
+  learned_from: string[];
+  saved: boolean;
+}
+
+// ============================================================================
+// Verdict Types
+// ============================================================================
+
+export interface VerdictRule {
+  id: string;
+  version: number;
+  description: string;
+  score: number;
+  matcher: BehaviourMatcher;
+  enabled: boolean;
+  builtin: boolean;
+  updated_at: string;
+}
+
+export type BehaviourMatcher =
+  | { type: 'Syscall'; syscalls: string[]; args_contain?: string[]; min_count?: number }
+  | { type: 'FileWrite'; outside_workdir?: boolean; paths?: string[]; ignore?: string[] }
+  | { type: 'Process'; executables?: string[]; min_count?: number }
+  | { type: 'Connection'; include_loopback?: boolean; ports?: number[] }
+  | { type: 'DnsQuery'; hosts?: string[] }
+  | { type: 'PolicyViolation' };
+
+export interface VerdictRuleRequest {
+  id: string;
+  description: string;
+  score: number;
+  matcher: BehaviourMatcher;
+  enabled?: boolean;
+}
+
+export type Verdict = 'Benign' | 'Suspicious' | 'Malicious';
+
+export interface RunVerdict {
+  verdict: Verdict;
+  score: number;
+  behaviours: TriggeredBehaviour[];
+  rules: Record<string, number>;
+  scored_at: string;
+}
+
+export interface TriggeredBehaviour {
+  rule: string;
+  rule_version: number;
+  description: string;
+  score: number;
+  count: number;
+  evidence: string[];
+}
+
+// ============================================================================
+// ATT&CK Types
+// ============================================================================
+
+export interface AttackMapping {
+  attack_version: string;
+  techniques: TechniqueMapping[];
+  updated_at: string;
+}
+
+export interface TechniqueMapping {
+  technique_id: string;
+  name: string;
+  tactics: string[];
+  matcher: BehaviourMatcher;
+}
+
+export interface AttackMappingRequest {
+  attack_version: string;
+  techniques: TechniqueMapping[];
+}
+
+export interface AttackTechnique {
+  technique_id: string;
+  name: string;
+  tactics: string[];
+  count: number;
+  evidence: string[];
+}
+
+// ============================================================================
+// Binary Inspection Types
+// ============================================================================
+
+export interface InspectBinaryRequest {
+  sha256: string; // Of a binary in the store
+}
+
+export interface BinaryInspection {
+  id: string;
+  path: string;
+  size: number;
+  class: string;
+  endianness: string;
+  architecture: string;
+  file_type: ElfFileType;
+  entry_point: number;
+  interpreter?: string;
+  statically_linked: boolean;
+  libraries: string[];
+  rpaths: string[];
+  sections: ElfSection[];
+  imported_symbols: string[];
+  exported_symbols: string[];
+  stripped: boolean;
+  hardening: Hardening;
+  inspected_at: string;
+}
+
+export type ElfFileType = 'Executable' | 'PieExecutable' | 'SharedLibrary' | 'Relocatable' | 'Core' | 'Other';
+
+export interface ElfSection {
+  name: string;
+  section_type: string;
+  address: number;
+  size: number;
+  flags: string;
+}
+
+export interface Hardening {
+  nx: boolean;
+  pie: boolean;
+  relro: Relro;
+  stack_canary: boolean;
+  fortify: boolean;
+  fortified_functions: string[];
+}
+
+export type Relro = 'None' | 'Partial' | 'Full';
+
+// ============================================================================
+// Root Filesystem Types
+// ============================================================================
+
+export type RootfsFormat = 'Tarball' | 'OciLayout';
+
+export interface RootfsImage {
+  name: string;
+  format: RootfsFormat;
+  digest: string;
+  os?: string;
+  architecture?: string;
+  layers: number;
+  size_bytes: number;
+  file_count: number;
+  created_at: string;
+}
+
+// ============================================================================
+// Binary Store Types
+// ============================================================================
+
+export interface StoredBinary {
+  sha256: string;
+  name: string;
+  size: number;
+  uploader?: string;
+  tags: string[];
+  uploaded_at: string;
+}
+
+export interface StoreUploadParams {
+  name: string;
+  uploader?: string;
+  tags?: string[];
+}
+
+export interface StoreGcRequest {
+  min_age?: string;
+  dry_run?: boolean;
+}
+
+export interface StoreGcReport {
+  removed: StoredBinary[];
+  freed_bytes: number;
+  remaining: number;
+  dry_run: boolean;
 }
 
 // ============================================================================
@@ -67,7 +678,7 @@ export interface ResourceUsage {
 // ============================================================================
 
 export interface FuzzStartRequest {
-  target_binary: string;
+  target_sha256: string; // A StoredBinary
   corpus_dir: string;
   crash_dir: string;
   timeout?: string;
@@ -78,6 +689,7 @@ export interface FuzzCampaign {
   name: string;
   status: FuzzStatus;
   target_binary: string;
+  target_sha256?: string;
   stats: FuzzStats;
   started_at: string;
   stopped_at?: string;
@@ -113,6 +725,7 @@ export interface LogEntry {
   source: LogSource;
   message: string;
   details?: any;
+  techniques: string[]; // ATT&CK technique IDs
   timestamp: string;
 }
 